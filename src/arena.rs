@@ -0,0 +1,971 @@
+//! The AST with its nodes in arenas. Declarations, statements, expressions
+//! and types each live in one `Vec`, refer to each other by typed
+//! `NodeId`s, and keep their lists of children in shared `Vec`s too. Spans
+//! are stored next to the nodes, and anything else known about a node, such
+//! as its type or what a name in it refers to, goes in a `SideTable` keyed
+//! by its id.
+//!
+//! `spl-compile measure [FILE]` compares this layout to the tree one.
+
+use crate::ast::*;
+use crate::visit::{walk_decl, walk_exp, walk_stmt, walk_type, Visitor};
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::size_of;
+use std::ops::Index;
+use std::time::{Duration, Instant};
+
+/// The index of a `T` in its arena.
+pub struct NodeId<T> {
+    index: u32,
+    _node: PhantomData<fn() -> T>,
+}
+
+impl<T> NodeId<T> {
+    fn new(index: usize) -> Self {
+        NodeId {
+            index: index as u32,
+            _node: PhantomData,
+        }
+    }
+
+    pub fn index(self) -> usize {
+        self.index as usize
+    }
+}
+
+// Derives would require T: Copy and so on.
+impl<T> Clone for NodeId<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for NodeId<T> {}
+
+impl<T> PartialEq for NodeId<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+
+impl<T> Eq for NodeId<T> {}
+
+impl<T> std::hash::Hash for NodeId<T> {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.index.hash(state)
+    }
+}
+
+impl<T> fmt::Debug for NodeId<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.index)
+    }
+}
+
+pub type DeclId = NodeId<DeclNode>;
+pub type StmtId = NodeId<StmtNode>;
+pub type ExpId = NodeId<ExpNode>;
+pub type TypeId = NodeId<TypeNode>;
+
+/// A run of `T`s in one of the list stores of an `Ast`.
+pub struct List<T> {
+    start: u32,
+    len: u32,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<T> List<T> {
+    pub fn len(self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<T> Clone for List<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for List<T> {}
+
+impl<T> PartialEq for List<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.start, self.len) == (other.start, other.len)
+    }
+}
+
+impl<T> fmt::Debug for List<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}..{}]", self.start, self.start + self.len)
+    }
+}
+
+fn push_list<T>(store: &mut Vec<T>, items: impl IntoIterator<Item = T>) -> List<T> {
+    let start = store.len();
+    store.extend(items);
+    List {
+        start: start as u32,
+        len: (store.len() - start) as u32,
+        _item: PhantomData,
+    }
+}
+
+/// Nodes of one kind, and their spans.
+#[derive(Clone)]
+pub struct Arena<T> {
+    nodes: Vec<T>,
+    spans: Vec<Option<Span>>,
+}
+
+impl<T> Arena<T> {
+    fn new() -> Self {
+        Arena {
+            nodes: Vec::new(),
+            spans: Vec::new(),
+        }
+    }
+
+    fn alloc(&mut self, node: T, span: Option<Span>) -> NodeId<T> {
+        self.nodes.push(node);
+        self.spans.push(span);
+        NodeId::new(self.nodes.len() - 1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn span(&self, id: NodeId<T>) -> Option<Span> {
+        self.spans[id.index()]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId<T>, &T)> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (NodeId::new(i), node))
+    }
+
+    fn heap_bytes(&self) -> usize {
+        self.nodes.capacity() * size_of::<T>() + self.spans.capacity() * size_of::<Option<Span>>()
+    }
+}
+
+impl<T> Index<NodeId<T>> for Arena<T> {
+    type Output = T;
+
+    fn index(&self, id: NodeId<T>) -> &T {
+        &self.nodes[id.index()]
+    }
+}
+
+/// Information about some of the nodes of one kind, by id.
+#[derive(Clone)]
+pub struct SideTable<T, V> {
+    values: Vec<Option<V>>,
+    _node: PhantomData<fn() -> T>,
+}
+
+impl<T, V> Default for SideTable<T, V> {
+    fn default() -> Self {
+        SideTable {
+            values: Vec::new(),
+            _node: PhantomData,
+        }
+    }
+}
+
+impl<T, V> SideTable<T, V> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the value for `id`, returning the previous one.
+    pub fn insert(&mut self, id: NodeId<T>, value: V) -> Option<V> {
+        if self.values.len() <= id.index() {
+            self.values.resize_with(id.index() + 1, || None);
+        }
+        self.values[id.index()].replace(value)
+    }
+
+    pub fn get(&self, id: NodeId<T>) -> Option<&V> {
+        self.values.get(id.index()).and_then(Option::as_ref)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId<T>, &V)> {
+        self.values
+            .iter()
+            .enumerate()
+            .filter_map(|(i, value)| Some((NodeId::new(i), value.as_ref()?)))
+    }
+}
+
+pub type VarNode = (Option<TypeId>, Id, ExpId);
+pub type FunTypeNode = Spanned<(List<TypeId>, TypeId)>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeclNode {
+    Global(VarNode),
+    Fun(Id, List<Id>, Option<FunTypeNode>, List<StmtId>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StmtNode {
+    ITE(ExpId, List<StmtId>, List<StmtId>),
+    While(ExpId, List<StmtId>),
+    Assign(Id, ExpId),
+    Call(Id, List<ExpId>),
+    Ret(Option<ExpId>),
+    Local(VarNode),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExpNode {
+    Var(Id, List<Selector>),
+    Call(Id, List<ExpId>),
+    Lit(LitVal),
+    Tuple(List<ExpId>),
+    BinOp(Op, ExpId, ExpId),
+    UnOp(Op, ExpId),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypeNode {
+    Lit(BType),
+    Typename(Id),
+    Tuple(List<TypeId>),
+    List(TypeId),
+}
+
+/// A program in arenas. Index it with a node id for the node, or with a
+/// `List` for its items.
+#[derive(Clone)]
+pub struct Ast {
+    pub decls: Vec<DeclId>,
+    pub decl_nodes: Arena<DeclNode>,
+    pub stmts: Arena<StmtNode>,
+    pub exps: Arena<ExpNode>,
+    pub types: Arena<TypeNode>,
+    /// The type annotations of expressions.
+    pub exp_types: SideTable<ExpNode, TypeId>,
+    stmt_lists: Vec<StmtId>,
+    exp_lists: Vec<ExpId>,
+    type_lists: Vec<TypeId>,
+    id_lists: Vec<Id>,
+    selector_lists: Vec<Selector>,
+}
+
+macro_rules! node_index {
+    ( $node : ty, $arena : ident ) => {
+        impl Index<NodeId<$node>> for Ast {
+            type Output = $node;
+
+            fn index(&self, id: NodeId<$node>) -> &$node {
+                &self.$arena[id]
+            }
+        }
+    };
+}
+
+macro_rules! list_index {
+    ( $item : ty, $store : ident ) => {
+        impl Index<List<$item>> for Ast {
+            type Output = [$item];
+
+            fn index(&self, list: List<$item>) -> &[$item] {
+                &self.$store[list.start as usize..(list.start + list.len) as usize]
+            }
+        }
+    };
+}
+
+node_index!(DeclNode, decl_nodes);
+node_index!(StmtNode, stmts);
+node_index!(ExpNode, exps);
+node_index!(TypeNode, types);
+list_index!(StmtId, stmt_lists);
+list_index!(ExpId, exp_lists);
+list_index!(TypeId, type_lists);
+list_index!(Id, id_lists);
+list_index!(Selector, selector_lists);
+
+impl Ast {
+    fn new() -> Self {
+        Ast {
+            decls: Vec::new(),
+            decl_nodes: Arena::new(),
+            stmts: Arena::new(),
+            exps: Arena::new(),
+            types: Arena::new(),
+            exp_types: SideTable::new(),
+            stmt_lists: Vec::new(),
+            exp_lists: Vec::new(),
+            type_lists: Vec::new(),
+            id_lists: Vec::new(),
+            selector_lists: Vec::new(),
+        }
+    }
+
+    pub fn from_spl(spl: &SPL) -> Self {
+        let mut ast = Ast::new();
+        for decl in spl {
+            let id = ast.add_decl(decl);
+            ast.decls.push(id);
+        }
+        ast
+    }
+
+    fn add_decl(&mut self, (decl, span): &Decl) -> DeclId {
+        let node = match decl {
+            BareDecl::Global(var) => DeclNode::Global(self.add_var(var)),
+            BareDecl::Fun(id, args, typ, body) => {
+                let args = push_list(&mut self.id_lists, args.iter().copied());
+                let typ = typ.as_ref().map(|((args, ret), span)| {
+                    let args: Vec<TypeId> = args.iter().map(|arg| self.add_type(arg)).collect();
+                    let ret = self.add_type(ret);
+                    ((push_list(&mut self.type_lists, args), ret), *span)
+                });
+                DeclNode::Fun(*id, args, typ, self.add_stmts(body))
+            }
+        };
+        self.decl_nodes.alloc(node, *span)
+    }
+
+    fn add_var(&mut self, (typ, id, exp): &VarDecl) -> VarNode {
+        let typ = typ.as_ref().map(|typ| self.add_type(typ));
+        (typ, *id, self.add_exp(exp))
+    }
+
+    fn add_stmts(&mut self, stmts: &[Stmt]) -> List<StmtId> {
+        let ids: Vec<StmtId> = stmts.iter().map(|stmt| self.add_stmt(stmt)).collect();
+        push_list(&mut self.stmt_lists, ids)
+    }
+
+    fn add_exps(&mut self, exps: &[Exp]) -> List<ExpId> {
+        let ids: Vec<ExpId> = exps.iter().map(|exp| self.add_exp(exp)).collect();
+        push_list(&mut self.exp_lists, ids)
+    }
+
+    fn add_stmt(&mut self, (stmt, span): &Stmt) -> StmtId {
+        let node = match stmt {
+            BareStmt::ITE(cond, then, els) => {
+                let cond = self.add_exp(cond);
+                let then = self.add_stmts(then);
+                StmtNode::ITE(cond, then, self.add_stmts(els))
+            }
+            BareStmt::While(cond, body) => {
+                let cond = self.add_exp(cond);
+                StmtNode::While(cond, self.add_stmts(body))
+            }
+            BareStmt::Assign(id, exp) => StmtNode::Assign(*id, self.add_exp(exp)),
+            BareStmt::Call(id, args) => StmtNode::Call(*id, self.add_exps(args)),
+            BareStmt::Ret(exp) => StmtNode::Ret(exp.as_ref().map(|exp| self.add_exp(exp))),
+            BareStmt::Local(var) => StmtNode::Local(self.add_var(var)),
+        };
+        self.stmts.alloc(node, *span)
+    }
+
+    fn add_exp(&mut self, ((exp, typ), span): &Exp) -> ExpId {
+        let node = match exp {
+            BareExp::Var(id, fields) => ExpNode::Var(
+                *id,
+                push_list(&mut self.selector_lists, fields.iter().copied()),
+            ),
+            BareExp::Call(id, args) => ExpNode::Call(*id, self.add_exps(args)),
+            BareExp::Lit(val) => ExpNode::Lit(*val),
+            BareExp::Tuple(elems) => ExpNode::Tuple(self.add_exps(elems)),
+            BareExp::BinOp(op, lhs, rhs) => {
+                let lhs = self.add_exp(lhs);
+                ExpNode::BinOp(*op, lhs, self.add_exp(rhs))
+            }
+            BareExp::UnOp(op, arg) => ExpNode::UnOp(*op, self.add_exp(arg)),
+        };
+        let typ = typ.as_ref().map(|typ| self.add_type(typ));
+        let id = self.exps.alloc(node, *span);
+        if let Some(typ) = typ {
+            self.exp_types.insert(id, typ);
+        }
+        id
+    }
+
+    fn add_type(&mut self, (typ, span): &Type) -> TypeId {
+        let node = match typ {
+            BareType::Lit(btype) => TypeNode::Lit(*btype),
+            BareType::Typename(id) => TypeNode::Typename(*id),
+            BareType::Tuple(elems) => {
+                let ids: Vec<TypeId> = elems.iter().map(|elem| self.add_type(elem)).collect();
+                TypeNode::Tuple(push_list(&mut self.type_lists, ids))
+            }
+            BareType::List(elem) => TypeNode::List(self.add_type(elem)),
+        };
+        self.types.alloc(node, *span)
+    }
+
+    /// The program as a tree again.
+    pub fn to_spl(&self) -> SPL {
+        self.decls.iter().map(|&id| self.decl(id)).collect()
+    }
+
+    pub fn decl(&self, id: DeclId) -> Decl {
+        let decl = match &self[id] {
+            DeclNode::Global(var) => BareDecl::Global(self.var(var)),
+            DeclNode::Fun(name, args, typ, body) => BareDecl::Fun(
+                *name,
+                self[*args].to_vec(),
+                typ.map(|((args, ret), span)| ((self.types_of(args), self.typ(ret)), span)),
+                self.stmts_of(*body),
+            ),
+        };
+        (decl, self.decl_nodes.span(id))
+    }
+
+    fn var(&self, &(typ, id, exp): &VarNode) -> VarDecl {
+        (typ.map(|typ| self.typ(typ)), id, self.exp(exp))
+    }
+
+    fn stmts_of(&self, list: List<StmtId>) -> Vec<Stmt> {
+        self[list].iter().map(|&id| self.stmt(id)).collect()
+    }
+
+    fn exps_of(&self, list: List<ExpId>) -> Vec<Exp> {
+        self[list].iter().map(|&id| self.exp(id)).collect()
+    }
+
+    fn types_of(&self, list: List<TypeId>) -> Vec<Type> {
+        self[list].iter().map(|&id| self.typ(id)).collect()
+    }
+
+    pub fn stmt(&self, id: StmtId) -> Stmt {
+        let stmt = match &self[id] {
+            StmtNode::ITE(cond, then, els) => {
+                BareStmt::ITE(self.exp(*cond), self.stmts_of(*then), self.stmts_of(*els))
+            }
+            StmtNode::While(cond, body) => BareStmt::While(self.exp(*cond), self.stmts_of(*body)),
+            StmtNode::Assign(name, exp) => BareStmt::Assign(*name, self.exp(*exp)),
+            StmtNode::Call(name, args) => BareStmt::Call(*name, self.exps_of(*args)),
+            StmtNode::Ret(exp) => BareStmt::Ret(exp.map(|exp| self.exp(exp))),
+            StmtNode::Local(var) => BareStmt::Local(self.var(var)),
+        };
+        (stmt, self.stmts.span(id))
+    }
+
+    pub fn exp(&self, id: ExpId) -> Exp {
+        let exp = match &self[id] {
+            ExpNode::Var(name, fields) => BareExp::Var(*name, self[*fields].to_vec()),
+            ExpNode::Call(name, args) => BareExp::Call(*name, self.exps_of(*args)),
+            ExpNode::Lit(val) => BareExp::Lit(*val),
+            ExpNode::Tuple(elems) => BareExp::Tuple(self.exps_of(*elems)),
+            ExpNode::BinOp(op, lhs, rhs) => {
+                BareExp::BinOp(*op, Box::new(self.exp(*lhs)), Box::new(self.exp(*rhs)))
+            }
+            ExpNode::UnOp(op, arg) => BareExp::UnOp(*op, Box::new(self.exp(*arg))),
+        };
+        let typ = self.exp_types.get(id).map(|&typ| self.typ(typ));
+        ((exp, typ), self.exps.span(id))
+    }
+
+    pub fn typ(&self, id: TypeId) -> Type {
+        let typ = match &self[id] {
+            TypeNode::Lit(btype) => BareType::Lit(*btype),
+            TypeNode::Typename(name) => BareType::Typename(*name),
+            TypeNode::Tuple(elems) => BareType::Tuple(self.types_of(*elems)),
+            TypeNode::List(elem) => BareType::List(Box::new(self.typ(*elem))),
+        };
+        (typ, self.types.span(id))
+    }
+
+    /// The bytes allocated for the program, not counting the `Ast` itself.
+    pub fn heap_bytes(&self) -> usize {
+        self.decls.capacity() * size_of::<DeclId>()
+            + self.decl_nodes.heap_bytes()
+            + self.stmts.heap_bytes()
+            + self.exps.heap_bytes()
+            + self.types.heap_bytes()
+            + self.exp_types.values.capacity() * size_of::<Option<TypeId>>()
+            + self.stmt_lists.capacity() * size_of::<StmtId>()
+            + self.exp_lists.capacity() * size_of::<ExpId>()
+            + self.type_lists.capacity() * size_of::<TypeId>()
+            + self.id_lists.capacity() * size_of::<Id>()
+            + self.selector_lists.capacity() * size_of::<Selector>()
+    }
+}
+
+/// What a name refers to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Binding {
+    Global(DeclId),
+    Fun(DeclId),
+    /// The argument of a function, by position.
+    Arg(DeclId, u32),
+    Local(StmtId),
+}
+
+/// The bindings of the names in variables, calls and assignments. Names
+/// without a declaration, such as those of built-in functions, are absent.
+#[derive(Default)]
+pub struct Resolutions {
+    pub exps: SideTable<ExpNode, Binding>,
+    pub stmts: SideTable<StmtNode, Binding>,
+}
+
+/// Resolve the names in `ast`. Global variables and functions are in scope
+/// everywhere, arguments in their function, and locals from after their
+/// declaration to the end of their block; inner declarations shadow outer
+/// ones.
+pub fn resolve(ast: &Ast) -> Resolutions {
+    let mut globals = HashMap::new();
+    for &decl in &ast.decls {
+        match &ast[decl] {
+            DeclNode::Global((_, id, _)) => globals.insert(id.0, Binding::Global(decl)),
+            DeclNode::Fun(id, _, _, _) => globals.insert(id.0, Binding::Fun(decl)),
+        };
+    }
+    let mut resolver = Resolver {
+        ast,
+        globals,
+        scope: Vec::new(),
+        res: Resolutions::default(),
+    };
+    for &decl in &ast.decls {
+        match &ast[decl] {
+            DeclNode::Global((_, _, exp)) => resolver.exp(*exp),
+            DeclNode::Fun(_, args, _, body) => {
+                for (i, arg) in ast[*args].iter().enumerate() {
+                    resolver.scope.push((arg.0, Binding::Arg(decl, i as u32)));
+                }
+                resolver.block(*body);
+                resolver.scope.clear();
+            }
+        }
+    }
+    resolver.res
+}
+
+struct Resolver<'a> {
+    ast: &'a Ast,
+    globals: HashMap<BareId, Binding>,
+    scope: Vec<(BareId, Binding)>,
+    res: Resolutions,
+}
+
+impl Resolver<'_> {
+    fn lookup(&self, id: &Id) -> Option<Binding> {
+        self.scope
+            .iter()
+            .rev()
+            .find(|&&(name, _)| name == id.0)
+            .map(|&(_, binding)| binding)
+            .or_else(|| self.globals.get(&id.0).copied())
+    }
+
+    fn block(&mut self, stmts: List<StmtId>) {
+        let depth = self.scope.len();
+        for &stmt in &self.ast[stmts] {
+            self.stmt(stmt);
+        }
+        self.scope.truncate(depth);
+    }
+
+    fn stmt(&mut self, stmt: StmtId) {
+        let ast = self.ast;
+        match &ast[stmt] {
+            StmtNode::ITE(cond, then, els) => {
+                self.exp(*cond);
+                self.block(*then);
+                self.block(*els);
+            }
+            StmtNode::While(cond, body) => {
+                self.exp(*cond);
+                self.block(*body);
+            }
+            StmtNode::Assign(id, exp) => {
+                self.exp(*exp);
+                if let Some(binding) = self.lookup(id) {
+                    self.res.stmts.insert(stmt, binding);
+                }
+            }
+            StmtNode::Call(id, args) => {
+                for &arg in &ast[*args] {
+                    self.exp(arg);
+                }
+                if let Some(binding) = self.lookup(id) {
+                    self.res.stmts.insert(stmt, binding);
+                }
+            }
+            StmtNode::Ret(exp) => {
+                if let Some(exp) = exp {
+                    self.exp(*exp);
+                }
+            }
+            StmtNode::Local((_, id, exp)) => {
+                self.exp(*exp);
+                self.scope.push((id.0, Binding::Local(stmt)));
+            }
+        }
+    }
+
+    fn exp(&mut self, exp: ExpId) {
+        let ast = self.ast;
+        match &ast[exp] {
+            ExpNode::Var(id, _) | ExpNode::Call(id, _) => {
+                if let Some(binding) = self.lookup(id) {
+                    self.res.exps.insert(exp, binding);
+                }
+                if let ExpNode::Call(_, args) = &ast[exp] {
+                    for &arg in &ast[*args] {
+                        self.exp(arg);
+                    }
+                }
+            }
+            ExpNode::Lit(_) => (),
+            ExpNode::Tuple(elems) => {
+                for &elem in &ast[*elems] {
+                    self.exp(elem);
+                }
+            }
+            ExpNode::BinOp(_, lhs, rhs) => {
+                self.exp(*lhs);
+                self.exp(*rhs);
+            }
+            ExpNode::UnOp(_, arg) => self.exp(*arg),
+        }
+    }
+}
+
+/// Adds up the heap allocations of a tree AST.
+#[derive(Default)]
+struct TreeBytes(usize);
+
+impl Visitor for TreeBytes {
+    fn visit_decl(&mut self, decl: &Decl) {
+        if let BareDecl::Fun(_, args, typ, body) = &decl.0 {
+            self.0 += args.capacity() * size_of::<Id>() + body.capacity() * size_of::<Stmt>();
+            if let Some(((args, _), _)) = typ {
+                self.0 += args.capacity() * size_of::<Type>();
+            }
+        }
+        walk_decl(self, decl)
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        self.0 += match &stmt.0 {
+            BareStmt::ITE(_, then, els) => (then.capacity() + els.capacity()) * size_of::<Stmt>(),
+            BareStmt::While(_, body) => body.capacity() * size_of::<Stmt>(),
+            BareStmt::Call(_, args) => args.capacity() * size_of::<Exp>(),
+            _ => 0,
+        };
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_exp(&mut self, exp: &Exp) {
+        self.0 += match &(exp.0).0 {
+            BareExp::Var(_, fields) => fields.capacity() * size_of::<Selector>(),
+            BareExp::Call(_, exps) | BareExp::Tuple(exps) => exps.capacity() * size_of::<Exp>(),
+            BareExp::BinOp(..) => 2 * size_of::<Exp>(),
+            BareExp::UnOp(..) => size_of::<Exp>(),
+            BareExp::Lit(_) => 0,
+        };
+        walk_exp(self, exp)
+    }
+
+    fn visit_type(&mut self, typ: &Type) {
+        self.0 += match &typ.0 {
+            BareType::Tuple(elems) => elems.capacity() * size_of::<Type>(),
+            BareType::List(_) => size_of::<Type>(),
+            _ => 0,
+        };
+        walk_type(self, typ)
+    }
+}
+
+/// Counts the variables in a tree AST, to time a traversal.
+struct CountVars(usize);
+
+impl Visitor for CountVars {
+    fn visit_exp(&mut self, exp: &Exp) {
+        if let BareExp::Var(..) = (exp.0).0 {
+            self.0 += 1;
+        }
+        walk_exp(self, exp)
+    }
+}
+
+/// Counts the variables in an arena AST, walking it recursively from its
+/// declarations through every node as `CountVars` does the tree.
+struct ArenaVars<'a> {
+    ast: &'a Ast,
+    vars: usize,
+}
+
+impl ArenaVars<'_> {
+    fn decl(&mut self, id: DeclId) {
+        let ast = self.ast;
+        match &ast[id] {
+            DeclNode::Global(var) => self.var(var),
+            DeclNode::Fun(_, _, typ, body) => {
+                if let Some(((args, ret), _)) = typ {
+                    ast[*args].iter().for_each(|&arg| self.typ(arg));
+                    self.typ(*ret);
+                }
+                self.stmts(*body);
+            }
+        }
+    }
+
+    fn var(&mut self, &(typ, _, exp): &VarNode) {
+        if let Some(typ) = typ {
+            self.typ(typ);
+        }
+        self.exp(exp);
+    }
+
+    fn stmts(&mut self, list: List<StmtId>) {
+        let ast = self.ast;
+        ast[list].iter().for_each(|&stmt| self.stmt(stmt));
+    }
+
+    fn stmt(&mut self, id: StmtId) {
+        let ast = self.ast;
+        match &ast[id] {
+            StmtNode::ITE(cond, then, els) => {
+                self.exp(*cond);
+                self.stmts(*then);
+                self.stmts(*els);
+            }
+            StmtNode::While(cond, body) => {
+                self.exp(*cond);
+                self.stmts(*body);
+            }
+            StmtNode::Assign(_, exp) => self.exp(*exp),
+            StmtNode::Call(_, args) => ast[*args].iter().for_each(|&arg| self.exp(arg)),
+            StmtNode::Ret(exp) => exp.iter().for_each(|&exp| self.exp(exp)),
+            StmtNode::Local(var) => self.var(var),
+        }
+    }
+
+    fn exp(&mut self, id: ExpId) {
+        let ast = self.ast;
+        match &ast[id] {
+            ExpNode::Var(..) => self.vars += 1,
+            ExpNode::Call(_, exps) | ExpNode::Tuple(exps) => {
+                ast[*exps].iter().for_each(|&exp| self.exp(exp))
+            }
+            ExpNode::Lit(_) => (),
+            ExpNode::BinOp(_, lhs, rhs) => {
+                self.exp(*lhs);
+                self.exp(*rhs);
+            }
+            ExpNode::UnOp(_, arg) => self.exp(*arg),
+        }
+        if let Some(&typ) = ast.exp_types.get(id) {
+            self.typ(typ);
+        }
+    }
+
+    fn typ(&mut self, id: TypeId) {
+        let ast = self.ast;
+        match &ast[id] {
+            TypeNode::Tuple(elems) => ast[*elems].iter().for_each(|&elem| self.typ(elem)),
+            TypeNode::List(elem) => self.typ(*elem),
+            TypeNode::Lit(_) | TypeNode::Typename(_) => (),
+        }
+    }
+}
+
+/// How the two layouts of a program compare.
+pub struct Measurement {
+    /// The bytes allocated for the program, not counting its root.
+    pub tree_bytes: usize,
+    pub arena_bytes: usize,
+    /// The time to make a deep copy of the program.
+    pub tree_copy: Duration,
+    pub arena_copy: Duration,
+    /// The time to count the variables of the program by a recursive walk
+    /// from its declarations.
+    pub tree_walk: Duration,
+    pub arena_walk: Duration,
+}
+
+/// Measure `spl` in both layouts, timing the fastest of `rounds` rounds of
+/// copying it and of counting its variables.
+pub fn measure(spl: &SPL, rounds: u32) -> Measurement {
+    fn best<T>(rounds: u32, mut run: impl FnMut() -> T) -> (Duration, T) {
+        let mut best = None;
+        let mut result = None;
+        for _ in 0..rounds.max(1) {
+            let start = Instant::now();
+            let value = run();
+            let time = start.elapsed();
+            best = Some(best.map_or(time, |best: Duration| best.min(time)));
+            result = Some(value);
+        }
+        (best.unwrap(), result.unwrap())
+    }
+    let ast = Ast::from_spl(spl);
+    let (tree_copy, tree) = best(rounds, || spl.clone());
+    let (arena_copy, ast) = best(rounds, || ast.clone());
+    let (tree_walk, tree_vars) = best(rounds, || {
+        let mut count = CountVars(0);
+        count.visit_spl(&tree);
+        count.0
+    });
+    let (arena_walk, arena_vars) = best(rounds, || {
+        let mut count = ArenaVars { ast: &ast, vars: 0 };
+        ast.decls.iter().for_each(|&decl| count.decl(decl));
+        count.vars
+    });
+    assert_eq!(tree_vars, arena_vars);
+    let mut tree_bytes = TreeBytes(tree.capacity() * size_of::<Decl>());
+    tree_bytes.visit_spl(&tree);
+    Measurement {
+        tree_bytes: tree_bytes.0,
+        arena_bytes: ast.heap_bytes(),
+        tree_copy,
+        arena_copy,
+        tree_walk,
+        arena_walk,
+    }
+}
+
+impl fmt::Display for Measurement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "layout  heap bytes  copy        walk")?;
+        writeln!(
+            f,
+            "tree    {:>10}  {:>10?}  {:>10?}",
+            self.tree_bytes, self.tree_copy, self.tree_walk
+        )?;
+        writeln!(
+            f,
+            "arena   {:>10}  {:>10?}  {:>10?}",
+            self.arena_bytes, self.arena_copy, self.arena_walk
+        )?;
+        writeln!(f, "heap bytes: allocated for the program, besides its root")?;
+        writeln!(f, "copy: fastest deep copy (SPL::clone, Ast::clone)")?;
+        write!(
+            f,
+            "walk: fastest recursive walk from the declarations, counting variables"
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::tests::{parse, SOURCE};
+
+    #[test]
+    fn round_trip() {
+        let mut spl = parse(SOURCE);
+        // Give an expression a type annotation, as type inference would.
+        if let BareDecl::Global((_, _, exp)) = &mut spl[0].0 {
+            (exp.0).1 = Some((BareType::Lit(BType::IntT), None));
+        }
+        let ast = Ast::from_spl(&spl);
+        assert_eq!(ast.decls.len(), 3);
+        assert_eq!(ast.exp_types.iter().count(), 1);
+        assert_eq!(ast.to_spl(), spl);
+    }
+
+    #[test]
+    fn lists_and_spans() {
+        let spl = parse(SOURCE);
+        let ast = Ast::from_spl(&spl);
+        let f = ast.decls[1];
+        match &ast[f] {
+            DeclNode::Fun(_, args, Some(((arg_types, ret), _)), body) => {
+                assert_eq!(args.len(), 2);
+                assert_eq!(arg_types.len(), 2);
+                assert!(matches!(ast[*ret], TypeNode::Tuple(elems) if elems.len() == 2));
+                assert_eq!(ast[*body].len(), 4);
+                assert!(matches!(ast[ast[*body][1]], StmtNode::ITE(..)));
+            }
+            node => panic!("{:?}", node),
+        }
+        assert_eq!(ast.decl_nodes.span(f), spl[1].1);
+        let fields = ast
+            .exps
+            .iter()
+            .find_map(|(_, exp)| match exp {
+                ExpNode::Var(_, fields) if fields.len() == 2 => Some(*fields),
+                _ => None,
+            })
+            .unwrap();
+        let selectors: Vec<_> = ast[fields].iter().map(|&(sel, _)| sel).collect();
+        assert_eq!(selectors, vec![BareSelector::Hd, BareSelector::Fst]);
+    }
+
+    #[test]
+    fn resolution() {
+        let spl = parse(SOURCE);
+        let ast = Ast::from_spl(&spl);
+        let res = resolve(&ast);
+        let (global_g, f, h) = (ast.decls[0], ast.decls[1], ast.decls[2]);
+        let name = |id: &Id| id.0.name();
+        let mut seen = Vec::new();
+        for (exp, binding) in res.exps.iter() {
+            if let ExpNode::Var(id, _) | ExpNode::Call(id, _) = &ast[exp] {
+                seen.push((name(id), *binding));
+            }
+        }
+        let local = |n| {
+            ast.stmts
+                .iter()
+                .filter(|(_, stmt)| matches!(stmt, StmtNode::Local(_)))
+                .nth(n)
+                .unwrap()
+                .0
+        };
+        let (z, inner_g) = (Binding::Local(local(0)), Binding::Local(local(1)));
+        let (x, y) = (Binding::Arg(f, 0), Binding::Arg(f, 1));
+        let g = Binding::Global(global_g);
+        assert_eq!(
+            seen,
+            vec![
+                ("x", x),
+                ("y", y),
+                ("z", z),
+                ("g", g),
+                ("z", z),
+                ("g", inner_g),
+                ("y", y),
+                ("f", Binding::Fun(f)),
+                ("z", z),
+                ("z", z),
+                ("z", z),
+                ("z", z),
+                ("g", g),
+                ("y", y),
+                ("g", g),
+                ("f", Binding::Fun(f)),
+            ]
+        );
+        // print is built in; the assignments are to z.
+        let stmts: Vec<_> = res.stmts.iter().map(|(_, &binding)| binding).collect();
+        assert_eq!(stmts, vec![z, z]);
+        assert!(ast.decls.contains(&h));
+    }
+
+    #[test]
+    fn measure_agrees() {
+        let before = measure(&parse(SOURCE), 1);
+        // Negating the global's value adds one expression: a node with its
+        // span in the arena, a boxed operand in the tree.
+        let after = measure(&parse(&SOURCE.replacen("1", "-(1)", 1)), 1);
+        assert_eq!(
+            after.arena_bytes - before.arena_bytes,
+            size_of::<ExpNode>() + size_of::<Option<Span>>()
+        );
+        assert_eq!(after.tree_bytes - before.tree_bytes, size_of::<Exp>());
+    }
+}
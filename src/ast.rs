@@ -1,48 +1,38 @@
+use crate::intern::Symbol;
+use crate::source::FileId;
+use std::fmt;
+use std::str::FromStr;
+
+/// A range of bytes in a file of the `SourceMap`.
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Span {
-    pub startline: u32,
-    pub endline: u32,
-    pub startcol: u16,
-    pub endcol: u16,
+    pub file: FileId,
+    pub start: u32,
+    pub end: u32,
 }
 
 impl From<crate::parser::Loc> for Span {
     fn from(loc: crate::parser::Loc) -> Self {
         Span {
-            startline: loc.line,
-            endline: loc.line,
-            startcol: loc.col,
-            endcol: loc.col + loc.len,
+            file: loc.file,
+            start: loc.start,
+            end: loc.start + loc.len,
         }
     }
 }
 
 impl Span {
-    pub(crate) fn new(startline: u32, endline: u32, startcol: u16, endcol: u16) -> Self {
-        Self {
-            startline,
-            endline,
-            startcol,
-            endcol,
-        }
+    pub(crate) fn new(file: FileId, start: u32, end: u32) -> Self {
+        Self { file, start, end }
     }
     pub(crate) fn hull(lhs: Self, rhs: Self) -> Self {
         use core::cmp::max;
         use core::cmp::min;
-        use std::cmp::Ordering::*;
+        debug_assert_eq!(lhs.file, rhs.file);
         Span {
-            startline: min(lhs.startline, rhs.startline),
-            endline: max(lhs.endline, rhs.endline),
-            startcol: match lhs.startline.cmp(&rhs.startline) {
-                Less => lhs.startcol,
-                Equal => min(lhs.startcol, rhs.startcol),
-                Greater => rhs.startcol,
-            },
-            endcol: match lhs.endline.cmp(&rhs.endline) {
-                Greater => lhs.endcol,
-                Equal => max(lhs.endcol, rhs.endcol),
-                Less => rhs.endcol,
-            },
+            file: lhs.file,
+            start: min(lhs.start, rhs.start),
+            end: max(lhs.end, rhs.end),
         }
     }
 }
@@ -52,7 +42,7 @@ pub type Spanned<T> = (T, Option<Span>);
 pub type SPL = Vec<Decl>;
 
 pub type Decl = Spanned<BareDecl>;
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum BareDecl {
     Global(VarDecl),
     Fun(Id, Vec<Id>, Option<FunType>, Vec<Stmt>),
@@ -61,7 +51,7 @@ pub enum BareDecl {
 pub type VarDecl = (Option<Type>, Id, Exp);
 
 pub type Exp = Spanned<Typed<BareExp>>;
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum BareExp {
     Var(Id, Vec<Selector>),
     Call(Id, Vec<Exp>),
@@ -72,7 +62,7 @@ pub enum BareExp {
 }
 
 pub type Stmt = Spanned<BareStmt>;
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum BareStmt {
     ITE(Exp, Vec<Stmt>, Vec<Stmt>),
     While(Exp, Vec<Stmt>),
@@ -86,7 +76,7 @@ pub type FunType = Spanned<BareFunType>;
 pub type BareFunType = (Vec<Type>, Type);
 
 pub type Type = Spanned<BareType>;
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum BareType {
     Lit(BType),
     Typename(Id),
@@ -99,7 +89,31 @@ pub type Typed<T> = (T, Option<Type>);
 /** Terminal symbols/tokens **/
 pub type Id = Spanned<BareId>;
 
-pub type BareId = u32;
+/// An identifier, as its name in the global interner.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BareId(pub Symbol);
+
+impl BareId {
+    pub fn intern(name: &str) -> Self {
+        BareId(Symbol::intern(name))
+    }
+
+    /// An identifier that no program has used or can use, named after
+    /// `base`.
+    pub fn fresh(base: &str) -> Self {
+        BareId(Symbol::fresh(base))
+    }
+
+    pub fn name(self) -> &'static str {
+        self.0.as_str()
+    }
+}
+
+impl fmt::Display for BareId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
 
 pub type Selector = Spanned<BareSelector>;
 
@@ -111,6 +125,24 @@ pub enum BareSelector {
     Snd,
 }
 
+impl BareSelector {
+    pub fn name(self) -> &'static str {
+        use BareSelector::*;
+        match self {
+            Hd => "hd",
+            Tl => "tl",
+            Fst => "fst",
+            Snd => "snd",
+        }
+    }
+}
+
+impl fmt::Display for BareSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum BType {
     IntT,
@@ -119,6 +151,24 @@ pub enum BType {
     UnitT,
 }
 
+impl BType {
+    pub fn name(self) -> &'static str {
+        use BType::*;
+        match self {
+            IntT => "Int",
+            BoolT => "Bool",
+            CharT => "Char",
+            UnitT => "Void",
+        }
+    }
+}
+
+impl fmt::Display for BType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum LitVal {
     Int(i64),
@@ -127,6 +177,87 @@ pub enum LitVal {
     Nil,
 }
 
+/// As an SPL literal. A negative `Int`, which only constant folding makes,
+/// prints as a negation.
+impl fmt::Display for LitVal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            LitVal::Int(n) => write!(f, "{}", n),
+            LitVal::Char(c) => match c {
+                '\n' => f.write_str("'\\n'"),
+                '\t' => f.write_str("'\\t'"),
+                '\r' => f.write_str("'\\r'"),
+                '\0' => f.write_str("'\\0'"),
+                '\\' => f.write_str("'\\\\'"),
+                '\'' => f.write_str("'\\''"),
+                c if c.is_control() => write!(f, "'\\u{{{:x}}}'", c as u32),
+                c => write!(f, "'{}'", c),
+            },
+            LitVal::Bool(true) => f.write_str("True"),
+            LitVal::Bool(false) => f.write_str("False"),
+            LitVal::Nil => f.write_str("[]"),
+        }
+    }
+}
+
+/// The width of `Int` values. Arithmetic wraps around in two's complement
+/// at this width.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IntWidth {
+    W32,
+    W64,
+}
+
+impl IntWidth {
+    pub fn bits(self) -> u32 {
+        match self {
+            IntWidth::W32 => 32,
+            IntWidth::W64 => 64,
+        }
+    }
+
+    /// Truncate `val` to this width, and tell whether that changed it.
+    pub fn wrap(self, val: i64) -> (i64, bool) {
+        let wrapped = match self {
+            IntWidth::W32 => val as i32 as i64,
+            IntWidth::W64 => val,
+        };
+        (wrapped, wrapped != val)
+    }
+
+    /// `-val`, wrapped to this width.
+    pub fn negate(self, val: i64) -> i64 {
+        self.wrap(val.wrapping_neg()).0
+    }
+
+    /// The value of an integer literal with digits `val`. Decimal literals
+    /// must fit the signed range, except that right after a minus sign they
+    /// may be the magnitude of its minimum; hexadecimal and binary ones may
+    /// use the whole unsigned range, and give the two's complement bit
+    /// pattern.
+    pub fn literal(self, val: u64, decimal: bool, negated: bool) -> Option<i64> {
+        let max = (u64::MAX >> (64 - self.bits() + decimal as u32)) + (decimal && negated) as u64;
+        if val > max {
+            None
+        } else {
+            Some(self.wrap(val as i64).0)
+        }
+    }
+}
+
+/// The number of bits, `32` or `64`, as given to `--int-width`.
+impl FromStr for IntWidth {
+    type Err = String;
+
+    fn from_str(bits: &str) -> Result<Self, String> {
+        match bits {
+            "32" => Ok(IntWidth::W32),
+            "64" => Ok(IntWidth::W64),
+            _ => Err(format!("Int width must be 32 or 64, not '{}'", bits)),
+        }
+    }
+}
+
 pub type Op = Spanned<BareOp>;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -143,12 +274,22 @@ pub enum BareOp {
     Plus,
     Minus,
     Mul,
+    /// Integer division, rounding towards zero.
     Div,
+    /// Remainder of `Div`, with the sign of the dividend, so that
+    /// `(a / b) * b + a % b == a`.
+    Mod,
     Neg,
     Cons,
 }
 
 type Priority = u8;
+impl fmt::Display for BareOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
 impl BareOp {
     pub fn prio(self) -> Priority {
         use BareOp::*;
@@ -171,6 +312,30 @@ impl BareOp {
             // which intuition suggests.
             Mul => 80,
             Div => 80,
+            Mod => 80,
+        }
+    }
+
+    /// How the operator is written in SPL source.
+    pub fn symbol(self) -> &'static str {
+        use BareOp::*;
+        match self {
+            And => "&&",
+            Or => "||",
+            Not => "!",
+            Lt => "<",
+            Leq => "<=",
+            Gt => ">",
+            Geq => ">=",
+            Eq => "==",
+            Neq => "!=",
+            Plus => "+",
+            Minus => "-",
+            Mul => "*",
+            Div => "/",
+            Mod => "%",
+            Neg => "-",
+            Cons => ":",
         }
     }
 
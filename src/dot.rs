@@ -0,0 +1,397 @@
+//! Graphviz DOT exports: the tree of the AST, and the control-flow graph
+//! of every function.
+
+use crate::ast::*;
+use crate::pretty::pretty;
+use std::fmt::Write;
+
+/// The AST of `spl` as a DOT graph, with a node for every declaration,
+/// statement, expression and type. Edges are labelled with the role of the
+/// child in its parent.
+pub fn ast_dot(spl: &SPL) -> String {
+    let mut dot = AstDot {
+        out: String::from("digraph ast {\n  node [shape=box, fontname=monospace];\n"),
+        next: 0,
+    };
+    let root = dot.node("SPL");
+    for decl in spl {
+        let child = dot.decl(decl);
+        dot.edge(root, child, None);
+    }
+    dot.out.push_str("}\n");
+    dot.out
+}
+
+/// The control-flow graph of every function in `spl`, one cluster per
+/// function. Straight-line statements are grouped into basic blocks, and
+/// the conditions of `if` and `while` are branch nodes with `true` and
+/// `false` edges.
+pub fn cfg_dot(spl: &SPL) -> String {
+    let mut dot = CfgDot {
+        out: String::from("digraph cfg {\n  node [shape=box, fontname=monospace];\n"),
+        next: 0,
+    };
+    for (decl, _) in spl {
+        if let BareDecl::Fun(name, _, _, body) = decl {
+            dot.fun(name, body);
+        }
+    }
+    dot.out.push_str("}\n");
+    dot.out
+}
+
+/// `text` as a DOT string, whose lines are left-aligned.
+fn quote(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\l"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+struct AstDot {
+    out: String,
+    next: usize,
+}
+
+impl AstDot {
+    fn node(&mut self, label: &str) -> usize {
+        let node = self.next;
+        self.next += 1;
+        writeln!(self.out, "  n{} [label={}];", node, quote(label)).unwrap();
+        node
+    }
+
+    fn edge(&mut self, from: usize, to: usize, role: Option<&str>) {
+        match role {
+            Some(role) => writeln!(self.out, "  n{} -> n{} [label={}];", from, to, quote(role)),
+            None => writeln!(self.out, "  n{} -> n{};", from, to),
+        }
+        .unwrap()
+    }
+
+    fn name(&self, id: &Id) -> String {
+        id.0.to_string()
+    }
+
+    fn decl(&mut self, (decl, _): &Decl) -> usize {
+        match decl {
+            BareDecl::Global(var) => self.var_decl("Global", var),
+            BareDecl::Fun(name, args, typ, body) => {
+                let args: Vec<String> = args.iter().map(|arg| self.name(arg)).collect();
+                let mut label = format!("Fun {}({})", self.name(name), args.join(", "));
+                if let Some(typ) = typ {
+                    write!(label, " :: {}", pretty(typ)).unwrap();
+                }
+                let node = self.node(&label);
+                self.stmts(node, body, "body");
+                node
+            }
+        }
+    }
+
+    fn var_decl(&mut self, kind: &str, (typ, name, value): &VarDecl) -> usize {
+        let node = self.node(&format!("{} {}", kind, self.name(name)));
+        if let Some(typ) = typ {
+            let child = self.typ(typ);
+            self.edge(node, child, Some("type"));
+        }
+        let child = self.exp(value);
+        self.edge(node, child, Some("value"));
+        node
+    }
+
+    fn stmts(&mut self, parent: usize, stmts: &[Stmt], role: &str) {
+        for stmt in stmts {
+            let child = self.stmt(stmt);
+            self.edge(parent, child, Some(role));
+        }
+    }
+
+    fn exps(&mut self, parent: usize, exps: &[Exp], role: &str) {
+        for exp in exps {
+            let child = self.exp(exp);
+            self.edge(parent, child, Some(role));
+        }
+    }
+
+    fn stmt(&mut self, (stmt, _): &Stmt) -> usize {
+        match stmt {
+            BareStmt::ITE(cond, then, els) => {
+                let node = self.node("ITE");
+                let child = self.exp(cond);
+                self.edge(node, child, Some("cond"));
+                self.stmts(node, then, "then");
+                self.stmts(node, els, "else");
+                node
+            }
+            BareStmt::While(cond, body) => {
+                let node = self.node("While");
+                let child = self.exp(cond);
+                self.edge(node, child, Some("cond"));
+                self.stmts(node, body, "body");
+                node
+            }
+            BareStmt::Assign(name, value) => {
+                let node = self.node(&format!("Assign {}", self.name(name)));
+                let child = self.exp(value);
+                self.edge(node, child, Some("value"));
+                node
+            }
+            BareStmt::Call(name, args) => {
+                let node = self.node(&format!("Call {}", self.name(name)));
+                self.exps(node, args, "arg");
+                node
+            }
+            BareStmt::Ret(value) => {
+                let node = self.node("Ret");
+                if let Some(value) = value {
+                    let child = self.exp(value);
+                    self.edge(node, child, Some("value"));
+                }
+                node
+            }
+            BareStmt::Local(var) => self.var_decl("Local", var),
+        }
+    }
+
+    fn exp(&mut self, ((exp, typ), _): &Exp) -> usize {
+        let mut label = match exp {
+            BareExp::Var(name, fields) => {
+                let mut label = format!("Var {}", self.name(name));
+                for (field, _) in fields {
+                    write!(label, ".{}", field).unwrap();
+                }
+                label
+            }
+            BareExp::Call(name, _) => format!("Call {}", self.name(name)),
+            BareExp::Lit(val) => format!("Lit {}", val),
+            BareExp::Tuple(_) => "Tuple".to_string(),
+            BareExp::BinOp((op, _), ..) => format!("BinOp {}", op),
+            BareExp::UnOp((op, _), _) => format!("UnOp {}", op),
+        };
+        if let Some(typ) = typ {
+            write!(label, " : {}", pretty(typ)).unwrap();
+        }
+        let node = self.node(&label);
+        match exp {
+            BareExp::Var(..) | BareExp::Lit(_) => {}
+            BareExp::Call(_, args) => self.exps(node, args, "arg"),
+            BareExp::Tuple(elems) => self.exps(node, elems, "elem"),
+            BareExp::BinOp(_, lhs, rhs) => {
+                let child = self.exp(lhs);
+                self.edge(node, child, Some("lhs"));
+                let child = self.exp(rhs);
+                self.edge(node, child, Some("rhs"));
+            }
+            BareExp::UnOp(_, arg) => {
+                let child = self.exp(arg);
+                self.edge(node, child, Some("arg"));
+            }
+        }
+        node
+    }
+
+    fn typ(&mut self, typ: &Type) -> usize {
+        self.node(&format!("Type {}", pretty(typ)))
+    }
+}
+
+/// An edge waiting for its target: the node it leaves and its label.
+type Exit = (usize, Option<&'static str>);
+
+struct CfgDot {
+    out: String,
+    next: usize,
+}
+
+impl CfgDot {
+    fn node(&mut self, label: &str, shape: &str) -> usize {
+        let node = self.next;
+        self.next += 1;
+        writeln!(
+            self.out,
+            "    n{} [label={}, shape={}];",
+            node,
+            quote(label),
+            shape
+        )
+        .unwrap();
+        node
+    }
+
+    fn edges(&mut self, from: &[Exit], to: usize) {
+        for &(from, label) in from {
+            match label {
+                Some(label) => writeln!(self.out, "    n{} -> n{} [label={}];", from, to, label),
+                None => writeln!(self.out, "    n{} -> n{};", from, to),
+            }
+            .unwrap()
+        }
+    }
+
+    fn fun(&mut self, name: &Id, body: &[Stmt]) {
+        let name = pretty(name).to_string();
+        writeln!(self.out, "  subgraph cluster_{} {{", self.next).unwrap();
+        writeln!(self.out, "    label={};", quote(&name)).unwrap();
+        let entry = self.node("entry", "oval");
+        let mut returns = Vec::new();
+        let exits = self.block(body, vec![(entry, None)], &mut returns);
+        let exit = self.node("exit", "oval");
+        self.edges(&exits, exit);
+        self.edges(&returns, exit);
+        self.out.push_str("  }\n");
+    }
+
+    /// Emit the nodes of `stmts`, entered from `preds`, and return the
+    /// edges that leave it at the end. Edges from `return` statements go to
+    /// `returns` instead. Code after a `return` gets no incoming edges.
+    fn block(
+        &mut self,
+        stmts: &[Stmt],
+        mut preds: Vec<Exit>,
+        returns: &mut Vec<Exit>,
+    ) -> Vec<Exit> {
+        let mut lines = String::new();
+        for stmt in stmts {
+            match &stmt.0 {
+                BareStmt::ITE(cond, then, els) => {
+                    preds = self.flush(&mut lines, preds);
+                    let branch = self.branch(cond, &preds);
+                    let mut exits = self.block(then, vec![(branch, Some("true"))], returns);
+                    exits.extend(self.block(els, vec![(branch, Some("false"))], returns));
+                    preds = exits;
+                }
+                BareStmt::While(cond, body) => {
+                    preds = self.flush(&mut lines, preds);
+                    let branch = self.branch(cond, &preds);
+                    let exits = self.block(body, vec![(branch, Some("true"))], returns);
+                    self.edges(&exits, branch);
+                    preds = vec![(branch, Some("false"))];
+                }
+                BareStmt::Ret(_) => {
+                    lines.push_str(&pretty(stmt).to_string());
+                    returns.extend(self.flush(&mut lines, preds));
+                    preds = Vec::new();
+                }
+                _ => lines.push_str(&pretty(stmt).to_string()),
+            }
+        }
+        self.flush(&mut lines, preds)
+    }
+
+    /// Emit the basic block of the statements in `lines`, if there are any.
+    fn flush(&mut self, lines: &mut String, preds: Vec<Exit>) -> Vec<Exit> {
+        if lines.is_empty() {
+            return preds;
+        }
+        let node = self.node(lines, "box");
+        self.edges(&preds, node);
+        lines.clear();
+        vec![(node, None)]
+    }
+
+    fn branch(&mut self, cond: &Exp, preds: &[Exit]) -> usize {
+        let node = self.node(&pretty(cond).to_string(), "diamond");
+        self.edges(preds, node);
+        node
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::tests::parse;
+
+    #[test]
+    fn ast_graph() {
+        let spl = parse("var s = \"a\\\"\"; f(x) :: Int -> Int { if (x < 1) { return -x.hd; } }");
+        assert_eq!(
+            ast_dot(&spl),
+            r#"digraph ast {
+  node [shape=box, fontname=monospace];
+  n0 [label="SPL"];
+  n1 [label="Global s"];
+  n2 [label="BinOp :"];
+  n3 [label="Lit 'a'"];
+  n2 -> n3 [label="lhs"];
+  n4 [label="BinOp :"];
+  n5 [label="Lit '\"'"];
+  n4 -> n5 [label="lhs"];
+  n6 [label="Lit []"];
+  n4 -> n6 [label="rhs"];
+  n2 -> n4 [label="rhs"];
+  n1 -> n2 [label="value"];
+  n0 -> n1;
+  n7 [label="Fun f(x) :: Int -> Int"];
+  n8 [label="ITE"];
+  n9 [label="BinOp <"];
+  n10 [label="Var x"];
+  n9 -> n10 [label="lhs"];
+  n11 [label="Lit 1"];
+  n9 -> n11 [label="rhs"];
+  n8 -> n9 [label="cond"];
+  n12 [label="Ret"];
+  n13 [label="UnOp -"];
+  n14 [label="Var x.hd"];
+  n13 -> n14 [label="arg"];
+  n12 -> n13 [label="value"];
+  n8 -> n12 [label="then"];
+  n7 -> n8 [label="body"];
+  n0 -> n7;
+}
+"#
+        );
+    }
+
+    #[test]
+    fn control_flow_graph() {
+        let spl = parse(
+            "var g = 0;
+            f(x) {
+                var y = x;
+                while (y > 0) {
+                    if (y % 2 == 0) { y = y / 2; } else { return y; }
+                }
+                print(y);
+                return 0;
+                g = 1;
+            }",
+        );
+        assert_eq!(
+            cfg_dot(&spl),
+            r#"digraph cfg {
+  node [shape=box, fontname=monospace];
+  subgraph cluster_0 {
+    label="f";
+    n0 [label="entry", shape=oval];
+    n1 [label="var y = x;\l", shape=box];
+    n0 -> n1;
+    n2 [label="y > 0", shape=diamond];
+    n1 -> n2;
+    n3 [label="y % 2 == 0", shape=diamond];
+    n2 -> n3 [label=true];
+    n4 [label="y = y / 2;\l", shape=box];
+    n3 -> n4 [label=true];
+    n5 [label="return y;\l", shape=box];
+    n3 -> n5 [label=false];
+    n4 -> n2;
+    n6 [label="print(y);\lreturn 0;\l", shape=box];
+    n2 -> n6 [label=false];
+    n7 [label="g = 1;\l", shape=box];
+    n8 [label="exit", shape=oval];
+    n7 -> n8;
+    n5 -> n8;
+    n6 -> n8;
+  }
+}
+"#
+        );
+    }
+}
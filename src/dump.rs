@@ -0,0 +1,593 @@
+//! Dumps of the AST as JSON and S-expressions, for external tools and
+//! teaching material. The JSON format is described by
+//! `doc/ast.schema.json`, and `from_json` reads it back.
+//!
+//! Identifiers appear by name, and every node carries its span as
+//! `[file, start, end]`, or `null` if it has none. Expressions carry their
+//! inferred type, which is `null` until type inference fills it in.
+
+use crate::ast::*;
+use crate::json::Json;
+use crate::source::FileId;
+use std::fmt::Write;
+
+/// An error in the structure of the JSON given to `from_json`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LoadError(pub String);
+
+type LoadResult<T> = Result<T, LoadError>;
+
+const OPS: [BareOp; 16] = {
+    use BareOp::*;
+    [
+        And, Or, Not, Lt, Leq, Gt, Geq, Eq, Neq, Plus, Minus, Mul, Div, Mod, Neg, Cons,
+    ]
+};
+const SELECTORS: [BareSelector; 4] = {
+    use BareSelector::*;
+    [Hd, Tl, Fst, Snd]
+};
+const BTYPES: [BType; 4] = {
+    use BType::*;
+    [IntT, BoolT, CharT, UnitT]
+};
+
+/// The JSON dump of `spl`.
+pub fn to_json(spl: &SPL) -> Json {
+    spl_json(spl)
+}
+
+/// The S-expression dump of `spl`. It holds the same data as the JSON dump:
+/// a node is `(Kind :field value ...)`, and arrays are lists.
+pub fn to_sexp(spl: &SPL) -> String {
+    let mut out = String::new();
+    sexp(&to_json(spl), 0, &mut out);
+    out
+}
+
+/// Read a JSON dump back into the AST, interning the names of its
+/// identifiers.
+pub fn from_json(json: &Json) -> LoadResult<SPL> {
+    array(field(json, "decls", "program")?, "decls")?
+        .iter()
+        .map(load_decl)
+        .collect()
+}
+
+fn span(span: Option<Span>) -> Json {
+    match span {
+        Some(Span { file, start, end }) => Json::Array(vec![
+            Json::Int(file.0 as i64),
+            Json::Int(start as i64),
+            Json::Int(end as i64),
+        ]),
+        None => Json::Null,
+    }
+}
+
+fn opt<T>(node: &Option<T>, dump: impl FnOnce(&T) -> Json) -> Json {
+    node.as_ref().map_or(Json::Null, dump)
+}
+
+fn node(kind: &str, mut fields: Vec<(&str, Json)>, at: Option<Span>) -> Json {
+    fields.insert(0, ("kind", Json::Str(kind.to_string())));
+    fields.push(("span", span(at)));
+    Json::object(fields)
+}
+
+fn spl_json(spl: &SPL) -> Json {
+    Json::object(vec![("decls", list(spl, decl_json))])
+}
+
+fn id_json(&(id, at): &Id) -> Json {
+    Json::object(vec![
+        ("name", Json::Str(id.name().to_string())),
+        ("span", span(at)),
+    ])
+}
+
+fn list<T>(nodes: &[T], dump: impl Fn(&T) -> Json) -> Json {
+    Json::Array(nodes.iter().map(dump).collect())
+}
+
+fn decl_json((decl, at): &Decl) -> Json {
+    match decl {
+        BareDecl::Global(var) => node("Global", vec![("var", var_decl_json(var))], *at),
+        BareDecl::Fun(name, args, typ, body) => node(
+            "Fun",
+            vec![
+                ("name", id_json(name)),
+                ("args", list(args, id_json)),
+                ("type", opt(typ, fun_type_json)),
+                ("body", list(body, stmt_json)),
+            ],
+            *at,
+        ),
+    }
+}
+
+fn var_decl_json((typ, name, value): &VarDecl) -> Json {
+    Json::object(vec![
+        ("type", opt(typ, type_json)),
+        ("name", id_json(name)),
+        ("value", exp_json(value)),
+    ])
+}
+
+fn fun_type_json(((args, ret), at): &FunType) -> Json {
+    Json::object(vec![
+        ("args", list(args, type_json)),
+        ("ret", type_json(ret)),
+        ("span", span(*at)),
+    ])
+}
+
+fn stmt_json((stmt, at): &Stmt) -> Json {
+    use BareStmt::*;
+    let (kind, fields) = match stmt {
+        ITE(cond, then, els) => (
+            "ITE",
+            vec![
+                ("cond", exp_json(cond)),
+                ("then", list(then, stmt_json)),
+                ("else", list(els, stmt_json)),
+            ],
+        ),
+        While(cond, body) => (
+            "While",
+            vec![("cond", exp_json(cond)), ("body", list(body, stmt_json))],
+        ),
+        Assign(name, value) => (
+            "Assign",
+            vec![("name", id_json(name)), ("value", exp_json(value))],
+        ),
+        Call(name, args) => (
+            "Call",
+            vec![("name", id_json(name)), ("args", list(args, exp_json))],
+        ),
+        Ret(value) => ("Ret", vec![("value", opt(value, exp_json))]),
+        Local(var) => ("Local", vec![("var", var_decl_json(var))]),
+    };
+    node(kind, fields, *at)
+}
+
+fn exp_json(((exp, typ), at): &Exp) -> Json {
+    use BareExp::*;
+    let (kind, mut fields) = match exp {
+        Var(name, selectors) => (
+            "Var",
+            vec![
+                ("name", id_json(name)),
+                (
+                    "fields",
+                    Json::Array(
+                        selectors
+                            .iter()
+                            .map(|&(sel, at)| {
+                                Json::object(vec![
+                                    ("field", Json::Str(sel.name().to_string())),
+                                    ("span", span(at)),
+                                ])
+                            })
+                            .collect(),
+                    ),
+                ),
+            ],
+        ),
+        Call(name, args) => (
+            "Call",
+            vec![("name", id_json(name)), ("args", list(args, exp_json))],
+        ),
+        Lit(val) => ("Lit", vec![("value", lit(*val))]),
+        Tuple(elems) => ("Tuple", vec![("elems", list(elems, exp_json))]),
+        BinOp(op, lhs, rhs) => (
+            "BinOp",
+            vec![
+                ("op", op_json(op)),
+                ("lhs", exp_json(lhs)),
+                ("rhs", exp_json(rhs)),
+            ],
+        ),
+        UnOp(op, arg) => ("UnOp", vec![("op", op_json(op)), ("arg", exp_json(arg))]),
+    };
+    fields.push(("type", opt(typ, type_json)));
+    node(kind, fields, *at)
+}
+
+fn type_json((typ, at): &Type) -> Json {
+    match typ {
+        BareType::Lit(btype) => node(btype.name(), Vec::new(), *at),
+        BareType::Typename(name) => node("Typename", vec![("name", id_json(name))], *at),
+        BareType::Tuple(elems) => node("Tuple", vec![("elems", list(elems, type_json))], *at),
+        BareType::List(elem) => node("List", vec![("elem", type_json(elem))], *at),
+    }
+}
+
+/// Integers are numbers, characters one-character strings, booleans
+/// booleans, and `[]` is `null`.
+fn lit(val: LitVal) -> Json {
+    match val {
+        LitVal::Int(n) => Json::Int(n),
+        LitVal::Char(c) => Json::Str(c.to_string()),
+        LitVal::Bool(b) => Json::Bool(b),
+        LitVal::Nil => Json::Null,
+    }
+}
+
+fn op_json(&(op, at): &Op) -> Json {
+    Json::object(vec![
+        ("op", Json::Str(format!("{:?}", op))),
+        ("span", span(at)),
+    ])
+}
+
+fn sexp(json: &Json, indent: usize, out: &mut String) {
+    let (open, items): (Option<&str>, Vec<(Option<&str>, &Json)>) = match json {
+        Json::Null => return out.push_str("nil"),
+        Json::Bool(true) => return out.push_str("#t"),
+        Json::Bool(false) => return out.push_str("#f"),
+        Json::Int(_) | Json::Str(_) => return write!(out, "{}", json).unwrap(),
+        Json::Array(items) => (None, items.iter().map(|item| (None, item)).collect()),
+        Json::Object(fields) => (
+            match json.get("kind") {
+                Some(Json::Str(kind)) => Some(kind),
+                _ => None,
+            },
+            fields
+                .iter()
+                .filter(|(key, _)| key != "kind")
+                .map(|(key, value)| (Some(key.as_str()), value))
+                .collect(),
+        ),
+    };
+    let inline = items.iter().all(|(_, item)| item.is_flat());
+    out.push('(');
+    let mut first = true;
+    if let Some(kind) = open {
+        out.push_str(kind);
+        first = false;
+    }
+    for (key, item) in items {
+        if first {
+            first = false;
+        } else if inline {
+            out.push(' ');
+        } else {
+            write!(out, "\n{:1$}", "", indent * 2 + 2).unwrap();
+        }
+        if let Some(key) = key {
+            write!(out, ":{} ", key).unwrap();
+        }
+        sexp(item, indent + 1, out);
+    }
+    out.push(')');
+}
+
+fn field<'j>(json: &'j Json, key: &str, what: &str) -> LoadResult<&'j Json> {
+    json.get(key)
+        .ok_or_else(|| LoadError(format!("Expected field '{}' in {}", key, what)))
+}
+
+fn array<'j>(json: &'j Json, what: &str) -> LoadResult<&'j [Json]> {
+    match json {
+        Json::Array(items) => Ok(items),
+        _ => Err(LoadError(format!("Expected an array for {}", what))),
+    }
+}
+
+fn string<'j>(json: &'j Json, what: &str) -> LoadResult<&'j str> {
+    match json {
+        Json::Str(s) => Ok(s),
+        _ => Err(LoadError(format!("Expected a string for {}", what))),
+    }
+}
+
+fn load_span(json: &Json) -> LoadResult<Option<Span>> {
+    let offset = |n: &Json| match n {
+        Json::Int(n) if (0..=u32::MAX as i64).contains(n) => Ok(*n as u32),
+        _ => Err(LoadError("Expected an offset in span".to_string())),
+    };
+    match json.get("span") {
+        None | Some(Json::Null) => Ok(None),
+        Some(Json::Array(parts)) if parts.len() == 3 => Ok(Some(Span::new(
+            FileId(offset(&parts[0])?),
+            offset(&parts[1])?,
+            offset(&parts[2])?,
+        ))),
+        Some(_) => Err(LoadError(
+            "Expected [file, start, end] or null for span".to_string(),
+        )),
+    }
+}
+
+fn load_opt<T>(
+    json: Option<&Json>,
+    load: impl FnOnce(&Json) -> LoadResult<T>,
+) -> LoadResult<Option<T>> {
+    match json {
+        None | Some(Json::Null) => Ok(None),
+        Some(json) => load(json).map(Some),
+    }
+}
+
+fn load_lit(json: &Json) -> LoadResult<LitVal> {
+    match json {
+        Json::Int(n) => Ok(LitVal::Int(*n)),
+        Json::Bool(b) => Ok(LitVal::Bool(*b)),
+        Json::Null => Ok(LitVal::Nil),
+        Json::Str(s) if s.chars().count() == 1 => Ok(LitVal::Char(s.chars().next().unwrap())),
+        _ => Err(LoadError(format!("Invalid literal {}", json))),
+    }
+}
+
+/// Load the operator of a `UnOp` if `unary`, else of a `BinOp`.
+fn load_op(json: &Json, unary: bool) -> LoadResult<Op> {
+    let name = string(field(json, "op", "operator")?, "op")?;
+    let op = OPS
+        .iter()
+        .find(|op| format!("{:?}", op) == name)
+        .ok_or_else(|| LoadError(format!("Unknown operator '{}'", name)))?;
+    if op.is_unary() != unary {
+        let arity = if unary { "unary" } else { "binary" };
+        return Err(LoadError(format!("Operator '{}' is not {}", name, arity)));
+    }
+    Ok((*op, load_span(json)?))
+}
+
+fn load_selector(json: &Json) -> LoadResult<Selector> {
+    let name = string(field(json, "field", "selector")?, "field")?;
+    let sel = SELECTORS
+        .iter()
+        .find(|sel| sel.name() == name)
+        .ok_or_else(|| LoadError(format!("Unknown field '{}'", name)))?;
+    Ok((*sel, load_span(json)?))
+}
+
+fn load_id(json: &Json) -> LoadResult<Id> {
+    let name = string(field(json, "name", "identifier")?, "name")?;
+    Ok((BareId::intern(name), load_span(json)?))
+}
+
+fn load_list<T>(
+    json: &Json,
+    key: &str,
+    what: &str,
+    load: impl Fn(&Json) -> LoadResult<T>,
+) -> LoadResult<Vec<T>> {
+    array(field(json, key, what)?, key)?
+        .iter()
+        .map(load)
+        .collect()
+}
+
+fn load_decl(json: &Json) -> LoadResult<Decl> {
+    let decl = match string(field(json, "kind", "declaration")?, "kind")? {
+        "Global" => BareDecl::Global(load_var_decl(field(json, "var", "Global")?)?),
+        "Fun" => BareDecl::Fun(
+            load_id(field(json, "name", "Fun")?)?,
+            load_list(json, "args", "Fun", load_id)?,
+            load_opt(json.get("type"), load_fun_type)?,
+            load_list(json, "body", "Fun", load_stmt)?,
+        ),
+        kind => return Err(LoadError(format!("Unknown declaration kind '{}'", kind))),
+    };
+    Ok((decl, load_span(json)?))
+}
+
+fn load_var_decl(json: &Json) -> LoadResult<VarDecl> {
+    Ok((
+        load_opt(json.get("type"), load_typ)?,
+        load_id(field(json, "name", "variable")?)?,
+        load_exp(field(json, "value", "variable")?)?,
+    ))
+}
+
+fn load_fun_type(json: &Json) -> LoadResult<FunType> {
+    Ok((
+        (
+            load_list(json, "args", "function type", load_typ)?,
+            load_typ(field(json, "ret", "function type")?)?,
+        ),
+        load_span(json)?,
+    ))
+}
+
+fn load_stmt(json: &Json) -> LoadResult<Stmt> {
+    use BareStmt::*;
+    let stmt = match string(field(json, "kind", "statement")?, "kind")? {
+        "ITE" => ITE(
+            load_exp(field(json, "cond", "ITE")?)?,
+            load_list(json, "then", "ITE", load_stmt)?,
+            load_list(json, "else", "ITE", load_stmt)?,
+        ),
+        "While" => While(
+            load_exp(field(json, "cond", "While")?)?,
+            load_list(json, "body", "While", load_stmt)?,
+        ),
+        "Assign" => Assign(
+            load_id(field(json, "name", "Assign")?)?,
+            load_exp(field(json, "value", "Assign")?)?,
+        ),
+        "Call" => Call(
+            load_id(field(json, "name", "Call")?)?,
+            load_list(json, "args", "Call", load_exp)?,
+        ),
+        "Ret" => Ret(load_opt(json.get("value"), load_exp)?),
+        "Local" => Local(load_var_decl(field(json, "var", "Local")?)?),
+        kind => return Err(LoadError(format!("Unknown statement kind '{}'", kind))),
+    };
+    Ok((stmt, load_span(json)?))
+}
+
+fn load_exp(json: &Json) -> LoadResult<Exp> {
+    use BareExp::*;
+    let exp = match string(field(json, "kind", "expression")?, "kind")? {
+        "Var" => Var(
+            load_id(field(json, "name", "Var")?)?,
+            array(field(json, "fields", "Var")?, "fields")?
+                .iter()
+                .map(load_selector)
+                .collect::<LoadResult<_>>()?,
+        ),
+        "Call" => Call(
+            load_id(field(json, "name", "Call")?)?,
+            load_list(json, "args", "Call", load_exp)?,
+        ),
+        "Lit" => Lit(load_lit(field(json, "value", "Lit")?)?),
+        "Tuple" => Tuple(load_list(json, "elems", "Tuple", load_exp)?),
+        "BinOp" => BinOp(
+            load_op(field(json, "op", "BinOp")?, false)?,
+            Box::new(load_exp(field(json, "lhs", "BinOp")?)?),
+            Box::new(load_exp(field(json, "rhs", "BinOp")?)?),
+        ),
+        "UnOp" => UnOp(
+            load_op(field(json, "op", "UnOp")?, true)?,
+            Box::new(load_exp(field(json, "arg", "UnOp")?)?),
+        ),
+        kind => return Err(LoadError(format!("Unknown expression kind '{}'", kind))),
+    };
+    let typ = load_opt(json.get("type"), load_typ)?;
+    Ok(((exp, typ), load_span(json)?))
+}
+
+fn load_typ(json: &Json) -> LoadResult<Type> {
+    let typ = match string(field(json, "kind", "type")?, "kind")? {
+        "Typename" => BareType::Typename(load_id(field(json, "name", "Typename")?)?),
+        "Tuple" => BareType::Tuple(load_list(json, "elems", "Tuple", load_typ)?),
+        "List" => BareType::List(Box::new(load_typ(field(json, "elem", "List")?)?)),
+        kind => match BTYPES.iter().find(|btype| btype.name() == kind) {
+            Some(&btype) => BareType::Lit(btype),
+            None => return Err(LoadError(format!("Unknown type kind '{}'", kind))),
+        },
+    };
+    Ok((typ, load_span(json)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::tests::{parse, SOURCE};
+
+    #[test]
+    fn json_round_trip() {
+        let mut spl = parse(SOURCE);
+        // Give an expression a type annotation, as type inference would.
+        if let BareDecl::Global((_, _, exp)) = &mut spl[0].0 {
+            (exp.0).1 = Some((BareType::Lit(BType::IntT), None));
+        }
+        let text = to_json(&spl).to_string();
+        let json = Json::parse(&text).unwrap();
+        assert_eq!(from_json(&json), Ok(spl));
+    }
+
+    #[test]
+    fn json_format() {
+        let spl = parse("var x = -(1) : y.tl;");
+        assert_eq!(
+            to_json(&spl).to_string(),
+            r#"{
+  "decls": [
+    {
+      "kind": "Global",
+      "var": {
+        "type": null,
+        "name": {"name": "x", "span": [0, 4, 5]},
+        "value": {
+          "kind": "BinOp",
+          "op": {"op": "Cons", "span": [0, 13, 14]},
+          "lhs": {
+            "kind": "UnOp",
+            "op": {"op": "Neg", "span": [0, 8, 9]},
+            "arg": {"kind": "Lit", "value": 1, "type": null, "span": [0, 9, 12]},
+            "type": null,
+            "span": [0, 8, 12]
+          },
+          "rhs": {
+            "kind": "Var",
+            "name": {"name": "y", "span": [0, 15, 16]},
+            "fields": [
+              {"field": "tl", "span": [0, 17, 19]}
+            ],
+            "type": null,
+            "span": [0, 15, 19]
+          },
+          "type": null,
+          "span": [0, 8, 19]
+        }
+      },
+      "span": [0, 0, 20]
+    }
+  ]
+}"#
+        );
+    }
+
+    #[test]
+    fn sexp_format() {
+        let spl = parse("f(x) :: Int -> Bool { return !x; }");
+        assert_eq!(
+            to_sexp(&spl),
+            r#"(:decls ((Fun
+      :name (:name "f" :span (0 0 1))
+      :args ((:name "x" :span (0 2 3)))
+      :type (:args ((Int :span (0 8 11)))
+        :ret (Bool :span (0 15 19))
+        :span (0 8 19))
+      :body ((Ret
+          :value (UnOp
+            :op (:op "Not" :span (0 29 30))
+            :arg (Var
+              :name (:name "x" :span (0 30 31))
+              :fields ()
+              :type nil
+              :span (0 30 31))
+            :type nil
+            :span (0 29 31))
+          :span (0 22 32)))
+      :span (0 0 34))))"#
+        );
+    }
+
+    #[test]
+    fn load_errors() {
+        let load = |text: &str| from_json(&Json::parse(text).unwrap()).map(|_| ());
+        assert_eq!(
+            load(r#"{}"#),
+            Err(LoadError("Expected field 'decls' in program".to_string()))
+        );
+        assert_eq!(
+            load(r#"{"decls": [{"kind": "Class", "span": null}]}"#),
+            Err(LoadError("Unknown declaration kind 'Class'".to_string()))
+        );
+        assert_eq!(
+            load(
+                r#"{"decls": [{"kind": "Global", "var": {"type": null,
+                    "name": {"name": "x", "span": [0, 4]},
+                    "value": {"kind": "Lit", "value": 1}}}]}"#
+            ),
+            Err(LoadError(
+                "Expected [file, start, end] or null for span".to_string()
+            ))
+        );
+        let exp = |kind, op| {
+            load(&format!(
+                r#"{{"decls": [{{"kind": "Global", "var": {{"type": null,
+                    "name": {{"name": "x", "span": null}},
+                    "value": {{"kind": "{}", "op": {{"op": "{}", "span": null}},
+                        "arg": {{"kind": "Lit", "value": 1}},
+                        "lhs": {{"kind": "Lit", "value": 1}},
+                        "rhs": {{"kind": "Lit", "value": 2}}}}}}, "span": null}}]}}"#,
+                kind, op
+            ))
+        };
+        assert_eq!(
+            exp("BinOp", "Not"),
+            Err(LoadError("Operator 'Not' is not binary".to_string()))
+        );
+        assert_eq!(
+            exp("UnOp", "Plus"),
+            Err(LoadError("Operator 'Plus' is not unary".to_string()))
+        );
+        assert_eq!(exp("UnOp", "Neg"), Ok(()));
+    }
+}
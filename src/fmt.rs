@@ -0,0 +1,566 @@
+use crate::ast::*;
+use crate::parser::cst::SyntaxKind;
+use crate::parser::{parse_lossless, ParseError};
+use crate::pretty::{parenthesize, Side};
+use crate::source::{FileId, SourceMap};
+use std::io::{Read, Write};
+
+const INDENT: &str = "    ";
+
+/// Reprint `file` in the canonical style: four space indentation, opening
+/// braces on the line of their statement, one space around binary
+/// operators, and only the parentheses that operator priority requires.
+/// Comments are kept, on their own line before the statement or
+/// declaration they precede, or at the end of the line of the one they
+/// follow; a comment inside a statement moves to the end of it. Single
+/// blank lines between statements are kept, and functions are separated
+/// from other declarations by one. Formatting formatted source changes
+/// nothing.
+pub fn format(map: &SourceMap, file: FileId, width: IntWidth) -> Result<String, ParseError> {
+    let (spl, root) = parse_lossless(map, file, width)?;
+    let mut fmt = Formatter {
+        map,
+        source: map.source(file),
+        comments: Vec::new(),
+        literals: Vec::new(),
+        elses: Vec::new(),
+        next: 0,
+        last: None,
+        indent: 0,
+        out: String::with_capacity(map.source(file).len()),
+    };
+    for tok in root.tokens() {
+        match tok.kind() {
+            SyntaxKind::LineComment | SyntaxKind::BlockComment => fmt.comments.push(tok.span()),
+            SyntaxKind::IntLit
+            | SyntaxKind::CharLit
+            | SyntaxKind::StrLit
+            | SyntaxKind::BoolLit
+            | SyntaxKind::NilLit => fmt.literals.push(tok.span()),
+            SyntaxKind::Operator if tok.parent().kind() == SyntaxKind::LitExp => {
+                fmt.literals.push(tok.span())
+            }
+            SyntaxKind::Keyword if tok.text() == "else" => fmt.elses.push(tok.span().start),
+            _ => (),
+        }
+    }
+    fmt.spl(&spl);
+    Ok(fmt.out)
+}
+
+fn spanned(span: Option<Span>) -> Span {
+    span.expect("Internal formatter error: parsed node without a span")
+}
+
+struct Formatter<'a> {
+    map: &'a SourceMap,
+    source: &'a str,
+    /// All comments, and the index of the first one not yet printed.
+    comments: Vec<Span>,
+    next: usize,
+    /// Literal tokens and the minus signs of negative literals, for printing
+    /// literals as they were written.
+    literals: Vec<Span>,
+    /// Offsets of the `else` keywords.
+    elses: Vec<u32>,
+    /// The source offset up to which everything has been printed, if
+    /// anything has been in the current block.
+    last: Option<u32>,
+    indent: usize,
+    out: String,
+}
+
+impl<'a> Formatter<'a> {
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn blank_line(&mut self) {
+        if !self.out.is_empty() && !self.out.ends_with("\n\n") && !self.out.ends_with("{\n") {
+            self.out.push('\n');
+        }
+    }
+
+    /// Whether the source has an empty line between the last printed
+    /// offset and `pos`.
+    fn blank_since(&self, pos: u32) -> bool {
+        let last = match self.last {
+            Some(last) if last < pos => last,
+            _ => return false,
+        };
+        let gap = self.source[last as usize..pos as usize]
+            .replace("\r\n", "\n")
+            .replace('\r', "\n");
+        let lines: Vec<&str> = gap.split('\n').collect();
+        lines.len() > 2
+            && lines[1..lines.len() - 1]
+                .iter()
+                .any(|l| l.trim().is_empty())
+    }
+
+    fn comment_text(&self, comment: Span) -> &'a str {
+        let text = &self.source[comment.start as usize..comment.end as usize];
+        if text.starts_with("//") {
+            text.trim_end()
+        } else {
+            text
+        }
+    }
+
+    /// Print the comments before `pos` on their own lines, after a blank
+    /// line if `blank`. Returns whether that blank line is still due.
+    fn comments_before(&mut self, pos: u32, mut blank: bool) -> bool {
+        while let Some(&comment) = self.comments.get(self.next) {
+            if comment.start >= pos {
+                break;
+            }
+            if blank || self.blank_since(comment.start) {
+                self.blank_line();
+            }
+            blank = false;
+            let text = self.comment_text(comment);
+            self.line(text);
+            self.last = Some(comment.end);
+            self.next += 1;
+        }
+        blank
+    }
+
+    /// Prepare to print something starting at `start`.
+    fn leading(&mut self, start: u32, blank: bool) {
+        if self.comments_before(start, blank) || self.blank_since(start) {
+            self.blank_line();
+        }
+    }
+
+    /// Append the comments inside the construct just printed, which ended
+    /// at `end`, and those following it on the same line, to its last line.
+    fn trailing(&mut self, end: u32) {
+        self.last = Some(end);
+        let mut own_line = false;
+        while let Some(&comment) = self.comments.get(self.next) {
+            let inside = comment.start < end;
+            let gap = &self.source[end.min(comment.start) as usize..comment.start as usize];
+            if !inside && gap.contains(['\n', '\r']) {
+                break;
+            }
+            let text = self.comment_text(comment);
+            if own_line {
+                self.line(text);
+            } else {
+                self.out.pop();
+                self.out.push(' ');
+                self.out.push_str(text);
+                self.out.push('\n');
+            }
+            own_line = text.starts_with("//");
+            self.last = Some(comment.end.max(end));
+            self.next += 1;
+        }
+    }
+
+    fn spl(&mut self, spl: &[Decl]) {
+        let mut prev_fun = None;
+        for decl in spl {
+            let span = spanned(decl.1);
+            let fun = matches!(decl.0, BareDecl::Fun(..));
+            self.leading(span.start, prev_fun.is_some_and(|prev| prev || fun));
+            self.decl(decl);
+            self.trailing(span.end);
+            prev_fun = Some(fun);
+        }
+        self.comments_before(u32::MAX, false);
+    }
+
+    fn decl(&mut self, (decl, span): &Decl) {
+        match decl {
+            BareDecl::Global(var) => {
+                let text = self.var_decl(var);
+                self.line(&text);
+            }
+            BareDecl::Fun(id, args, typ, body) => {
+                let args: Vec<&str> = args.iter().map(|arg| self.name(arg)).collect();
+                let mut header = format!("{}({})", self.name(id), args.join(", "));
+                if let Some(((args, ret), _)) = typ {
+                    header.push_str(" ::");
+                    for arg in args {
+                        header.push(' ');
+                        header.push_str(&self.typ(arg));
+                    }
+                    header.push_str(" -> ");
+                    header.push_str(&self.typ(ret));
+                }
+                header.push_str(" {");
+                self.line(&header);
+                self.block(body, spanned(*span).end);
+                self.line("}");
+            }
+        }
+    }
+
+    /// Print `stmts` one level deeper, with the comments before `end`.
+    fn block(&mut self, stmts: &[Stmt], end: u32) {
+        self.indent += 1;
+        self.last = None;
+        for stmt in stmts {
+            let span = spanned(stmt.1);
+            self.leading(span.start, false);
+            self.stmt(stmt);
+            self.trailing(span.end);
+        }
+        self.comments_before(end, false);
+        self.indent -= 1;
+    }
+
+    fn stmt(&mut self, (stmt, span): &Stmt) {
+        let span = spanned(*span);
+        match stmt {
+            BareStmt::ITE(cond, then, els) => {
+                let text = format!("if ({}) {{", self.exp(cond));
+                self.line(&text);
+                // Comments up to the `else` belong to the first branch.
+                let after = then.last().map_or(cond.1, |stmt| stmt.1);
+                let after = spanned(after).end;
+                let idx = self.elses.partition_point(|&pos| pos < after);
+                let then_end = match self.elses.get(idx) {
+                    Some(&pos) if pos < span.end => pos,
+                    _ => span.end,
+                };
+                self.block(then, then_end);
+                if els.is_empty() {
+                    self.line("}");
+                } else {
+                    self.line("} else {");
+                    self.block(els, span.end);
+                    self.line("}");
+                }
+            }
+            BareStmt::While(cond, body) => {
+                let text = format!("while ({}) {{", self.exp(cond));
+                self.line(&text);
+                self.block(body, span.end);
+                self.line("}");
+            }
+            BareStmt::Assign(id, exp) => {
+                let text = format!("{} = {};", self.name(id), self.exp(exp));
+                self.line(&text);
+            }
+            BareStmt::Call(id, args) => {
+                let text = format!("{};", self.call(id, args));
+                self.line(&text);
+            }
+            BareStmt::Ret(None) => self.line("return;"),
+            BareStmt::Ret(Some(exp)) => {
+                let text = format!("return {};", self.exp(exp));
+                self.line(&text);
+            }
+            BareStmt::Local(var) => {
+                let text = self.var_decl(var);
+                self.line(&text);
+            }
+        }
+    }
+
+    fn var_decl(&self, (typ, id, exp): &VarDecl) -> String {
+        let typ = typ.as_ref().map_or("var".to_string(), |typ| self.typ(typ));
+        format!("{} {} = {};", typ, self.name(id), self.exp(exp))
+    }
+
+    fn name(&self, id: &Id) -> &'a str {
+        self.map.snippet(spanned(id.1))
+    }
+
+    fn typ(&self, (typ, _): &Type) -> String {
+        match typ {
+            BareType::Lit(btype) => btype.name().to_string(),
+            BareType::Typename(id) => self.name(id).to_string(),
+            BareType::Tuple(elems) => {
+                let elems: Vec<String> = elems.iter().map(|elem| self.typ(elem)).collect();
+                format!("({})", elems.join(", "))
+            }
+            BareType::List(elem) => format!("[{}]", self.typ(elem)),
+        }
+    }
+
+    /// The literal at or after `start`; parentheses around a literal are
+    /// part of its span.
+    fn literal(&self, start: u32) -> String {
+        let idx = self.literals.partition_point(|lit| lit.start < start);
+        match self.map.snippet(self.literals[idx]) {
+            "-" => format!("-{}", self.map.snippet(self.literals[idx + 1])),
+            text => text.to_string(),
+        }
+    }
+
+    fn call(&self, id: &Id, args: &[Exp]) -> String {
+        let args: Vec<String> = args.iter().map(|arg| self.exp(arg)).collect();
+        format!("{}({})", self.name(id), args.join(", "))
+    }
+
+    fn exp(&self, ((exp, _), span): &Exp) -> String {
+        match exp {
+            BareExp::Var(id, fields) => {
+                let mut text = self.name(id).to_string();
+                for (field, _) in fields {
+                    text.push('.');
+                    text.push_str(field.name());
+                }
+                text
+            }
+            BareExp::Call(id, args) => self.call(id, args),
+            BareExp::Lit(_) => self.literal(spanned(*span).start),
+            BareExp::Tuple(elems) => {
+                let elems: Vec<String> = elems.iter().map(|elem| self.exp(elem)).collect();
+                format!("({})", elems.join(", "))
+            }
+            BareExp::BinOp(_, lhs, rhs) if is_string(lhs, rhs) => {
+                self.literal(spanned(lhs.1).start)
+            }
+            BareExp::BinOp((op, _), lhs, rhs) => format!(
+                "{} {} {}",
+                self.operand(lhs, parenthesize(*op, Side::Left, lhs, &is_literal)),
+                op.symbol(),
+                self.operand(rhs, parenthesize(*op, Side::Right, rhs, &is_literal)),
+            ),
+            BareExp::UnOp((op, _), arg) => format!(
+                "{}{}",
+                op.symbol(),
+                self.operand(arg, parenthesize(*op, Side::Right, arg, &is_literal))
+            ),
+        }
+    }
+
+    fn operand(&self, exp: &Exp, parens: bool) -> String {
+        if parens {
+            format!("({})", self.exp(exp))
+        } else {
+            self.exp(exp)
+        }
+    }
+}
+
+/// Whether `lhs : rhs` is (part of) a desugared string literal, all of
+/// whose parts have the span of the literal.
+fn is_string(lhs: &Exp, rhs: &Exp) -> bool {
+    lhs.1.is_some() && lhs.1 == rhs.1
+}
+
+/// Whether `exp` is a string literal, which prints as written.
+fn is_literal(((exp, _), _): &Exp) -> bool {
+    matches!(exp, BareExp::BinOp(_, lhs, rhs) if is_string(lhs, rhs))
+}
+
+fn describe(map: &SourceMap, file: FileId, err: &ParseError) -> String {
+    match err.1 {
+        Some(loc) => format!("{}: {}", map.describe(loc.into()), err.0),
+        None => format!("{}: {}", map.name(file), err.0),
+    }
+}
+
+/// `spl fmt [--check] [FILE]...`: format the files in place, or standard
+/// input to standard output. With `--check`, change nothing but list the
+/// files that are not formatted and fail if there are any. Returns the exit
+/// code: 1 if `--check` failed, 2 on errors.
+pub fn main(args: &[String], width: IntWidth) -> i32 {
+    let check = args.iter().any(|arg| arg == "--check");
+    let files: Vec<&String> = args.iter().filter(|&arg| arg != "--check").collect();
+    let mut map = SourceMap::new();
+    let mut status = 0;
+    if files.is_empty() {
+        let mut source = String::new();
+        if let Err(err) = std::io::stdin().read_to_string(&mut source) {
+            eprintln!("<stdin>: {}", err);
+            return 2;
+        }
+        let file = map.add("<stdin>".to_string(), source);
+        match format(&map, file, width) {
+            Ok(formatted) if check => {
+                if formatted != map.source(file) {
+                    println!("<stdin>");
+                    status = 1;
+                }
+            }
+            Ok(formatted) => {
+                if let Err(err) = std::io::stdout().write_all(formatted.as_bytes()) {
+                    eprintln!("<stdout>: {}", err);
+                    status = 2;
+                }
+            }
+            Err(err) => {
+                eprintln!("{}", describe(&map, file, &err));
+                status = 2;
+            }
+        }
+        return status;
+    }
+    for name in files {
+        let source = match std::fs::read_to_string(name) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("{}: {}", name, err);
+                status = 2;
+                continue;
+            }
+        };
+        let file = map.add(name.clone(), source);
+        let formatted = match format(&map, file, width) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                eprintln!("{}", describe(&map, file, &err));
+                status = 2;
+                continue;
+            }
+        };
+        if formatted == map.source(file) {
+            continue;
+        }
+        if check {
+            println!("{}", name);
+            status = status.max(1);
+        } else if let Err(err) = std::fs::write(name, formatted) {
+            eprintln!("{}: {}", name, err);
+            status = 2;
+        }
+    }
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::visit::{Fold, StripSpans};
+
+    fn fmt(source: &str) -> String {
+        let mut map = SourceMap::new();
+        let file = map.add("test.spl".to_string(), source.to_string());
+        let formatted = format(&map, file, IntWidth::W64).unwrap();
+        let again = map.add("again.spl".to_string(), formatted.clone());
+        assert_eq!(
+            format(&map, again, IntWidth::W64).unwrap(),
+            formatted,
+            "formatting is not idempotent"
+        );
+        formatted
+    }
+
+    #[test]
+    fn layout() {
+        let source = "var  x=1;Int y = x+2 ;\nf(a,b)::Int Int->Int{if(a<b){return a;}else{while(a>0){a=a-1;}}return b;}\ng(){f(1,2);return;}";
+        let expected = "var x = 1;
+Int y = x + 2;
+
+f(a, b) :: Int Int -> Int {
+    if (a < b) {
+        return a;
+    } else {
+        while (a > 0) {
+            a = a - 1;
+        }
+    }
+    return b;
+}
+
+g() {
+    f(1, 2);
+    return;
+}
+";
+        assert_eq!(fmt(source), expected);
+    }
+
+    #[test]
+    fn minimal_parentheses() {
+        let cases = [
+            ("((a * b)) + (c)", "a * b + c"),
+            ("(a + b) * c - (d - e)", "(a + b) * c - (d - e)"),
+            ("(a - b) - c", "a - b - c"),
+            ("a : (b : c)", "a : b : c"),
+            ("(a : b) : c", "(a : b) : c"),
+            ("-(a * b)", "-(a * b)"),
+            ("(-a) * b", "-a * b"),
+            ("!(a == b)", "!(a == b)"),
+            ("!(a < b) && (!c)", "!a < b && !c"),
+            ("(a || b) && c || d", "(a || b) && c || d"),
+            ("- (- a)", "--a"),
+            (
+                "(1, (x.hd.tl), f(('c'), \"s\"))",
+                "(1, x.hd.tl, f('c', \"s\"))",
+            ),
+            ("(\"ab\") : [] : (\"\")", "\"ab\" : [] : \"\""),
+            // `!` binds looser than `<`, so without the parentheses the
+            // comparison would be its operand.
+            ("(x + !y) < r", "(x + !y) < r"),
+            ("(!y) < r", "(!y) < r"),
+            ("x + (!y < r)", "x + !y < r"),
+        ];
+        for &(input, expected) in cases.iter() {
+            let formatted = fmt(&format!("var x = {};", input));
+            assert_eq!(formatted, format!("var x = {};\n", expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn literals_as_written() {
+        assert_eq!(
+            fmt("var x = (0x1F) + '\\n' + True + 007;"),
+            "var x = 0x1F + '\\n' + True + 007;\n"
+        );
+        assert_eq!(
+            fmt("var y = - /* c */ 0x1F - -(1);"),
+            "var y = -0x1F - -(1); /* c */\n"
+        );
+    }
+
+    #[test]
+    fn comments_kept() {
+        let source = "// header\r\n\r\n\r\nvar x = 1; // one\n/* doc */ f(a) {\n  // first\n\n\n  a = a + /* inline */ 1;\n  if (a) { return a; } // then\n  else { /* empty */ }\n  // last\n}\n// trailer\n";
+        let expected = "// header
+
+var x = 1; // one
+
+/* doc */
+f(a) {
+    // first
+
+    a = a + 1; /* inline */
+    if (a) {
+        return a; // then
+    } /* empty */
+    // last
+}
+// trailer
+";
+        assert_eq!(fmt(source), expected);
+    }
+
+    #[test]
+    fn blank_lines_between_globals() {
+        let source = "var a = 1;\nvar b = 2;\n\n\nvar c = 3;\nf() { }\nvar d = 4;";
+        let expected = "var a = 1;\nvar b = 2;\n\nvar c = 3;\n\nf() {\n}\n\nvar d = 4;\n";
+        assert_eq!(fmt(source), expected);
+    }
+
+    #[test]
+    fn ast_preserved() {
+        let sources = [
+            "f(x) :: [(Int, a)] -> Bool { var l = (1, x) : []; (Int, Bool) p = (-x, !(x < 2)); l = p.fst : (3 - (4 - 5)) : l; return \"x\" == l; }",
+            // A prefix operator at the end of a left operand.
+            "var b = (x + !y) < r; var c = (a * -b) + !c == d;",
+            // Negated literals, and negative ones.
+            "var n = -(5) - -5 * - 0x10 + (-9223372036854775808);",
+        ];
+        let strip = |spl| StripSpans.fold_spl(spl);
+        for source in sources.iter() {
+            let mut map = SourceMap::new();
+            let file = map.add("a.spl".to_string(), source.to_string());
+            let formatted = format(&map, file, IntWidth::W64).unwrap();
+            let again = map.add("b.spl".to_string(), formatted);
+            let parse = |file| crate::parser::Parser::for_file(&map, file, IntWidth::W64).spl();
+            assert_eq!(strip(parse(file).unwrap()), strip(parse(again).unwrap()));
+        }
+    }
+}
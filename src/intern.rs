@@ -0,0 +1,183 @@
+//! The global string interner. Every identifier in every file, and every
+//! name a compiler phase makes up, is a `Symbol` in one table, so equal
+//! names are equal symbols everywhere. The keywords are interned first, as
+//! the constants in `kw`.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+/// An interned string.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+/// The keywords, in the order of the symbols in `kw`.
+pub const KEYWORDS: [&str; 15] = [
+    "var", "Void", "Int", "Bool", "Char", "if", "else", "while", "return", "hd", "tl", "fst",
+    "snd", "False", "True",
+];
+
+/// The symbols of the keywords.
+pub mod kw {
+    use super::Symbol;
+
+    pub const VAR: Symbol = Symbol(0);
+    pub const VOID: Symbol = Symbol(1);
+    pub const INT: Symbol = Symbol(2);
+    pub const BOOL: Symbol = Symbol(3);
+    pub const CHAR: Symbol = Symbol(4);
+    pub const IF: Symbol = Symbol(5);
+    pub const ELSE: Symbol = Symbol(6);
+    pub const WHILE: Symbol = Symbol(7);
+    pub const RETURN: Symbol = Symbol(8);
+    pub const HD: Symbol = Symbol(9);
+    pub const TL: Symbol = Symbol(10);
+    pub const FST: Symbol = Symbol(11);
+    pub const SND: Symbol = Symbol(12);
+    pub const FALSE: Symbol = Symbol(13);
+    pub const TRUE: Symbol = Symbol(14);
+}
+
+/// What separates the base of a fresh name from its number.
+pub const FRESH: char = '$';
+
+/// A table of strings, each stored once for the rest of the program.
+pub struct Interner {
+    symbols: HashMap<&'static str, Symbol>,
+    strings: Vec<&'static str>,
+}
+
+impl Interner {
+    /// An interner holding just the keywords.
+    pub fn new() -> Self {
+        let mut interner = Interner {
+            symbols: HashMap::with_capacity(256),
+            strings: Vec::with_capacity(256),
+        };
+        for word in KEYWORDS.iter() {
+            interner.intern(word);
+        }
+        interner
+    }
+
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(name) {
+            return symbol;
+        }
+        // Interned strings are never freed, so they can be borrowed forever.
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let symbol = Symbol(self.strings.len() as u32);
+        self.strings.push(name);
+        self.symbols.insert(name, symbol);
+        symbol
+    }
+
+    pub fn get(&self, symbol: Symbol) -> &'static str {
+        self.strings[symbol.0 as usize]
+    }
+
+    /// A new symbol named `base`, a `$` and the smallest number that gives
+    /// a name not interned yet. Identifiers in source cannot contain `$`, so
+    /// no file lexed later can use the name either. A `base` that is itself
+    /// fresh counts from its own base.
+    pub fn fresh(&mut self, base: &str) -> Symbol {
+        let base = base.split(FRESH).next().unwrap_or(base);
+        let name = (0..)
+            .map(|n| format!("{}{}{}", base, FRESH, n))
+            .find(|name| !self.symbols.contains_key(name.as_str()))
+            .unwrap();
+        self.intern(&name)
+    }
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Run `f` on the interner shared by the whole compiler.
+fn with_interner<T>(f: impl FnOnce(&mut Interner) -> T) -> T {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    let mut interner = INTERNER
+        .get_or_init(|| Mutex::new(Interner::new()))
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    f(&mut interner)
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Self {
+        with_interner(|interner| interner.intern(name))
+    }
+
+    /// A symbol that no identifier has had or will have, made from `base`.
+    pub fn fresh(base: &str) -> Self {
+        with_interner(|interner| interner.fresh(base))
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match KEYWORDS.get(self.0 as usize) {
+            Some(word) => word,
+            None => with_interner(|interner| interner.get(self)),
+        }
+    }
+
+    pub fn is_keyword(self) -> bool {
+        (self.0 as usize) < KEYWORDS.len()
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn keywords_first() {
+        let interner = Interner::new();
+        assert_eq!(interner.strings.len(), KEYWORDS.len());
+        for (i, word) in KEYWORDS.iter().enumerate() {
+            assert_eq!(Symbol::intern(word), Symbol(i as u32));
+            assert_eq!(interner.get(Symbol(i as u32)), *word);
+        }
+        assert_eq!(Symbol::intern("while"), kw::WHILE);
+        assert_eq!(kw::TRUE.as_str(), "True");
+        assert!(kw::TRUE.is_keyword());
+        assert!(!Symbol::intern("whilst").is_keyword());
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let here = Symbol::intern("shared_between_threads");
+        let there = thread::spawn(|| Symbol::intern("shared_between_threads"))
+            .join()
+            .unwrap();
+        assert_eq!(here, there);
+        assert_eq!(there.as_str(), "shared_between_threads");
+        assert_ne!(here, Symbol::intern("shared_between_thread"));
+    }
+
+    #[test]
+    fn fresh_names() {
+        let mut interner = Interner::new();
+        interner.intern("tmp$0");
+        let tmp = interner.fresh("tmp");
+        assert_eq!(interner.get(tmp), "tmp$1");
+        let tmp2 = interner.fresh("tmp$1");
+        assert_eq!(interner.get(tmp2), "tmp$2");
+        assert_eq!(interner.intern("tmp$1"), tmp);
+    }
+}
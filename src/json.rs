@@ -0,0 +1,362 @@
+//! Just enough JSON for the AST dumps: numbers are integers, and objects
+//! keep their keys in order.
+
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+/// A syntax error at a byte offset.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JsonError(pub String, pub usize);
+
+impl Json {
+    pub fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// The value of `key`, if this is an object that has it.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Json, JsonError> {
+        let mut parser = JsonParser { text, pos: 0 };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos < text.len() {
+            return Err(parser.error("Trailing characters after JSON value"));
+        }
+        Ok(value)
+    }
+
+    fn is_scalar(&self) -> bool {
+        !matches!(self, Json::Array(_) | Json::Object(_))
+    }
+
+    /// Whether this is a scalar or an array of scalars, like a span.
+    pub(crate) fn is_flat(&self) -> bool {
+        match self {
+            Json::Array(items) => items.iter().all(Json::is_scalar),
+            Json::Object(_) => false,
+            _ => true,
+        }
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        // Values holding only flat values go on one line, others one per line.
+        let inline = match self {
+            Json::Array(items) => items.iter().all(Json::is_flat),
+            Json::Object(fields) => fields.iter().all(|(_, v)| v.is_flat()),
+            _ => true,
+        };
+        let newline = |f: &mut fmt::Formatter<'_>, indent: usize| -> fmt::Result {
+            if inline {
+                Ok(())
+            } else {
+                write!(f, "\n{:1$}", "", indent * 2)
+            }
+        };
+        match self {
+            Json::Null => f.write_str("null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Int(n) => write!(f, "{}", n),
+            Json::Str(s) => write_string(f, s),
+            Json::Array(items) if items.is_empty() => f.write_str("[]"),
+            Json::Array(items) => {
+                f.write_str("[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(if inline { ", " } else { "," })?;
+                    }
+                    newline(f, indent + 1)?;
+                    item.write(f, indent + 1)?;
+                }
+                newline(f, indent)?;
+                f.write_str("]")
+            }
+            Json::Object(fields) if fields.is_empty() => f.write_str("{}"),
+            Json::Object(fields) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(if inline { ", " } else { "," })?;
+                    }
+                    newline(f, indent + 1)?;
+                    write_string(f, key)?;
+                    f.write_str(": ")?;
+                    value.write(f, indent + 1)?;
+                }
+                newline(f, indent)?;
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    f.write_str("\"")
+}
+
+/// Indented JSON text.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+struct JsonParser<'t> {
+    text: &'t str,
+    pos: usize,
+}
+
+impl JsonParser<'_> {
+    fn error(&self, msg: &str) -> JsonError {
+        JsonError(msg.to_string(), self.pos)
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ') | Some(b'\t') | Some(b'\n') | Some(b'\r') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, word: &str) -> Result<(), JsonError> {
+        if self.text[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", word)))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => Err(self.error("Unexpected end of JSON")),
+            Some(b'n') => self.expect("null").map(|_| Json::Null),
+            Some(b't') => self.expect("true").map(|_| Json::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| Json::Bool(false)),
+            Some(b'"') => self.string().map(Json::Str),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(b']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Json::Array(items));
+                        }
+                        _ => return Err(self.error("Expected ',' or ']'")),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.peek() == Some(b'}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.peek() != Some(b'"') {
+                        return Err(self.error("Expected a string key"));
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(":")?;
+                    fields.push((key, self.value()?));
+                    self.skip_whitespace();
+                    match self.peek() {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Json::Object(fields));
+                        }
+                        _ => return Err(self.error("Expected ',' or '}'")),
+                    }
+                }
+            }
+            Some(b'-') | Some(b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("Unexpected character")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, JsonError> {
+        let start = self.pos;
+        if self.peek() == Some(b'-') {
+            self.pos += 1;
+        }
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.pos += 1;
+        }
+        if let Some(b'.') | Some(b'e') | Some(b'E') = self.peek() {
+            return Err(self.error("Only integers are supported"));
+        }
+        self.text[start..self.pos]
+            .parse()
+            .map(Json::Int)
+            .map_err(|_| JsonError("Invalid integer".to_string(), start))
+    }
+
+    fn hex4(&mut self) -> Result<u32, JsonError> {
+        let digits = self
+            .text
+            .get(self.pos..self.pos + 4)
+            .ok_or_else(|| self.error("Expected 4 hex digits"))?;
+        let val =
+            u32::from_str_radix(digits, 16).map_err(|_| self.error("Expected 4 hex digits"))?;
+        self.pos += 4;
+        Ok(val)
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let c = match self.text[self.pos..].chars().next() {
+                None => return Err(self.error("Unterminated string")),
+                Some(c) => c,
+            };
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Ok(out),
+                '\\' => {
+                    let escape = self
+                        .peek()
+                        .ok_or_else(|| self.error("Unterminated string"))?;
+                    self.pos += 1;
+                    match escape {
+                        b'"' => out.push('"'),
+                        b'\\' => out.push('\\'),
+                        b'/' => out.push('/'),
+                        b'b' => out.push('\u{8}'),
+                        b'f' => out.push('\u{c}'),
+                        b'n' => out.push('\n'),
+                        b'r' => out.push('\r'),
+                        b't' => out.push('\t'),
+                        b'u' => {
+                            let mut code = self.hex4()?;
+                            if (0xd800..0xdc00).contains(&code) {
+                                self.expect("\\u")?;
+                                let low = self.hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.error("Invalid surrogate pair"));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            out.push(
+                                char::from_u32(code)
+                                    .ok_or_else(|| self.error("Invalid \\u escape"))?,
+                            );
+                        }
+                        _ => return Err(self.error("Invalid escape")),
+                    }
+                }
+                c if (c as u32) < 0x20 => return Err(self.error("Control character in string")),
+                c => out.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_print() {
+        let text =
+            r#" {"a": [1, -2, true, null], "b": {"c": "x\"\n\u00e9\ud834\udd1e"}, "d": []} "#;
+        let value = Json::parse(text).unwrap();
+        assert_eq!(
+            value.get("a"),
+            Some(&Json::Array(vec![
+                Json::Int(1),
+                Json::Int(-2),
+                Json::Bool(true),
+                Json::Null
+            ]))
+        );
+        assert_eq!(
+            value.get("b").and_then(|b| b.get("c")),
+            Some(&Json::Str("x\"\né𝄞".to_string()))
+        );
+        let printed = value.to_string();
+        assert_eq!(
+            printed,
+            "{\n  \"a\": [1, -2, true, null],\n  \"b\": {\"c\": \"x\\\"\\né𝄞\"},\n  \"d\": []\n}"
+        );
+        assert_eq!(Json::parse(&printed), Ok(value));
+    }
+
+    #[test]
+    fn errors() {
+        let err = |text| Json::parse(text).unwrap_err();
+        assert_eq!(
+            err("[1, 2"),
+            JsonError("Expected ',' or ']'".to_string(), 5)
+        );
+        assert_eq!(
+            err("1.5"),
+            JsonError("Only integers are supported".to_string(), 1)
+        );
+        assert_eq!(
+            err("{1: 2}"),
+            JsonError("Expected a string key".to_string(), 1)
+        );
+        assert_eq!(
+            err("\"abc"),
+            JsonError("Unterminated string".to_string(), 4)
+        );
+        assert_eq!(
+            err("[] x"),
+            JsonError("Trailing characters after JSON value".to_string(), 3)
+        );
+        assert_eq!(
+            err("99999999999999999999"),
+            JsonError("Invalid integer".to_string(), 0)
+        );
+    }
+}
@@ -4,10 +4,41 @@ use ast::*;
 use regex::Regex;
 use std::mem::size_of;
 
+mod arena;
 mod ast;
+mod dot;
+mod dump;
+mod fmt;
+mod intern;
+mod json;
+mod opt;
 mod parser;
+mod pretty;
+mod source;
+mod ssm;
+mod visit;
 
 fn main() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    let width = match int_width(&mut args) {
+        Ok(width) => width,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            std::process::exit(2);
+        }
+    };
+    if args.first().map(String::as_str) == Some("fmt") {
+        std::process::exit(fmt::main(&args[1..], width));
+    }
+    if args.first().map(String::as_str) == Some("--emit") {
+        std::process::exit(emit(&args[1..], width));
+    }
+    if args.first().map(String::as_str) == Some("peephole") {
+        std::process::exit(peephole(&args[1..], width));
+    }
+    if args.first().map(String::as_str) == Some("measure") {
+        std::process::exit(measure(args.get(1), width));
+    }
     println!("Hello, world!");
     let re = Regex::new(r"^\d{4}-\d{2}-\d{2}$").unwrap();
     println!("{}", re.is_match("2014-01-01"));
@@ -34,4 +65,167 @@ fn main() {
         size_of::<Option<Box<&str>>>(),
         size_of::<Type>()
     );
+    println!(
+        "arena: {}, {}, {}, {}",
+        size_of::<arena::DeclNode>(),
+        size_of::<arena::StmtNode>(),
+        size_of::<arena::ExpNode>(),
+        size_of::<arena::TypeNode>()
+    );
+}
+
+/// Remove `--int-width 32|64` from `args`, which sets the width of `Int`
+/// for every command. It is 64 bits by default.
+fn int_width(args: &mut Vec<String>) -> Result<IntWidth, String> {
+    let pos = match args.iter().position(|arg| arg == "--int-width") {
+        Some(pos) => pos,
+        None => return Ok(IntWidth::W64),
+    };
+    let bits = args
+        .get(pos + 1)
+        .ok_or("--int-width needs a value, 32 or 64")?;
+    let width = bits.parse()?;
+    args.drain(pos..pos + 2);
+    Ok(width)
+}
+
+/// `spl-compile --emit KIND FILE`: print the AST of `FILE` in one of the
+/// dump formats, `ast-json`, `ast-sexp` or `ast-dot`, or as the control-flow
+/// graphs of its functions with `cfg-dot`.
+fn emit(args: &[String], width: IntWidth) -> i32 {
+    let usage = "usage: spl-compile --emit ast-json|ast-sexp|ast-dot|cfg-dot FILE";
+    let (kind, path) = match args {
+        [kind, path] => (kind.as_str(), path),
+        _ => {
+            eprintln!("{}", usage);
+            return 2;
+        }
+    };
+    if !["ast-json", "ast-sexp", "ast-dot", "cfg-dot"].contains(&kind) {
+        eprintln!("{}", usage);
+        return 2;
+    }
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            return 2;
+        }
+    };
+    let mut map = source::SourceMap::new();
+    let file = map.add(path.clone(), source);
+    let mut parser = parser::Parser::for_file(&map, file, width);
+    let spl = match parser.spl() {
+        Ok(spl) => spl,
+        Err(parser::ParseError(msg, Some(loc))) => {
+            eprintln!("{}: {}", map.describe(loc.into()), msg);
+            return 2;
+        }
+        Err(parser::ParseError(msg, None)) => {
+            eprintln!("{}: {}", map.name(file), msg);
+            return 2;
+        }
+    };
+    match kind {
+        "ast-json" => println!("{}", dump::to_json(&spl)),
+        "ast-sexp" => println!("{}", dump::to_sexp(&spl)),
+        "ast-dot" => print!("{}", dot::ast_dot(&spl)),
+        _ => print!("{}", dot::cfg_dot(&spl)),
+    }
+    0
+}
+
+/// `spl-compile peephole [--stats] FILE`: print the SSM assembly in `FILE`
+/// after the peephole optimiser, and with `--stats` the instruction counts
+/// before and after on stderr.
+fn peephole(args: &[String], width: IntWidth) -> i32 {
+    let stats = args.iter().any(|arg| arg == "--stats");
+    let path = match args
+        .iter()
+        .filter(|&arg| arg != "--stats")
+        .collect::<Vec<_>>()[..]
+    {
+        [path] => path,
+        _ => {
+            eprintln!("usage: spl-compile peephole [--stats] FILE");
+            return 2;
+        }
+    };
+    let code = match std::fs::read_to_string(path) {
+        Ok(text) => match ssm::parse(&text) {
+            Ok(code) => code,
+            Err((line, msg)) => {
+                eprintln!("{}:{}: {}", path, line, msg);
+                return 2;
+            }
+        },
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            return 2;
+        }
+    };
+    let (code, counts) = ssm::peephole::run(code, width);
+    for instr in code {
+        println!("{}", instr);
+    }
+    if stats {
+        eprintln!("{}", counts);
+    }
+    0
+}
+
+/// `spl-compile measure [FILE]`: compare the tree and arena layouts of the
+/// AST of `FILE`, or of a generated program.
+fn measure(path: Option<&String>, width: IntWidth) -> i32 {
+    let source = match path {
+        Some(path) => match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("{}: {}", path, err);
+                return 2;
+            }
+        },
+        None => (0..2000)
+            .map(|i| {
+                format!(
+                    "f{0}(x, l) :: Int [Int] -> Int {{ var y = x * {0} + l.hd; \
+                     if (y < 10 && !(x == 0)) {{ y = f{0}(y - 1, l.tl); }} \
+                     while (y > 0) {{ y = y / 2 - (x % 3); }} return y; }}\n",
+                    i
+                )
+            })
+            .collect(),
+    };
+    let mut map = source::SourceMap::new();
+    let file = map.add(
+        path.map_or("<generated>".to_string(), String::clone),
+        source,
+    );
+    let spl = match parser::Parser::for_file(&map, file, width).spl() {
+        Ok(spl) => spl,
+        Err(parser::ParseError(msg, Some(loc))) => {
+            eprintln!("{}: {}", map.describe(loc.into()), msg);
+            return 2;
+        }
+        Err(parser::ParseError(msg, None)) => {
+            eprintln!("{}: {}", map.name(file), msg);
+            return 2;
+        }
+    };
+    println!(
+        "sizes: Decl {}, Stmt {}, Exp {}, Type {}",
+        size_of::<Decl>(),
+        size_of::<Stmt>(),
+        size_of::<Exp>(),
+        size_of::<Type>()
+    );
+    println!(
+        "arena: DeclNode {}, StmtNode {}, ExpNode {}, TypeNode {}",
+        size_of::<arena::DeclNode>(),
+        size_of::<arena::StmtNode>(),
+        size_of::<arena::ExpNode>(),
+        size_of::<arena::TypeNode>()
+    );
+    println!("{}", arena::measure(&spl, 20));
+    0
 }
@@ -0,0 +1,125 @@
+use crate::ast::*;
+use std::collections::{HashMap, HashSet};
+
+/// Which functions call which, by name.
+pub struct CallGraph {
+    calls: HashMap<BareId, HashSet<BareId>>,
+    /// Functions called from the initialisers of globals.
+    init_calls: HashSet<BareId>,
+}
+
+pub(crate) fn exp_calls(exp: &Exp, calls: &mut HashSet<BareId>) {
+    use BareExp::*;
+    match &(exp.0).0 {
+        Var(..) | Lit(_) => (),
+        Call(id, args) => {
+            calls.insert(id.0);
+            args.iter().for_each(|arg| exp_calls(arg, calls));
+        }
+        Tuple(coords) => coords.iter().for_each(|coord| exp_calls(coord, calls)),
+        BinOp(_, lhs, rhs) => {
+            exp_calls(lhs, calls);
+            exp_calls(rhs, calls);
+        }
+        UnOp(_, arg) => exp_calls(arg, calls),
+    }
+}
+
+pub(crate) fn stmt_calls(stmts: &[Stmt], calls: &mut HashSet<BareId>) {
+    use BareStmt::*;
+    for (stmt, _) in stmts {
+        match stmt {
+            ITE(cond, then, els) => {
+                exp_calls(cond, calls);
+                stmt_calls(then, calls);
+                stmt_calls(els, calls);
+            }
+            While(cond, body) => {
+                exp_calls(cond, calls);
+                stmt_calls(body, calls);
+            }
+            Assign(_, exp) | Local((_, _, exp)) | Ret(Some(exp)) => exp_calls(exp, calls),
+            Call(id, args) => {
+                calls.insert(id.0);
+                args.iter().for_each(|arg| exp_calls(arg, calls));
+            }
+            Ret(None) => (),
+        }
+    }
+}
+
+impl CallGraph {
+    pub fn new(spl: &SPL) -> Self {
+        let mut graph = CallGraph {
+            calls: HashMap::new(),
+            init_calls: HashSet::new(),
+        };
+        for (decl, _) in spl {
+            match decl {
+                BareDecl::Global((_, _, exp)) => exp_calls(exp, &mut graph.init_calls),
+                BareDecl::Fun(id, _, _, body) => {
+                    let callees = graph.calls.entry(id.0).or_default();
+                    stmt_calls(body, callees);
+                }
+            }
+        }
+        graph
+    }
+
+    pub fn callees(&self, fun: BareId) -> impl Iterator<Item = BareId> + '_ {
+        self.calls.get(&fun).into_iter().flatten().copied()
+    }
+
+    /// All functions transitively called from `roots`, including the roots.
+    pub fn reachable<I: IntoIterator<Item = BareId>>(&self, roots: I) -> HashSet<BareId> {
+        let mut seen = HashSet::new();
+        let mut todo: Vec<BareId> = roots.into_iter().collect();
+        while let Some(fun) = todo.pop() {
+            if seen.insert(fun) {
+                todo.extend(self.callees(fun));
+            }
+        }
+        seen
+    }
+
+    /// All functions that can run in a program starting at `main`.
+    pub fn live(&self, main: BareId) -> HashSet<BareId> {
+        self.reachable(std::iter::once(main).chain(self.init_calls.iter().copied()))
+    }
+
+    /// Whether `fun` can (indirectly) call itself.
+    pub fn is_recursive(&self, fun: BareId) -> bool {
+        self.reachable(self.callees(fun)).contains(&fun)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::test_util::{call, fun, id, stmt};
+
+    #[test]
+    fn reachability_and_recursion() {
+        let calls = |name| stmt(BareStmt::Call(id(name), Vec::new()));
+        let spl = vec![
+            fun("main", &[], vec![calls("even")]),
+            fun("even", &[], vec![calls("odd")]),
+            fun("odd", &[], vec![calls("even")]),
+            fun("init", &[], Vec::new()),
+            fun("spin", &[], vec![calls("spin")]),
+            (
+                BareDecl::Global((None, id("g"), call("init", Vec::new()))),
+                None,
+            ),
+        ];
+        let graph = CallGraph::new(&spl);
+        let id = BareId::intern;
+        let live = graph.live(id("main"));
+        let expected = ["main", "even", "odd", "init"];
+        assert_eq!(live, expected.iter().map(|name| id(name)).collect());
+        assert!(!graph.is_recursive(id("main")));
+        assert!(graph.is_recursive(id("even")));
+        assert!(graph.is_recursive(id("odd")));
+        assert!(graph.is_recursive(id("spin")));
+    }
+}
@@ -0,0 +1,327 @@
+use super::callgraph::CallGraph;
+use super::{count_uses, diverges, is_pure, lit_of, stmts_span, Warning};
+use crate::ast::BareStmt::*;
+use crate::ast::LitVal::*;
+use crate::ast::*;
+use std::collections::HashMap;
+
+/// Dead code and unreachable branch elimination.
+///
+/// Meant to run after constant folding, so that constant conditions are
+/// literals by the time this pass sees them. Every removal is reported as a
+/// warning pointing at the removed code; whether to show those is up to the
+/// caller.
+pub struct DeadCodeEliminator {
+    main: Option<BareId>,
+    warnings: Vec<Warning>,
+}
+
+impl DeadCodeEliminator {
+    /// Functions unreachable from `main` are only removed if `main` is given.
+    pub fn new(main: Option<BareId>) -> Self {
+        DeadCodeEliminator {
+            main,
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn run(mut self, spl: SPL) -> (SPL, Vec<Warning>) {
+        let spl: SPL = spl.into_iter().map(|decl| self.decl(decl)).collect();
+        let spl = match self.main {
+            None => spl,
+            Some(main) => {
+                let live = CallGraph::new(&spl).live(main);
+                let warnings = &mut self.warnings;
+                spl.into_iter()
+                    .filter(|(decl, span)| match decl {
+                        BareDecl::Fun(id, ..) if !live.contains(&id.0) => {
+                            warnings.push(Warning("Function is never called".to_string(), *span));
+                            false
+                        }
+                        _ => true,
+                    })
+                    .collect()
+            }
+        };
+        (spl, self.warnings)
+    }
+
+    fn warn(&mut self, msg: &str, span: Option<Span>) {
+        self.warnings.push(Warning(msg.to_string(), span));
+    }
+
+    fn decl(&mut self, (decl, span): Decl) -> Decl {
+        match decl {
+            BareDecl::Fun(id, args, typ, body) => {
+                let body = self.stmts(body);
+                (BareDecl::Fun(id, args, typ, self.unused_locals(body)), span)
+            }
+            global => (global, span),
+        }
+    }
+
+    fn stmts(&mut self, stmts: Vec<Stmt>) -> Vec<Stmt> {
+        let mut live = Vec::with_capacity(stmts.len());
+        let mut rest = stmts.into_iter();
+        while let Some(stmt) = rest.next() {
+            self.stmt(stmt, &mut live);
+            if live.last().is_some_and(diverges) {
+                let dead: Vec<Stmt> = rest.collect();
+                if !dead.is_empty() {
+                    self.warn("Unreachable code", stmts_span(&dead));
+                }
+                break;
+            }
+        }
+        live
+    }
+
+    /// Push what remains of `stmt` onto `live`.
+    fn stmt(&mut self, (stmt, span): Stmt, live: &mut Vec<Stmt>) {
+        match stmt {
+            ITE(cond, then, els) => {
+                let then = self.stmts(then);
+                let els = self.stmts(els);
+                match lit_of(&cond) {
+                    Some(Bool(taken)) => {
+                        let (kept, dead) = if taken { (then, els) } else { (els, then) };
+                        if !dead.is_empty() {
+                            self.warn("Branch is never taken", stmts_span(&dead));
+                        }
+                        // Only splice the branch into the enclosing block if
+                        // that cannot move a local into a different scope.
+                        if kept.iter().any(|(stmt, _)| matches!(stmt, Local(_))) {
+                            let cond = ((BareExp::Lit(Bool(true)), (cond.0).1), cond.1);
+                            live.push((ITE(cond, kept, Vec::new()), span));
+                        } else {
+                            live.extend(kept);
+                        }
+                    }
+                    _ => live.push((ITE(cond, then, els), span)),
+                }
+            }
+            While(cond, body) => {
+                if lit_of(&cond) == Some(Bool(false)) {
+                    self.warn("Loop body never runs", span);
+                } else {
+                    live.push((While(cond, self.stmts(body)), span));
+                }
+            }
+            stmt => live.push((stmt, span)),
+        }
+    }
+
+    /// Remove locals that are never used and have initialisers without side
+    /// effects, until no more can be removed.
+    fn unused_locals(&mut self, mut body: Vec<Stmt>) -> Vec<Stmt> {
+        loop {
+            let mut uses = HashMap::new();
+            count_uses(&body, &mut uses);
+            let before = self.warnings.len();
+            body = self.drop_unused(body, &uses);
+            if self.warnings.len() == before {
+                break body;
+            }
+        }
+    }
+
+    fn drop_unused(&mut self, stmts: Vec<Stmt>, uses: &HashMap<BareId, u32>) -> Vec<Stmt> {
+        let mut kept = Vec::with_capacity(stmts.len());
+        for (stmt, span) in stmts {
+            let stmt = match stmt {
+                Local((_, id, ref exp)) if !uses.contains_key(&id.0) && is_pure(exp) => {
+                    self.warn("Unused local variable", span);
+                    continue;
+                }
+                ITE(cond, then, els) => ITE(
+                    cond,
+                    self.drop_unused(then, uses),
+                    self.drop_unused(els, uses),
+                ),
+                While(cond, body) => While(cond, self.drop_unused(body, uses)),
+                stmt => stmt,
+            };
+            kept.push((stmt, span));
+        }
+        kept
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::test_util::{at, bin, body, call, fun, id, lit, local, stmt, var};
+    use crate::source::FileId;
+    use BareDecl::*;
+
+    /// A span per line, for lines of ten bytes.
+    fn tspan(line: u32) -> Option<Span> {
+        Some(Span::new(FileId(0), 10 * line, 10 * line + 1))
+    }
+
+    fn stmt_at(bare: BareStmt, line: u32) -> Stmt {
+        at(stmt(bare), tspan(line))
+    }
+
+    fn local_at(name: &str, exp: Exp, line: u32) -> Stmt {
+        at(local(name, exp), tspan(line))
+    }
+
+    fn fun_at(name: &str, line: u32, body: Vec<Stmt>) -> Decl {
+        at(fun(name, &[], body), tspan(line))
+    }
+
+    fn run(body: Vec<Stmt>) -> (Vec<Stmt>, Vec<Warning>) {
+        let (mut spl, warnings) = DeadCodeEliminator::new(None).run(vec![fun_at("f", 100, body)]);
+        match spl.pop().unwrap().0 {
+            Fun(_, _, _, body) => (body, warnings),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn constant_ite() {
+        let (body, warnings) = run(vec![stmt_at(
+            ITE(
+                lit(Bool(true)),
+                vec![stmt_at(Ret(None), 1)],
+                vec![stmt_at(Call(id("g"), Vec::new()), 2)],
+            ),
+            0,
+        )]);
+        assert_eq!(body, vec![stmt_at(Ret(None), 1)]);
+        assert_eq!(
+            warnings,
+            vec![Warning("Branch is never taken".to_string(), tspan(2))]
+        );
+    }
+
+    #[test]
+    fn constant_ite_keeps_scope_of_locals() {
+        let (body, _) = run(vec![stmt_at(
+            ITE(
+                lit(Bool(false)),
+                Vec::new(),
+                vec![local_at("x", call("g", Vec::new()), 1)],
+            ),
+            0,
+        )]);
+        assert_eq!(
+            body,
+            vec![stmt_at(
+                ITE(
+                    lit(Bool(true)),
+                    vec![local_at("x", call("g", Vec::new()), 1)],
+                    Vec::new()
+                ),
+                0
+            )]
+        );
+    }
+
+    #[test]
+    fn while_false() {
+        let (body, warnings) = run(vec![
+            stmt_at(While(lit(Bool(false)), vec![stmt_at(Ret(None), 1)]), 0),
+            stmt_at(Ret(None), 2),
+        ]);
+        assert_eq!(body, vec![stmt_at(Ret(None), 2)]);
+        assert_eq!(
+            warnings,
+            vec![Warning("Loop body never runs".to_string(), tspan(0))]
+        );
+    }
+
+    #[test]
+    fn after_return() {
+        let (body, warnings) = run(vec![
+            stmt_at(
+                ITE(
+                    var("b"),
+                    vec![stmt_at(Ret(Some(lit(Int(1)))), 1)],
+                    vec![stmt_at(Ret(Some(lit(Int(2)))), 2)],
+                ),
+                0,
+            ),
+            stmt_at(Call(id("g"), Vec::new()), 3),
+            stmt_at(Ret(None), 4),
+        ]);
+        assert_eq!(body.len(), 1);
+        assert_eq!(
+            warnings,
+            vec![Warning(
+                "Unreachable code".to_string(),
+                Some(Span::new(FileId(0), 30, 41))
+            )]
+        );
+    }
+
+    #[test]
+    fn unused_locals() {
+        let (body, warnings) = run(vec![
+            local_at("a", lit(Int(1)), 0),
+            local_at("b", var("a"), 1),
+            local_at("c", call("g", Vec::new()), 2),
+            local_at("e", lit(Int(5)), 3),
+            stmt_at(Ret(Some(var("e"))), 4),
+        ]);
+        assert_eq!(
+            body,
+            vec![
+                local_at("c", call("g", Vec::new()), 2),
+                local_at("e", lit(Int(5)), 3),
+                stmt_at(Ret(Some(var("e"))), 4),
+            ]
+        );
+        assert_eq!(warnings.len(), 2);
+    }
+
+    #[test]
+    fn unused_locals_that_can_fail() {
+        let field = |name, field| ((BareExp::Var(id(name), vec![(field, None)]), None), None);
+        let div = |op, rhs| bin(op, var("a"), rhs);
+        let failing = vec![
+            local_at("h", field("xs", BareSelector::Hd), 0),
+            local_at("t", field("xs", BareSelector::Tl), 1),
+            local_at("q", div(BareOp::Div, lit(Int(0))), 2),
+            local_at("r", div(BareOp::Mod, var("b")), 3),
+        ];
+        let (body, warnings) = run(failing.clone());
+        assert_eq!(body, failing);
+        assert!(warnings.is_empty());
+        let (body, warnings) = run(vec![
+            local_at("f", field("p", BareSelector::Fst), 0),
+            local_at("q", div(BareOp::Div, lit(Int(2))), 1),
+        ]);
+        assert!(body.is_empty());
+        assert_eq!(warnings.len(), 2);
+    }
+
+    #[test]
+    fn unreachable_functions() {
+        let main = fun_at("main", 100, vec![stmt_at(Call(id("f"), Vec::new()), 0)]);
+        let global = (Global((None, id("x"), call("g", Vec::new()))), None);
+        let spl = vec![
+            main,
+            fun_at("f", 101, Vec::new()),
+            fun_at("g", 102, Vec::new()),
+            fun_at("h", 103, Vec::new()),
+            global,
+        ];
+        let main = Some(BareId::intern("main"));
+        let (spl, warnings) = DeadCodeEliminator::new(main).run(spl);
+        assert_eq!(spl.len(), 4);
+        assert_eq!(body(&spl[0]).len(), 1);
+        assert_eq!(
+            warnings,
+            vec![Warning("Function is never called".to_string(), tspan(103))]
+        );
+    }
+
+    #[test]
+    fn no_main_keeps_functions() {
+        let (spl, warnings) = DeadCodeEliminator::new(None).run(vec![fun_at("f", 101, Vec::new())]);
+        assert_eq!(spl.len(), 1);
+        assert!(warnings.is_empty());
+    }
+}
@@ -0,0 +1,333 @@
+use super::{lit_of, Warning};
+use crate::ast::BareExp::*;
+use crate::ast::BareOp::*;
+use crate::ast::LitVal::*;
+use crate::ast::*;
+
+/// Constant folding and algebraic simplification of expressions.
+///
+/// Integer arithmetic wraps around on overflow (two's complement at the
+/// configured `IntWidth`), and both overflow and division by zero are reported
+/// as warnings. Divisions by zero are left in place, so they still fail at run
+/// time. Rewritten expressions keep the span of the expression they replace.
+pub struct ConstFolder {
+    width: IntWidth,
+    warnings: Vec<Warning>,
+}
+
+/// Put `exp` in the place of an expression with type `typ` and span `span`.
+fn replace(((exp, inner_typ), _): Exp, typ: Option<Type>, span: Option<Span>) -> Exp {
+    ((exp, typ.or(inner_typ)), span)
+}
+
+fn compare<T: PartialOrd>(op: BareOp, lhs: T, rhs: T) -> Option<LitVal> {
+    Some(Bool(match op {
+        Lt => lhs < rhs,
+        Leq => lhs <= rhs,
+        Gt => lhs > rhs,
+        Geq => lhs >= rhs,
+        Eq => lhs == rhs,
+        Neq => lhs != rhs,
+        _ => return None,
+    }))
+}
+
+impl ConstFolder {
+    pub fn new(width: IntWidth) -> Self {
+        ConstFolder {
+            width,
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn run(mut self, spl: SPL) -> (SPL, Vec<Warning>) {
+        let spl = spl.into_iter().map(|decl| self.decl(decl)).collect();
+        (spl, self.warnings)
+    }
+
+    fn warn(&mut self, msg: String, span: Option<Span>) {
+        self.warnings.push(Warning(msg, span));
+    }
+
+    fn decl(&mut self, (decl, span): Decl) -> Decl {
+        use BareDecl::*;
+        let decl = match decl {
+            Global(var) => Global(self.var_decl(var)),
+            Fun(id, args, typ, body) => Fun(id, args, typ, self.stmts(body)),
+        };
+        (decl, span)
+    }
+
+    fn var_decl(&mut self, (typ, id, exp): VarDecl) -> VarDecl {
+        (typ, id, self.exp(exp))
+    }
+
+    fn stmts(&mut self, stmts: Vec<Stmt>) -> Vec<Stmt> {
+        stmts.into_iter().map(|stmt| self.stmt(stmt)).collect()
+    }
+
+    fn stmt(&mut self, (stmt, span): Stmt) -> Stmt {
+        use BareStmt::*;
+        let stmt = match stmt {
+            ITE(cond, then, els) => ITE(self.exp(cond), self.stmts(then), self.stmts(els)),
+            While(cond, body) => While(self.exp(cond), self.stmts(body)),
+            Assign(id, exp) => Assign(id, self.exp(exp)),
+            Call(id, args) => Call(id, self.exps(args)),
+            Ret(exp) => Ret(exp.map(|exp| self.exp(exp))),
+            Local(var) => Local(self.var_decl(var)),
+        };
+        (stmt, span)
+    }
+
+    fn exps(&mut self, exps: Vec<Exp>) -> Vec<Exp> {
+        exps.into_iter().map(|exp| self.exp(exp)).collect()
+    }
+
+    pub fn exp(&mut self, ((exp, typ), span): Exp) -> Exp {
+        match exp {
+            Call(id, args) => ((Call(id, self.exps(args)), typ), span),
+            Tuple(coords) => ((Tuple(self.exps(coords)), typ), span),
+            BinOp(op, lhs, rhs) => {
+                let lhs = self.exp(*lhs);
+                let rhs = self.exp(*rhs);
+                self.binop(op, lhs, rhs, typ, span)
+            }
+            UnOp(op, arg) => {
+                let arg = self.exp(*arg);
+                self.unop(op, arg, typ, span)
+            }
+            exp => ((exp, typ), span),
+        }
+    }
+
+    fn binop(&mut self, op: Op, lhs: Exp, rhs: Exp, typ: Option<Type>, span: Option<Span>) -> Exp {
+        let (lval, rval) = (lit_of(&lhs), lit_of(&rhs));
+        if let (Some(l), Some(r)) = (lval, rval) {
+            if let Some(val) = self.eval_binop(op.0, l, r, span) {
+                return ((Lit(val), typ), span);
+            }
+        }
+        match (op.0, lval, rval) {
+            (Plus, _, Some(Int(0)))
+            | (Minus, _, Some(Int(0)))
+            | (Mul, _, Some(Int(1)))
+            | (Div, _, Some(Int(1)))
+            | (And, _, Some(Bool(true)))
+            | (Or, _, Some(Bool(false)))
+            | (And, Some(Bool(false)), _)
+            | (Or, Some(Bool(true)), _) => replace(lhs, typ, span),
+            (Plus, Some(Int(0)), _)
+            | (Mul, Some(Int(1)), _)
+            | (And, Some(Bool(true)), _)
+            | (Or, Some(Bool(false)), _) => replace(rhs, typ, span),
+            _ => ((BinOp(op, Box::new(lhs), Box::new(rhs)), typ), span),
+        }
+    }
+
+    fn unop(&mut self, op: Op, arg: Exp, typ: Option<Type>, span: Option<Span>) -> Exp {
+        match (op.0, &(arg.0).0) {
+            (Neg, &Lit(Int(n))) => {
+                let val = self.arith(n.overflowing_neg(), span);
+                ((Lit(val), typ), span)
+            }
+            (Not, &Lit(Bool(b))) => ((Lit(Bool(!b)), typ), span),
+            (Neg, UnOp((Neg, _), _)) | (Not, UnOp((Not, _), _)) => match (arg.0).0 {
+                UnOp(_, inner) => replace(*inner, typ, span),
+                _ => unreachable!(),
+            },
+            _ => ((UnOp(op, Box::new(arg)), typ), span),
+        }
+    }
+
+    fn eval_binop(
+        &mut self,
+        op: BareOp,
+        lhs: LitVal,
+        rhs: LitVal,
+        span: Option<Span>,
+    ) -> Option<LitVal> {
+        match (lhs, rhs) {
+            (Int(l), Int(r)) => match op {
+                Plus => Some(self.arith(l.overflowing_add(r), span)),
+                Minus => Some(self.arith(l.overflowing_sub(r), span)),
+                Mul => Some(self.arith(l.overflowing_mul(r), span)),
+                Div | Mod if r == 0 => {
+                    self.warn("Division by zero in constant expression".to_string(), span);
+                    None
+                }
+                Div => Some(self.arith(l.overflowing_div(r), span)),
+                // The remainder is representable even where the quotient
+                // overflows: `i64::MIN % -1` is 0.
+                Mod => Some(Int(l.wrapping_rem(r))),
+                _ => compare(op, l, r),
+            },
+            (Char(l), Char(r)) => compare(op, l, r),
+            (Bool(l), Bool(r)) => match op {
+                And => Some(Bool(l && r)),
+                Or => Some(Bool(l || r)),
+                Eq => Some(Bool(l == r)),
+                Neq => Some(Bool(l != r)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn arith(&mut self, (val, overflow): (i64, bool), span: Option<Span>) -> LitVal {
+        let (val, truncated) = self.width.wrap(val);
+        if overflow || truncated {
+            self.warn(
+                format!("Integer overflow in constant expression, wraps to {}", val),
+                span,
+            );
+        }
+        Int(val)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::test_util::{at, bin, lit, un, var};
+    use crate::source::FileId;
+
+    fn tspan(start: u32, end: u32) -> Option<Span> {
+        Some(Span::new(FileId(0), start, end))
+    }
+
+    fn atom(exp: BareExp, span: Option<Span>) -> Exp {
+        ((exp, None), span)
+    }
+
+    fn int(n: i64, col: u32) -> Exp {
+        at(lit(Int(n)), tspan(col, col + 1))
+    }
+
+    fn var_at(name: &str, col: u32) -> Exp {
+        at(var(name), tspan(col, col + 1))
+    }
+
+    fn fold(exp: Exp) -> (Exp, Vec<Warning>) {
+        let mut folder = ConstFolder::new(IntWidth::W64);
+        let exp = folder.exp(exp);
+        (exp, folder.warnings)
+    }
+
+    #[test]
+    fn fold_arith() {
+        let (exp, warnings) = fold(bin(Plus, int(1, 0), bin(Mul, int(2, 2), int(3, 4))));
+        assert_eq!(exp, atom(Lit(Int(7)), tspan(0, 5)));
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn fold_neg_literal() {
+        let (exp, _) = fold(un(Neg, int(42, 1)));
+        assert_eq!((exp.0).0, Lit(Int(-42)));
+    }
+
+    #[test]
+    fn fold_overflow_wraps() {
+        let (exp, warnings) = fold(bin(Plus, int(i64::MAX, 0), int(1, 2)));
+        assert_eq!(exp, atom(Lit(Int(i64::MIN)), tspan(0, 3)));
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].1, tspan(0, 3));
+    }
+
+    #[test]
+    fn fold_overflow_32_bit() {
+        let mut folder = ConstFolder::new(IntWidth::W32);
+        let exp = folder.exp(bin(Plus, int(i32::MAX as i64, 0), int(1, 2)));
+        assert_eq!((exp.0).0, Lit(Int(i32::MIN as i64)));
+        let exp = folder.exp(bin(Div, int(i32::MIN as i64, 0), int(-1, 2)));
+        assert_eq!((exp.0).0, Lit(Int(i32::MIN as i64)));
+        assert_eq!(folder.warnings.len(), 2);
+    }
+
+    #[test]
+    fn division_by_zero_unfolded() {
+        let div = bin(Div, int(1, 0), int(0, 2));
+        let (exp, warnings) = fold(bin(Div, int(1, 0), int(0, 2)));
+        assert_eq!(exp, div);
+        assert_eq!(
+            warnings,
+            vec![Warning(
+                "Division by zero in constant expression".to_string(),
+                tspan(0, 3)
+            )]
+        );
+    }
+
+    #[test]
+    fn truncating_division() {
+        let eval = |op, l, r| (fold(bin(op, int(l, 0), int(r, 2))).0 .0).0;
+        assert_eq!(eval(Div, -7, 2), Lit(Int(-3)));
+        assert_eq!(eval(Mod, -7, 2), Lit(Int(-1)));
+        assert_eq!(eval(Mod, 7, -2), Lit(Int(1)));
+        assert_eq!(eval(Mod, i64::MIN, -1), Lit(Int(0)));
+        let (_, warnings) = fold(bin(Mod, int(1, 0), int(0, 2)));
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn fold_bool_and_char() {
+        let t = || atom(Lit(Bool(true)), tspan(0, 4));
+        let (exp, _) = fold(un(Not, bin(And, t(), atom(Lit(Bool(false)), tspan(8, 13)))));
+        assert_eq!((exp.0).0, Lit(Bool(true)));
+        let (exp, _) = fold(bin(
+            Lt,
+            atom(Lit(Char('a')), tspan(0, 3)),
+            atom(Lit(Char('b')), tspan(4, 7)),
+        ));
+        assert_eq!((exp.0).0, Lit(Bool(true)));
+    }
+
+    #[test]
+    fn identities_keep_outer_span() {
+        let (exp, _) = fold(bin(Mul, var_at("x", 0), int(1, 2)));
+        assert_eq!(exp, (((var_at("x", 0).0).0, None), tspan(0, 3)));
+        let (exp, _) = fold(bin(Plus, int(0, 0), var_at("x", 2)));
+        assert_eq!(exp, (((var_at("x", 2).0).0, None), tspan(0, 3)));
+        let (exp, _) = fold(un(Not, un(Not, var_at("x", 2))));
+        assert_eq!(exp, var_at("x", 2));
+    }
+
+    #[test]
+    fn short_circuit_rewrites() {
+        let b = || var_at("b", 8);
+        let t = atom(Lit(Bool(true)), tspan(0, 4));
+        let (exp, _) = fold(bin(And, t, b()));
+        assert_eq!((exp.0).0, (b().0).0);
+        let f = atom(Lit(Bool(false)), tspan(0, 5));
+        let (exp, _) = fold(bin(Or, f, b()));
+        assert_eq!((exp.0).0, (b().0).0);
+        let f = atom(Lit(Bool(false)), tspan(0, 5));
+        let call = atom(
+            Call((BareId::intern("f"), tspan(9, 10)), Vec::new()),
+            tspan(9, 12),
+        );
+        let (exp, _) = fold(bin(And, f, call));
+        assert_eq!(exp, atom(Lit(Bool(false)), tspan(0, 12)));
+    }
+
+    #[test]
+    fn fold_in_statements() {
+        use BareDecl::*;
+        use BareStmt::*;
+        let cond = bin(Lt, int(1, 6), int(2, 8));
+        let body = vec![(While(cond, Vec::new()), tspan(0, 12))];
+        let spl = vec![(
+            Fun((BareId::intern("main"), None), Vec::new(), None, body),
+            None,
+        )];
+        let (spl, warnings) = ConstFolder::new(IntWidth::W64).run(spl);
+        assert!(warnings.is_empty());
+        match &spl[0].0 {
+            Fun(_, _, _, body) => match &body[0].0 {
+                While(cond, _) => assert_eq!(*cond, atom(Lit(Bool(true)), tspan(6, 9))),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,785 @@
+use super::callgraph::CallGraph;
+use super::{bool_lit, count_exp_uses, count_uses, diverges, is_pure, not, stmts_span, var};
+use crate::ast::BareStmt::*;
+use crate::ast::*;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+
+/// Inlining of small, non-recursive functions.
+///
+/// A call inside an expression is inlined if the callee body is a single
+/// `return`, by substituting the (side effect free) arguments for the
+/// parameters. A call that makes up a whole statement (`f(..);`,
+/// `x = f(..);`, `var x = f(..);` or `return f(..);`) is replaced by the
+/// callee body instead, with the parameters bound to fresh locals and all
+/// locals of the callee renamed, so neither can capture the caller's
+/// variables. Early returns in such a body set a fresh flag that guards the
+/// rest of the body.
+///
+/// Only callees with at most `budget` AST nodes are inlined, measured before
+/// any inlining into them.
+pub struct Inliner {
+    budget: usize,
+    candidates: HashMap<BareId, Candidate>,
+}
+
+struct Candidate {
+    params: Vec<Id>,
+    body: Vec<Stmt>,
+    /// Variables used but not bound by the function: globals.
+    free: HashSet<BareId>,
+    /// Whether the body returns anywhere but in its last statement.
+    early: bool,
+}
+
+/// What to do with the result of a call that is inlined as statements.
+enum Target {
+    Discard,
+    Assign(Id),
+    Declare(Option<Type>, Id),
+    Return,
+}
+
+fn exp_size(exp: &Exp) -> usize {
+    use BareExp::*;
+    1 + match &(exp.0).0 {
+        Var(..) | Lit(_) => 0,
+        Call(_, args) | Tuple(args) => args.iter().map(exp_size).sum(),
+        BinOp(_, lhs, rhs) => exp_size(lhs) + exp_size(rhs),
+        UnOp(_, arg) => exp_size(arg),
+    }
+}
+
+/// The number of statement and expression nodes in `stmts`.
+fn size(stmts: &[Stmt]) -> usize {
+    stmts
+        .iter()
+        .map(|(stmt, _)| {
+            1 + match stmt {
+                ITE(cond, then, els) => exp_size(cond) + size(then) + size(els),
+                While(cond, body) => exp_size(cond) + size(body),
+                Assign(_, exp) | Local((_, _, exp)) | Ret(Some(exp)) => exp_size(exp),
+                Call(_, args) => args.iter().map(exp_size).sum(),
+                Ret(None) => 0,
+            }
+        })
+        .sum()
+}
+
+fn locals<E: Extend<BareId>>(stmts: &[Stmt], bound: &mut E) {
+    for (stmt, _) in stmts {
+        match stmt {
+            ITE(_, then, els) => {
+                locals(then, bound);
+                locals(els, bound);
+            }
+            While(_, body) => locals(body, bound),
+            Local((_, id, _)) => bound.extend(Some(id.0)),
+            _ => (),
+        }
+    }
+}
+
+fn may_return((stmt, _): &Stmt) -> bool {
+    match stmt {
+        Ret(_) => true,
+        ITE(_, then, els) => then.iter().any(may_return) || els.iter().any(may_return),
+        While(_, body) => body.iter().any(may_return),
+        _ => false,
+    }
+}
+
+/// Whether every way out of `stmts` returns a value: it ends in one and no
+/// `return;` comes before.
+fn returns_value(stmts: &[Stmt]) -> bool {
+    fn bare_return((stmt, _): &Stmt) -> bool {
+        match stmt {
+            Ret(None) => true,
+            ITE(_, then, els) => then.iter().chain(els).any(bare_return),
+            While(_, body) => body.iter().any(bare_return),
+            _ => false,
+        }
+    }
+    matches!(stmts.last(), Some((Ret(Some(_)), _))) && !stmts.iter().any(bare_return)
+}
+
+/// Whether `exp` contains a call, which may change globals.
+fn has_call(exp: &Exp) -> bool {
+    use BareExp::*;
+    match &(exp.0).0 {
+        Var(..) | Lit(_) => false,
+        Call(..) => true,
+        Tuple(coords) => coords.iter().any(has_call),
+        BinOp(_, lhs, rhs) => has_call(lhs) || has_call(rhs),
+        UnOp(_, arg) => has_call(arg),
+    }
+}
+
+fn ret_values<'a>(stmts: &'a [Stmt], values: &mut Vec<&'a Exp>) {
+    for (stmt, _) in stmts {
+        match stmt {
+            ITE(_, then, els) => {
+                ret_values(then, values);
+                ret_values(els, values);
+            }
+            While(_, body) => ret_values(body, values),
+            Ret(Some(exp)) => values.push(exp),
+            _ => (),
+        }
+    }
+}
+
+fn rename_id((id, span): Id, names: &HashMap<BareId, BareId>) -> Id {
+    (*names.get(&id).unwrap_or(&id), span)
+}
+
+fn rename_exp(((exp, typ), span): Exp, names: &HashMap<BareId, BareId>) -> Exp {
+    use BareExp::*;
+    let exp = match exp {
+        Var(id, fields) => Var(rename_id(id, names), fields),
+        Call(fun, args) => Call(fun, rename_exps(args, names)),
+        Tuple(coords) => Tuple(rename_exps(coords, names)),
+        BinOp(op, lhs, rhs) => BinOp(
+            op,
+            Box::new(rename_exp(*lhs, names)),
+            Box::new(rename_exp(*rhs, names)),
+        ),
+        UnOp(op, arg) => UnOp(op, Box::new(rename_exp(*arg, names))),
+        exp => exp,
+    };
+    ((exp, typ), span)
+}
+
+fn rename_exps(exps: Vec<Exp>, names: &HashMap<BareId, BareId>) -> Vec<Exp> {
+    exps.into_iter().map(|exp| rename_exp(exp, names)).collect()
+}
+
+fn rename(stmts: Vec<Stmt>, names: &HashMap<BareId, BareId>) -> Vec<Stmt> {
+    stmts
+        .into_iter()
+        .map(|(stmt, span)| {
+            let stmt = match stmt {
+                ITE(cond, then, els) => ITE(
+                    rename_exp(cond, names),
+                    rename(then, names),
+                    rename(els, names),
+                ),
+                While(cond, body) => While(rename_exp(cond, names), rename(body, names)),
+                Assign(id, exp) => Assign(rename_id(id, names), rename_exp(exp, names)),
+                Call(fun, args) => Call(fun, rename_exps(args, names)),
+                Ret(exp) => Ret(exp.map(|exp| rename_exp(exp, names))),
+                Local((typ, id, exp)) => Local((typ, rename_id(id, names), rename_exp(exp, names))),
+            };
+            (stmt, span)
+        })
+        .collect()
+}
+
+/// `exp` with every parameter replaced by its argument, if that can be
+/// expressed: a parameter with fields can only be replaced by a variable.
+fn substitute(((exp, typ), span): Exp, args: &HashMap<BareId, &Exp>) -> Option<Exp> {
+    use BareExp::*;
+    let exp = match exp {
+        Var(id, fields) => match args.get(&id.0) {
+            None => Var(id, fields),
+            Some(arg) if fields.is_empty() => return Some((*arg).clone()),
+            Some(arg) => match &(arg.0).0 {
+                Var(argid, argfields) => Var(*argid, [&argfields[..], &fields[..]].concat()),
+                _ => return None,
+            },
+        },
+        Call(fun, fargs) => Call(fun, substitute_all(fargs, args)?),
+        Tuple(coords) => Tuple(substitute_all(coords, args)?),
+        BinOp(op, lhs, rhs) => BinOp(
+            op,
+            Box::new(substitute(*lhs, args)?),
+            Box::new(substitute(*rhs, args)?),
+        ),
+        UnOp(op, arg) => UnOp(op, Box::new(substitute(*arg, args)?)),
+        exp => exp,
+    };
+    Some(((exp, typ), span))
+}
+
+fn substitute_all(exps: Vec<Exp>, args: &HashMap<BareId, &Exp>) -> Option<Vec<Exp>> {
+    exps.into_iter().map(|exp| substitute(exp, args)).collect()
+}
+
+impl Candidate {
+    fn new(params: &[Id], body: &[Stmt]) -> Self {
+        let mut uses = HashMap::new();
+        count_uses(body, &mut uses);
+        let mut bound: HashSet<BareId> = params.iter().map(|param| param.0).collect();
+        locals(body, &mut bound);
+        let early = match body.split_last() {
+            None => false,
+            Some(((Ret(_), _), init)) => init.iter().any(may_return),
+            Some(_) => body.iter().any(may_return),
+        };
+        Candidate {
+            params: params.to_vec(),
+            body: body.to_vec(),
+            free: uses.into_keys().filter(|id| !bound.contains(id)).collect(),
+            early,
+        }
+    }
+}
+
+impl Target {
+    /// The statement that hands `exp` to the caller, if any is needed.
+    fn finish(&self, exp: Exp, span: Option<Span>) -> Option<Stmt> {
+        let stmt = match self {
+            Target::Discard => match exp {
+                ((BareExp::Call(fun, args), _), _) => Call(fun, args),
+                _ => return None,
+            },
+            Target::Assign(id) => Assign(*id, exp),
+            Target::Declare(typ, id) => Local((typ.clone(), *id, exp)),
+            Target::Return => Ret(Some(exp)),
+        };
+        Some((stmt, span))
+    }
+
+    /// Rewrite the returns in `stmts` to hand over their value and set the
+    /// flag `done`, which guards everything that could run after them.
+    fn eliminate_returns(&self, stmts: Vec<Stmt>, done: BareId) -> Vec<Stmt> {
+        let mut out = Vec::with_capacity(stmts.len());
+        let mut rest = stmts.into_iter();
+        while let Some(stmt) = rest.next() {
+            let returns = may_return(&stmt);
+            match stmt {
+                (Ret(exp), span) => {
+                    out.extend(exp.and_then(|exp| self.finish(exp, span)));
+                    out.push((Assign((done, span), bool_lit(true, span)), span));
+                    break;
+                }
+                (ITE(cond, then, els), span) => out.push((
+                    ITE(
+                        cond,
+                        self.eliminate_returns(then, done),
+                        self.eliminate_returns(els, done),
+                    ),
+                    span,
+                )),
+                (While(cond, body), span) if returns => {
+                    let cond_span = cond.1;
+                    let guard = BareExp::BinOp(
+                        (BareOp::And, None),
+                        Box::new(not(var(done, cond_span))),
+                        Box::new(cond),
+                    );
+                    out.push((
+                        While(
+                            ((guard, None), cond_span),
+                            self.eliminate_returns(body, done),
+                        ),
+                        span,
+                    ));
+                }
+                stmt => out.push(stmt),
+            }
+            if returns {
+                let rest: Vec<Stmt> = rest.collect();
+                if !rest.is_empty() {
+                    let span = stmts_span(&rest);
+                    let rest = self.eliminate_returns(rest, done);
+                    out.push((ITE(not(var(done, span)), rest, Vec::new()), span));
+                }
+                break;
+            }
+        }
+        out
+    }
+}
+
+impl Inliner {
+    pub fn new(budget: usize) -> Self {
+        Inliner {
+            budget,
+            candidates: HashMap::new(),
+        }
+    }
+
+    pub fn run(mut self, spl: SPL) -> SPL {
+        let graph = CallGraph::new(&spl);
+        for (decl, _) in &spl {
+            if let BareDecl::Fun(id, params, _, body) = decl {
+                if size(body) <= self.budget && !graph.is_recursive(id.0) {
+                    self.candidates.insert(id.0, Candidate::new(params, body));
+                }
+            }
+        }
+        spl.into_iter().map(|decl| self.decl(decl)).collect()
+    }
+
+    fn decl(&mut self, (decl, span): Decl) -> Decl {
+        use BareDecl::*;
+        let decl = match decl {
+            Global((typ, id, exp)) => Global((typ, id, self.exp(exp, &HashSet::new()))),
+            Fun(id, params, typ, body) => {
+                let mut bound = params.iter().map(|param| param.0).collect();
+                locals(&body, &mut bound);
+                let body = self.stmts(body, &bound);
+                Fun(id, params, typ, body)
+            }
+        };
+        (decl, span)
+    }
+
+    fn stmts(&mut self, stmts: Vec<Stmt>, bound: &HashSet<BareId>) -> Vec<Stmt> {
+        let mut out = Vec::with_capacity(stmts.len());
+        for stmt in stmts {
+            self.stmt(stmt, bound, &mut out);
+        }
+        out
+    }
+
+    /// Push `stmt` onto `out`, after inlining the calls in it.
+    fn stmt(&mut self, (stmt, span): Stmt, bound: &HashSet<BareId>, out: &mut Vec<Stmt>) {
+        let (target, exp) = match stmt {
+            ITE(cond, then, els) => {
+                let cond = self.exp(cond, bound);
+                let stmt = ITE(cond, self.stmts(then, bound), self.stmts(els, bound));
+                return out.push((stmt, span));
+            }
+            While(cond, body) => {
+                let stmt = While(self.exp(cond, bound), self.stmts(body, bound));
+                return out.push((stmt, span));
+            }
+            Call(fun, args) => {
+                let args = self.exps(args, bound);
+                if self.fits(fun.0, &args, &Target::Discard, bound) {
+                    return self.inline_stmts(fun.0, args, Target::Discard, span, out);
+                }
+                return out.push((Call(fun, args), span));
+            }
+            Ret(None) => return out.push((Ret(None), span)),
+            Assign(id, exp) => (Target::Assign(id), exp),
+            Local((typ, id, exp)) => (Target::Declare(typ, id), exp),
+            Ret(Some(exp)) => (Target::Return, exp),
+        };
+        match self.exp(exp, bound) {
+            ((BareExp::Call(fun, args), _), _) if self.fits(fun.0, &args, &target, bound) => {
+                self.inline_stmts(fun.0, args, target, span, out)
+            }
+            exp => out.extend(target.finish(exp, span)),
+        }
+    }
+
+    /// Whether a call to `fun` with `args` can be inlined as statements.
+    fn fits(&self, fun: BareId, args: &[Exp], target: &Target, bound: &HashSet<BareId>) -> bool {
+        let cand = match self.candidates.get(&fun) {
+            Some(cand) => cand,
+            None => return false,
+        };
+        if cand.params.len() != args.len() || !cand.free.is_disjoint(bound) {
+            return false;
+        }
+        match target {
+            Target::Discard => {
+                let mut values = Vec::new();
+                ret_values(&cand.body, &mut values);
+                values
+                    .into_iter()
+                    .all(|exp| is_pure(exp) || matches!((exp.0).0, BareExp::Call(..)))
+            }
+            // Otherwise the value would be lost when control falls off the
+            // end of the body or reaches a `return;`.
+            Target::Assign(_) => returns_value(&cand.body),
+            Target::Declare(..) => !cand.early && returns_value(&cand.body),
+            Target::Return => cand.body.iter().any(diverges),
+        }
+    }
+
+    fn inline_stmts(
+        &mut self,
+        fun: BareId,
+        args: Vec<Exp>,
+        target: Target,
+        span: Option<Span>,
+        out: &mut Vec<Stmt>,
+    ) {
+        let cand = &self.candidates[&fun];
+        let (params, body, early) = (cand.params.clone(), cand.body.clone(), cand.early);
+        let mut names = HashMap::new();
+        for (param, arg) in params.into_iter().zip(args) {
+            let fresh = BareId::fresh(param.0.name());
+            names.insert(param.0, fresh);
+            out.push((Local((None, (fresh, param.1), arg)), span));
+        }
+        let mut bound = Vec::new();
+        locals(&body, &mut bound);
+        for local in bound {
+            if let Entry::Vacant(entry) = names.entry(local) {
+                entry.insert(BareId::fresh(local.name()));
+            }
+        }
+        let mut body = rename(body, &names);
+        match target {
+            Target::Return => out.extend(body),
+            _ if !early => match body.pop() {
+                Some((Ret(Some(exp)), ret_span)) => {
+                    out.extend(body);
+                    out.extend(target.finish(exp, ret_span));
+                }
+                Some((Ret(None), _)) => out.extend(body),
+                Some(last) => {
+                    out.extend(body);
+                    out.push(last);
+                }
+                None => (),
+            },
+            _ => {
+                let done = BareId::fresh("done");
+                out.push((Local((None, (done, span), bool_lit(false, span))), span));
+                out.extend(target.eliminate_returns(body, done));
+            }
+        }
+    }
+
+    fn exps(&mut self, exps: Vec<Exp>, bound: &HashSet<BareId>) -> Vec<Exp> {
+        exps.into_iter().map(|exp| self.exp(exp, bound)).collect()
+    }
+
+    fn exp(&mut self, ((exp, typ), span): Exp, bound: &HashSet<BareId>) -> Exp {
+        use BareExp::*;
+        let exp = match exp {
+            Call(fun, args) => {
+                let args = self.exps(args, bound);
+                match self.inline_exp(fun.0, &args, bound) {
+                    Some(((exp, inner_typ), _)) => return ((exp, typ.or(inner_typ)), span),
+                    None => Call(fun, args),
+                }
+            }
+            Tuple(coords) => Tuple(self.exps(coords, bound)),
+            BinOp(op, lhs, rhs) => BinOp(
+                op,
+                Box::new(self.exp(*lhs, bound)),
+                Box::new(self.exp(*rhs, bound)),
+            ),
+            UnOp(op, arg) => UnOp(op, Box::new(self.exp(*arg, bound))),
+            exp => exp,
+        };
+        ((exp, typ), span)
+    }
+
+    /// The body of `fun` with `args` substituted for its parameters, if the
+    /// body is a single `return` and the substitution keeps the meaning of the
+    /// call: arguments must be side effect free, and may only be duplicated or
+    /// dropped if they are variables or literals. If the body calls a
+    /// function, which may assign to globals, arguments must moreover be
+    /// literals or locals of the caller, since they are now evaluated after
+    /// that call rather than before. Calls that make up a statement are
+    /// still inlined by `inline_stmts`, which binds the arguments first.
+    fn inline_exp(&self, fun: BareId, args: &[Exp], bound: &HashSet<BareId>) -> Option<Exp> {
+        let cand = self.candidates.get(&fun)?;
+        let ret = match cand.body.as_slice() {
+            [(Ret(Some(exp)), _)] => exp,
+            _ => return None,
+        };
+        if cand.params.len() != args.len()
+            || !cand.free.is_disjoint(bound)
+            || !args.iter().all(is_pure)
+        {
+            return None;
+        }
+        if has_call(ret) {
+            let local = |arg: &Exp| match &(arg.0).0 {
+                BareExp::Lit(_) => true,
+                BareExp::Var(id, _) => bound.contains(&id.0),
+                _ => false,
+            };
+            if !args.iter().all(local) {
+                return None;
+            }
+        }
+        let mut uses = HashMap::new();
+        count_exp_uses(ret, &mut uses);
+        for (param, arg) in cand.params.iter().zip(args) {
+            let trivial = match &(arg.0).0 {
+                BareExp::Lit(_) => true,
+                BareExp::Var(_, fields) => fields.is_empty(),
+                _ => false,
+            };
+            if uses.get(&param.0) != Some(&1) && !trivial {
+                return None;
+            }
+        }
+        let args = cand.params.iter().map(|param| param.0).zip(args).collect();
+        substitute(ret.clone(), &args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::test_util::{bin, body, call, fun, id, lit, local, stmt, var};
+    use LitVal::*;
+
+    /// The name of the local declared by `stmt`, which must be fresh: made
+    /// from `base` and not `base` itself.
+    fn fresh_local(stmt: &Stmt, base: &str) -> &'static str {
+        match &stmt.0 {
+            Local((_, (id, _), _)) => {
+                let name = id.name();
+                assert!(name.starts_with(base) && name != base, "{}", name);
+                name
+            }
+            _ => panic!("{:?}", stmt),
+        }
+    }
+
+    #[test]
+    fn inline_expression_function() {
+        // isZero(x) { return x == 0; } main(n) { return isZero(n - 1); }
+        let is_zero = fun(
+            "isZero",
+            &["x"],
+            vec![stmt(Ret(Some(bin(BareOp::Eq, var("x"), lit(Int(0))))))],
+        );
+        let arg = bin(BareOp::Minus, var("n"), lit(Int(1)));
+        let main = fun(
+            "main",
+            &["n"],
+            vec![stmt(Ret(Some(call("isZero", vec![arg.clone()]))))],
+        );
+        let spl = Inliner::new(10).run(vec![is_zero, main]);
+        assert_eq!(
+            body(&spl[1]),
+            &vec![stmt(Ret(Some(bin(BareOp::Eq, arg, lit(Int(0))))))]
+        );
+    }
+
+    #[test]
+    fn keep_recursive_and_large() {
+        let f = fun(
+            "f",
+            &["x"],
+            vec![stmt(Ret(Some(call("f", vec![var("x")]))))],
+        );
+        let g = fun("g", &[], vec![stmt(Ret(Some(lit(Int(1)))))]);
+        let main = vec![
+            stmt(Call(id("f"), vec![lit(Int(1))])),
+            stmt(Ret(Some(call("g", Vec::new())))),
+        ];
+        let spl = vec![f, g, fun("main", &[], main.clone())];
+        let inlined = Inliner::new(10).run(spl.clone());
+        assert_eq!(body(&inlined[2])[0], main[0]);
+        assert_eq!(body(&inlined[2])[1], stmt(Ret(Some(lit(Int(1))))));
+        let inlined = Inliner::new(1).run(spl);
+        assert_eq!(body(&inlined[2]), &main);
+    }
+
+    #[test]
+    fn early_return_into_assignment() {
+        // f(a) { if (a) { return 1; } return 2; } main(b) { x = f(b); }
+        let f = fun(
+            "f",
+            &["a"],
+            vec![
+                stmt(ITE(
+                    var("a"),
+                    vec![stmt(Ret(Some(lit(Int(1)))))],
+                    Vec::new(),
+                )),
+                stmt(Ret(Some(lit(Int(2))))),
+            ],
+        );
+        let main = fun(
+            "main",
+            &["b"],
+            vec![stmt(Assign(id("x"), call("f", vec![var("b")])))],
+        );
+        let spl = Inliner::new(10).run(vec![f, main]);
+        let body = body(&spl[1]);
+        let a = fresh_local(&body[0], "a");
+        let done = fresh_local(&body[1], "done");
+        let set_done = || stmt(Assign(id(done), lit(Bool(true))));
+        assert_eq!(
+            body,
+            &vec![
+                local(a, var("b")),
+                local(done, lit(Bool(false))),
+                stmt(ITE(
+                    var(a),
+                    vec![stmt(Assign(id("x"), lit(Int(1)))), set_done()],
+                    Vec::new()
+                )),
+                stmt(ITE(
+                    not(var(done)),
+                    vec![stmt(Assign(id("x"), lit(Int(2)))), set_done()],
+                    Vec::new()
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn early_return_from_void_call() {
+        // f(a) { if (a) { return; } h(); } main() { f(True); }
+        let f = fun(
+            "f",
+            &["a"],
+            vec![
+                stmt(ITE(var("a"), vec![stmt(Ret(None))], Vec::new())),
+                stmt(Call(id("h"), Vec::new())),
+            ],
+        );
+        let main = fun(
+            "main",
+            &[],
+            vec![stmt(Call(id("f"), vec![lit(Bool(true))]))],
+        );
+        let spl = Inliner::new(10).run(vec![f, main]);
+        let body = body(&spl[1]);
+        let a = fresh_local(&body[0], "a");
+        let done = fresh_local(&body[1], "done");
+        assert_eq!(
+            body,
+            &vec![
+                local(a, lit(Bool(true))),
+                local(done, lit(Bool(false))),
+                stmt(ITE(
+                    var(a),
+                    vec![stmt(Assign(id(done), lit(Bool(true))))],
+                    Vec::new()
+                )),
+                stmt(ITE(
+                    not(var(done)),
+                    vec![stmt(Call(id("h"), Vec::new()))],
+                    Vec::new()
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn early_return_not_inlined_into_declaration() {
+        let f = fun(
+            "f",
+            &["a"],
+            vec![
+                stmt(ITE(
+                    var("a"),
+                    vec![stmt(Ret(Some(lit(Int(1)))))],
+                    Vec::new(),
+                )),
+                stmt(Ret(Some(lit(Int(2))))),
+            ],
+        );
+        let main = vec![local("x", call("f", vec![lit(Bool(true))]))];
+        let spl = Inliner::new(10).run(vec![f, fun("main", &[], main.clone())]);
+        assert_eq!(body(&spl[1]), &main);
+    }
+
+    #[test]
+    fn rename_locals() {
+        // g(y) { var t = y; h(t); } main() { var t = 1; g(t); }
+        let g = fun(
+            "g",
+            &["y"],
+            vec![local("t", var("y")), stmt(Call(id("h"), vec![var("t")]))],
+        );
+        let main = fun(
+            "main",
+            &[],
+            vec![local("t", lit(Int(1))), stmt(Call(id("g"), vec![var("t")]))],
+        );
+        let spl = Inliner::new(10).run(vec![g, main]);
+        let body = body(&spl[1]);
+        let y = fresh_local(&body[1], "y");
+        let t = fresh_local(&body[2], "t");
+        assert_eq!(
+            body,
+            &vec![
+                local("t", lit(Int(1))),
+                local(y, var("t")),
+                local(t, var(y)),
+                stmt(Call(id("h"), vec![var(t)])),
+            ]
+        );
+    }
+
+    #[test]
+    fn arguments_not_moved_past_calls() {
+        // h() { g = 2; return 0; } f(a) { return h() + a; }
+        // main() { var l = 1; return f(g) + f(l) + f(l + 1); }
+        let h = fun(
+            "h",
+            &[],
+            vec![
+                stmt(Assign(id("g"), lit(Int(2)))),
+                stmt(Ret(Some(lit(Int(0))))),
+            ],
+        );
+        let f = fun(
+            "f",
+            &["a"],
+            vec![stmt(Ret(Some(bin(
+                BareOp::Plus,
+                call("h", Vec::new()),
+                var("a"),
+            ))))],
+        );
+        let plus = |lhs, rhs| bin(BareOp::Plus, lhs, rhs);
+        let global = call("f", vec![var("g")]);
+        let compound = call("f", vec![plus(var("l"), lit(Int(1)))]);
+        let main = fun(
+            "main",
+            &[],
+            vec![
+                local("l", lit(Int(1))),
+                stmt(Ret(Some(plus(
+                    plus(global.clone(), call("f", vec![var("l")])),
+                    compound.clone(),
+                )))),
+            ],
+        );
+        let spl = Inliner::new(10).run(vec![h, f, main]);
+        let inlined = plus(call("h", Vec::new()), var("l"));
+        assert_eq!(
+            body(&spl[2])[1],
+            stmt(Ret(Some(plus(plus(global, inlined), compound))))
+        );
+    }
+
+    #[test]
+    fn value_never_returned() {
+        // f(a) { h(a); } g(a) { if (a) { return; } return 1; }
+        // main() { var x = f(1); y = f(2); y = g(True); }
+        let f = fun("f", &["a"], vec![stmt(Call(id("h"), vec![var("a")]))]);
+        let g = fun(
+            "g",
+            &["a"],
+            vec![
+                stmt(ITE(var("a"), vec![stmt(Ret(None))], Vec::new())),
+                stmt(Ret(Some(lit(Int(1))))),
+            ],
+        );
+        let main = vec![
+            local("x", call("f", vec![lit(Int(1))])),
+            stmt(Assign(id("y"), call("f", vec![lit(Int(2))]))),
+            stmt(Assign(id("y"), call("g", vec![lit(Bool(true))]))),
+        ];
+        let spl = Inliner::new(10).run(vec![f, g, fun("main", &[], main.clone())]);
+        assert_eq!(body(&spl[2]), &main);
+    }
+
+    #[test]
+    fn no_capture_of_globals() {
+        // g() { return x; } main() { var x = 1; return g(); }
+        let g = fun("g", &[], vec![stmt(Ret(Some(var("x"))))]);
+        let shadowing = vec![
+            local("x", lit(Int(1))),
+            stmt(Ret(Some(call("g", Vec::new())))),
+        ];
+        let spl = vec![
+            g,
+            fun("main", &[], shadowing.clone()),
+            fun("h", &[], vec![stmt(Ret(Some(call("g", Vec::new()))))]),
+        ];
+        let spl = Inliner::new(10).run(spl);
+        assert_eq!(body(&spl[1]), &shadowing);
+        assert_eq!(body(&spl[2]), &vec![stmt(Ret(Some(var("x"))))]);
+    }
+}
@@ -0,0 +1,175 @@
+pub mod callgraph;
+pub mod dce;
+pub mod fold;
+pub mod inline;
+pub mod tailcall;
+
+use crate::ast::*;
+use crate::visit::{walk_exp, walk_stmt, Visitor};
+use std::collections::HashMap;
+
+/// Non-fatal diagnostic produced by a pass over the AST.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Warning(pub String, pub Option<Span>);
+
+pub(crate) fn lit_of(exp: &Exp) -> Option<LitVal> {
+    match (exp.0).0 {
+        BareExp::Lit(val) => Some(val),
+        _ => None,
+    }
+}
+
+/// Whether evaluating `exp` can neither have side effects nor fail, so that
+/// a pass may drop or duplicate it. Calls can have side effects; `.hd` and
+/// `.tl` fail on an empty list, and `/` and `%` unless the divisor is a
+/// literal other than 0.
+pub(crate) fn is_pure(exp: &Exp) -> bool {
+    use BareExp::*;
+    match &(exp.0).0 {
+        Var(_, fields) => fields
+            .iter()
+            .all(|(field, _)| matches!(field, BareSelector::Fst | BareSelector::Snd)),
+        Lit(_) => true,
+        Call(..) => false,
+        Tuple(coords) => coords.iter().all(is_pure),
+        BinOp((BareOp::Div, _), _, rhs) | BinOp((BareOp::Mod, _), _, rhs) if !matches!(lit_of(rhs), Some(LitVal::Int(n)) if n != 0) => {
+            false
+        }
+        BinOp(_, lhs, rhs) => is_pure(lhs) && is_pure(rhs),
+        UnOp(_, arg) => is_pure(arg),
+    }
+}
+
+pub(crate) fn var(id: BareId, span: Option<Span>) -> Exp {
+    ((BareExp::Var((id, span), Vec::new()), None), span)
+}
+
+pub(crate) fn bool_lit(val: bool, span: Option<Span>) -> Exp {
+    ((BareExp::Lit(LitVal::Bool(val)), None), span)
+}
+
+pub(crate) fn not(exp: Exp) -> Exp {
+    let span = exp.1;
+    (
+        (BareExp::UnOp((BareOp::Not, None), Box::new(exp)), None),
+        span,
+    )
+}
+
+pub(crate) fn stmts_span(stmts: &[Stmt]) -> Option<Span> {
+    let first = stmts.first()?.1;
+    let last = stmts.last()?.1;
+    Some(Span::hull(first?, last?))
+}
+
+/// Whether control can never continue past `stmt`. Note that SPL has no
+/// `break`, so `while (True)` never finishes.
+pub(crate) fn diverges((stmt, _): &Stmt) -> bool {
+    use BareStmt::*;
+    match stmt {
+        Ret(_) => true,
+        ITE(_, then, els) => then.iter().any(diverges) && els.iter().any(diverges),
+        While(cond, _) => lit_of(cond) == Some(LitVal::Bool(true)),
+        _ => false,
+    }
+}
+
+/// Counts the occurrences of variables, both reads and assignments.
+struct UseCounter<'u>(&'u mut HashMap<BareId, u32>);
+
+impl Visitor for UseCounter<'_> {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        if let BareStmt::Assign(id, _) = &stmt.0 {
+            *self.0.entry(id.0).or_insert(0) += 1;
+        }
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_exp(&mut self, exp: &Exp) {
+        if let BareExp::Var(id, _) = &(exp.0).0 {
+            *self.0.entry(id.0).or_insert(0) += 1;
+        }
+        walk_exp(self, exp)
+    }
+}
+
+pub(crate) fn count_exp_uses(exp: &Exp, uses: &mut HashMap<BareId, u32>) {
+    UseCounter(uses).visit_exp(exp)
+}
+
+/// Count the occurrences of every variable in `stmts`, other than in the
+/// declarations of locals.
+pub(crate) fn count_uses(stmts: &[Stmt], uses: &mut HashMap<BareId, u32>) {
+    let mut counter = UseCounter(uses);
+    for stmt in stmts {
+        counter.visit_stmt(stmt);
+    }
+}
+
+/// Builders for the ASTs the tests of the passes run on. Nodes have no types,
+/// and no spans unless `at` gives them one.
+#[cfg(test)]
+pub(crate) mod test_util {
+    use crate::ast::*;
+
+    pub(crate) fn at<T>((node, _): Spanned<T>, span: Option<Span>) -> Spanned<T> {
+        (node, span)
+    }
+
+    pub(crate) fn lit(val: LitVal) -> Exp {
+        ((BareExp::Lit(val), None), None)
+    }
+
+    pub(crate) fn id(name: &str) -> Id {
+        (BareId::intern(name), None)
+    }
+
+    pub(crate) fn var(name: &str) -> Exp {
+        super::var(BareId::intern(name), None)
+    }
+
+    pub(crate) fn call(name: &str, args: Vec<Exp>) -> Exp {
+        ((BareExp::Call(id(name), args), None), None)
+    }
+
+    /// `lhs op rhs`, spanning both operands if they have spans.
+    pub(crate) fn bin(op: BareOp, lhs: Exp, rhs: Exp) -> Exp {
+        let span = match (lhs.1, rhs.1) {
+            (Some(lhs), Some(rhs)) => Some(Span::hull(lhs, rhs)),
+            _ => None,
+        };
+        (
+            (
+                BareExp::BinOp((op, None), Box::new(lhs), Box::new(rhs)),
+                None,
+            ),
+            span,
+        )
+    }
+
+    /// `op arg`, with the span of `arg`.
+    pub(crate) fn un(op: BareOp, arg: Exp) -> Exp {
+        let span = arg.1;
+        ((BareExp::UnOp((op, None), Box::new(arg)), None), span)
+    }
+
+    pub(crate) fn stmt(stmt: BareStmt) -> Stmt {
+        (stmt, None)
+    }
+
+    pub(crate) fn local(name: &str, exp: Exp) -> Stmt {
+        stmt(BareStmt::Local((None, id(name), exp)))
+    }
+
+    pub(crate) fn fun(name: &str, params: &[&str], body: Vec<Stmt>) -> Decl {
+        let params = params.iter().map(|param| id(param)).collect();
+        (BareDecl::Fun(id(name), params, None, body), None)
+    }
+
+    pub(crate) fn body(decl: &Decl) -> &Vec<Stmt> {
+        match &decl.0 {
+            BareDecl::Fun(_, _, _, body) => body,
+            _ => unreachable!(),
+        }
+    }
+}
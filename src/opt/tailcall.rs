@@ -0,0 +1,279 @@
+use super::{bool_lit, not, stmts_span, var};
+use crate::ast::BareStmt::*;
+use crate::ast::*;
+
+/// Elimination of direct self-recursion in tail position.
+///
+/// In a function whose body contains `return f(..);` with `f` the function
+/// itself, the body is wrapped in a loop, and each such return assigns the
+/// arguments to the parameters and starts the next iteration instead, so
+/// the stack does not grow. Tail calls between different functions are left
+/// alone.
+pub struct TailCallEliminator;
+
+fn is_self_call(exp: &Exp, fun: BareId, arity: usize) -> bool {
+    match &(exp.0).0 {
+        BareExp::Call(id, args) => id.0 == fun && args.len() == arity,
+        _ => false,
+    }
+}
+
+fn has_tail_call((stmt, _): &Stmt, fun: BareId, arity: usize) -> bool {
+    let any = |stmts: &Vec<Stmt>| stmts.iter().any(|stmt| has_tail_call(stmt, fun, arity));
+    match stmt {
+        Ret(Some(exp)) => is_self_call(exp, fun, arity),
+        ITE(_, then, els) => any(then) || any(els),
+        While(_, body) => any(body),
+        _ => false,
+    }
+}
+
+impl TailCallEliminator {
+    pub fn run(&self, spl: SPL) -> SPL {
+        spl.into_iter().map(|decl| self.decl(decl)).collect()
+    }
+
+    fn decl(&self, (decl, span): Decl) -> Decl {
+        match decl {
+            BareDecl::Fun(id, params, typ, body)
+                if body
+                    .iter()
+                    .any(|stmt| has_tail_call(stmt, id.0, params.len())) =>
+            {
+                let again = BareId::fresh("again");
+                let body_span = stmts_span(&body);
+                let mut looped = vec![(Assign((again, None), bool_lit(false, None)), None)];
+                looped.extend(self.stmts(body, id.0, &params, again));
+                let body = vec![
+                    (Local((None, (again, None), bool_lit(true, None))), None),
+                    (While(var(again, None), looped), body_span),
+                ];
+                (BareDecl::Fun(id, params, typ, body), span)
+            }
+            decl => (decl, span),
+        }
+    }
+
+    /// Replace the tail calls in `stmts` by updates of `params` that set the
+    /// flag `again`, which guards everything that could run after them.
+    fn stmts(&self, stmts: Vec<Stmt>, fun: BareId, params: &[Id], again: BareId) -> Vec<Stmt> {
+        let mut out = Vec::with_capacity(stmts.len());
+        let mut rest = stmts.into_iter();
+        while let Some(stmt) = rest.next() {
+            let continues = has_tail_call(&stmt, fun, params.len());
+            match stmt {
+                (Ret(Some(((BareExp::Call(_, args), _), _))), span) if continues => {
+                    self.rebind(args, params, span, &mut out);
+                    out.push((Assign((again, span), bool_lit(true, span)), span));
+                    break;
+                }
+                (ITE(cond, then, els), span) => {
+                    let then = self.stmts(then, fun, params, again);
+                    let els = self.stmts(els, fun, params, again);
+                    out.push((ITE(cond, then, els), span));
+                }
+                (While(cond, body), span) if continues => {
+                    let cond_span = cond.1;
+                    let guard = BareExp::BinOp(
+                        (BareOp::And, None),
+                        Box::new(not(var(again, cond_span))),
+                        Box::new(cond),
+                    );
+                    let body = self.stmts(body, fun, params, again);
+                    out.push((While(((guard, None), cond_span), body), span));
+                }
+                stmt => out.push(stmt),
+            }
+            if continues {
+                let rest: Vec<Stmt> = rest.collect();
+                if !rest.is_empty() {
+                    let span = stmts_span(&rest);
+                    let rest = self.stmts(rest, fun, params, again);
+                    out.push((ITE(not(var(again, span)), rest, Vec::new()), span));
+                }
+                break;
+            }
+        }
+        out
+    }
+
+    /// Assign `args` to `params`, evaluating all of them before assigning any.
+    fn rebind(&self, args: Vec<Exp>, params: &[Id], span: Option<Span>, out: &mut Vec<Stmt>) {
+        let changed: Vec<(Id, Exp)> = params
+            .iter()
+            .copied()
+            .zip(args)
+            .filter(|(param, arg)| match &(arg.0).0 {
+                BareExp::Var(id, fields) => id.0 != param.0 || !fields.is_empty(),
+                _ => true,
+            })
+            .collect();
+        if changed.len() == 1 {
+            let (param, arg) = changed.into_iter().next().unwrap();
+            return out.push((Assign((param.0, span), arg), span));
+        }
+        let mut assigns = Vec::with_capacity(changed.len());
+        for (param, arg) in changed {
+            let tmp = BareId::fresh(param.0.name());
+            out.push((Local((None, (tmp, span), arg)), span));
+            assigns.push((Assign((param.0, span), var(tmp, span)), span));
+        }
+        out.extend(assigns);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opt::test_util::{bin, call, fun, id, lit, stmt, var};
+    use BareDecl::*;
+    use LitVal::*;
+
+    fn looped(again: &str, body: Vec<Stmt>) -> Vec<Stmt> {
+        let mut looped = vec![set(again, lit(Bool(false)))];
+        looped.extend(body);
+        vec![
+            stmt(Local((None, id(again), lit(Bool(true))))),
+            stmt(While(var(again), looped)),
+        ]
+    }
+
+    fn set(name: &str, exp: Exp) -> Stmt {
+        stmt(Assign(id(name), exp))
+    }
+
+    /// The names of the locals declared in the body of `decl`, in order,
+    /// checked to be fresh ones made from `bases`.
+    fn fresh_locals(decl: &Decl, bases: &[&str]) -> Vec<&'static str> {
+        fn collect(stmts: &[Stmt], names: &mut Vec<&'static str>) {
+            for (stmt, _) in stmts {
+                match stmt {
+                    Local((_, (id, _), _)) => names.push(id.name()),
+                    ITE(_, then, els) => {
+                        collect(then, names);
+                        collect(els, names);
+                    }
+                    While(_, body) => collect(body, names),
+                    _ => (),
+                }
+            }
+        }
+        let mut names = Vec::new();
+        if let Fun(_, _, _, body) = &decl.0 {
+            collect(body, &mut names);
+        }
+        assert_eq!(names.len(), bases.len(), "{:?}", names);
+        for (name, base) in names.iter().zip(bases) {
+            assert!(name.starts_with(base) && name != base, "{}", name);
+        }
+        names
+    }
+
+    #[test]
+    fn accumulator() {
+        // sum(n, acc) { if (n == 0) { return acc; } return sum(n - 1, acc + n); }
+        let base = stmt(ITE(
+            bin(BareOp::Eq, var("n"), lit(Int(0))),
+            vec![stmt(Ret(Some(var("acc"))))],
+            Vec::new(),
+        ));
+        let next_n = bin(BareOp::Minus, var("n"), lit(Int(1)));
+        let next_acc = bin(BareOp::Plus, var("acc"), var("n"));
+        let rec = stmt(Ret(Some(call(
+            "sum",
+            vec![next_n.clone(), next_acc.clone()],
+        ))));
+        let spl = TailCallEliminator.run(vec![fun("sum", &["n", "acc"], vec![base.clone(), rec])]);
+        let names = fresh_locals(&spl[0], &["again", "n", "acc"]);
+        let (again, n, acc) = (names[0], names[1], names[2]);
+        let expected = looped(
+            again,
+            vec![
+                base,
+                stmt(Local((None, id(n), next_n))),
+                stmt(Local((None, id(acc), next_acc))),
+                set("n", var(n)),
+                set("acc", var(acc)),
+                set(again, lit(Bool(true))),
+            ],
+        );
+        assert_eq!(spl, vec![fun("sum", &["n", "acc"], expected)]);
+    }
+
+    #[test]
+    fn single_changed_argument() {
+        // drop(xs, k) { if (k == 0) { return xs; } else { return drop(xs.tl, k); } }
+        let tl = (
+            (BareExp::Var(id("xs"), vec![(BareSelector::Tl, None)]), None),
+            None,
+        );
+        let body = vec![stmt(ITE(
+            bin(BareOp::Eq, var("k"), lit(Int(0))),
+            vec![stmt(Ret(Some(var("xs"))))],
+            vec![stmt(Ret(Some(call("drop", vec![tl.clone(), var("k")]))))],
+        ))];
+        let spl = TailCallEliminator.run(vec![fun("drop", &["xs", "k"], body)]);
+        let again = fresh_locals(&spl[0], &["again"])[0];
+        let expected = looped(
+            again,
+            vec![stmt(ITE(
+                bin(BareOp::Eq, var("k"), lit(Int(0))),
+                vec![stmt(Ret(Some(var("xs"))))],
+                vec![set("xs", tl), set(again, lit(Bool(true)))],
+            ))],
+        );
+        assert_eq!(spl, vec![fun("drop", &["xs", "k"], expected)]);
+    }
+
+    #[test]
+    fn guard_rest_of_body() {
+        // f(x) { while (x) { return f(False); } g(); }
+        let body = vec![
+            stmt(While(
+                var("x"),
+                vec![stmt(Ret(Some(call("f", vec![lit(Bool(false))]))))],
+            )),
+            stmt(Call(id("g"), Vec::new())),
+        ];
+        let spl = TailCallEliminator.run(vec![fun("f", &["x"], body)]);
+        let again = fresh_locals(&spl[0], &["again"])[0];
+        let guard = bin(BareOp::And, not(var(again)), var("x"));
+        let guard = (((guard.0).0, None), None);
+        let expected = looped(
+            again,
+            vec![
+                stmt(While(
+                    guard,
+                    vec![set("x", lit(Bool(false))), set(again, lit(Bool(true)))],
+                )),
+                stmt(ITE(
+                    not(var(again)),
+                    vec![stmt(Call(id("g"), Vec::new()))],
+                    Vec::new(),
+                )),
+            ],
+        );
+        assert_eq!(spl, vec![fun("f", &["x"], expected)]);
+    }
+
+    #[test]
+    fn leave_other_calls() {
+        // length(xs) { return 1 + length(xs.tl); } g(x) { return length(x); }
+        let length = fun(
+            "length",
+            &["xs"],
+            vec![stmt(Ret(Some(bin(
+                BareOp::Plus,
+                lit(Int(1)),
+                call("length", vec![var("xs")]),
+            ))))],
+        );
+        let g = fun(
+            "g",
+            &["x"],
+            vec![stmt(Ret(Some(call("length", vec![var("x")]))))],
+        );
+        let spl = vec![length, g];
+        assert_eq!(TailCallEliminator.run(spl.clone()), spl);
+    }
+}
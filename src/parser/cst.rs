@@ -0,0 +1,867 @@
+use super::lex::{Lex, LexError};
+use super::tok::{Loc, LocTok, Misc, Token, Trivia};
+use super::{ParseError, ParseResult};
+use crate::ast::*;
+use crate::source::FileId;
+use std::iter::Peekable;
+use std::rc::Rc;
+use SyntaxKind::*;
+
+/// The kind of a token or node of a concrete syntax tree.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SyntaxKind {
+    // Tokens
+    Whitespace,
+    LineComment,
+    BlockComment,
+    Ident,
+    Keyword,
+    IntLit,
+    CharLit,
+    StrLit,
+    BoolLit,
+    NilLit,
+    Operator,
+    Punct,
+    // Nodes
+    Program,
+    VarDecl,
+    FunDecl,
+    FunType,
+    Type,
+    IfStmt,
+    WhileStmt,
+    AssignStmt,
+    CallStmt,
+    ReturnStmt,
+    LocalStmt,
+    VarExp,
+    CallExp,
+    LitExp,
+    TupleExp,
+    /// A parenthesised expression.
+    ParenExp,
+    BinExp,
+    UnExp,
+}
+
+impl SyntaxKind {
+    pub fn is_trivia(self) -> bool {
+        matches!(self, Whitespace | LineComment | BlockComment)
+    }
+}
+
+fn token_kind(tok: Token) -> SyntaxKind {
+    match tok {
+        Token::Trivia(Trivia::Whitespace) => Whitespace,
+        Token::Trivia(Trivia::LineComment) => LineComment,
+        Token::Trivia(Trivia::BlockComment) => BlockComment,
+        Token::IdTok(_) => Ident,
+        Token::Selector(_) | Token::TypeTok(_) => Keyword,
+        Token::Marker(Misc::Var)
+        | Token::Marker(Misc::If)
+        | Token::Marker(Misc::Else)
+        | Token::Marker(Misc::While)
+        | Token::Marker(Misc::Return) => Keyword,
+        Token::Marker(_) => Punct,
+        Token::Lit(LitVal::Int(_)) => IntLit,
+        Token::Lit(LitVal::Char(_)) => CharLit,
+        Token::Lit(LitVal::Bool(_)) => BoolLit,
+        Token::Lit(LitVal::Nil) => NilLit,
+        Token::StrLit(_) => StrLit,
+        Token::Op(_) => Operator,
+    }
+}
+
+/// A token of the immutable, position-independent ("green") tree.
+#[derive(Debug, PartialEq, Eq)]
+pub struct GreenToken {
+    kind: SyntaxKind,
+    text: String,
+}
+
+/// A node of the green tree: its kind, children and length in bytes.
+#[derive(Debug, PartialEq, Eq)]
+pub struct GreenNode {
+    kind: SyntaxKind,
+    len: u32,
+    children: Vec<GreenElement>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GreenElement {
+    Node(Rc<GreenNode>),
+    Token(Rc<GreenToken>),
+}
+
+impl GreenToken {
+    pub fn new(kind: SyntaxKind, text: String) -> Self {
+        GreenToken { kind, text }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+impl GreenNode {
+    pub fn new(kind: SyntaxKind, children: Vec<GreenElement>) -> Self {
+        let len = children.iter().map(GreenElement::len).sum();
+        GreenNode {
+            kind,
+            len,
+            children,
+        }
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.kind
+    }
+
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    pub fn children(&self) -> &[GreenElement] {
+        &self.children
+    }
+}
+
+impl GreenElement {
+    pub fn len(&self) -> u32 {
+        match self {
+            GreenElement::Node(node) => node.len,
+            GreenElement::Token(tok) => tok.text.len() as u32,
+        }
+    }
+}
+
+/// A node of the green tree at a position in a file (the "red" tree).
+/// These are created on demand while walking down from the root.
+#[derive(Clone, Debug)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+#[derive(Debug)]
+struct NodeData {
+    green: Rc<GreenNode>,
+    file: FileId,
+    start: u32,
+    parent: Option<SyntaxNode>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SyntaxToken {
+    green: Rc<GreenToken>,
+    parent: SyntaxNode,
+    start: u32,
+}
+
+#[derive(Clone, Debug)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxNode {
+    pub fn new_root(green: Rc<GreenNode>, file: FileId) -> Self {
+        SyntaxNode(Rc::new(NodeData {
+            green,
+            file,
+            start: 0,
+            parent: None,
+        }))
+    }
+
+    pub fn kind(&self) -> SyntaxKind {
+        self.0.green.kind
+    }
+
+    pub fn green(&self) -> &Rc<GreenNode> {
+        &self.0.green
+    }
+
+    pub fn file(&self) -> FileId {
+        self.0.file
+    }
+
+    pub fn span(&self) -> Span {
+        Span::new(self.0.file, self.0.start, self.0.start + self.0.green.len)
+    }
+
+    pub fn parent(&self) -> Option<&SyntaxNode> {
+        self.0.parent.as_ref()
+    }
+
+    pub fn children(&self) -> Vec<SyntaxElement> {
+        let mut start = self.0.start;
+        let mut children = Vec::with_capacity(self.0.green.children.len());
+        for child in &self.0.green.children {
+            children.push(match child {
+                GreenElement::Node(green) => SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                    green: green.clone(),
+                    file: self.0.file,
+                    start,
+                    parent: Some(self.clone()),
+                }))),
+                GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken {
+                    green: green.clone(),
+                    parent: self.clone(),
+                    start,
+                }),
+            });
+            start += child.len();
+        }
+        children
+    }
+
+    pub fn child_nodes(&self) -> Vec<SyntaxNode> {
+        self.children()
+            .into_iter()
+            .filter_map(|child| match child {
+                SyntaxElement::Node(node) => Some(node),
+                SyntaxElement::Token(_) => None,
+            })
+            .collect()
+    }
+
+    /// All tokens in the subtree, in source order.
+    pub fn tokens(&self) -> Vec<SyntaxToken> {
+        let mut tokens = Vec::new();
+        for child in self.children() {
+            match child {
+                SyntaxElement::Node(node) => tokens.extend(node.tokens()),
+                SyntaxElement::Token(tok) => tokens.push(tok),
+            }
+        }
+        tokens
+    }
+
+    /// The source text of the subtree, exactly as lexed.
+    pub fn text(&self) -> String {
+        fn push(green: &GreenNode, out: &mut String) {
+            for child in &green.children {
+                match child {
+                    GreenElement::Node(node) => push(node, out),
+                    GreenElement::Token(tok) => out.push_str(&tok.text),
+                }
+            }
+        }
+        let mut text = String::with_capacity(self.0.green.len as usize);
+        push(&self.0.green, &mut text);
+        text
+    }
+}
+
+impl SyntaxToken {
+    pub fn kind(&self) -> SyntaxKind {
+        self.green.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.green.text
+    }
+
+    pub fn span(&self) -> Span {
+        let file = self.parent.file();
+        Span::new(file, self.start, self.start + self.green.text.len() as u32)
+    }
+
+    pub fn parent(&self) -> &SyntaxNode {
+        &self.parent
+    }
+}
+
+/// Builds the green tree while parsing. Tokens are appended as the parser
+/// consumes them, and once it has parsed a construct, everything from the
+/// construct's first token on becomes its node. Trivia is only appended
+/// along with the next token, so trivia between the parts of a construct
+/// belongs to that construct, and trivia before or after it to its parent.
+pub(super) struct Builder<'s> {
+    source: &'s str,
+    /// The elements so far, covering the source from its start.
+    children: Vec<GreenElement>,
+    trivia: Vec<GreenElement>,
+    /// The number of trivia tokens appended along with the last token.
+    flushed: usize,
+}
+
+impl<'s> Builder<'s> {
+    pub(super) fn new(source: &'s str) -> Self {
+        Builder {
+            source,
+            children: Vec::new(),
+            trivia: Vec::new(),
+            flushed: 0,
+        }
+    }
+
+    fn green(&self, (tok, loc): LocTok) -> GreenElement {
+        let text = &self.source[loc.start as usize..(loc.start + loc.len) as usize];
+        GreenElement::Token(Rc::new(GreenToken::new(token_kind(tok), text.to_string())))
+    }
+
+    pub(super) fn trivia(&mut self, trivia: LocTok) {
+        let green = self.green(trivia);
+        self.trivia.push(green);
+    }
+
+    pub(super) fn token(&mut self, token: LocTok) {
+        let green = self.green(token);
+        self.flushed = self.trivia.len();
+        self.children.append(&mut self.trivia);
+        self.children.push(green);
+    }
+
+    /// Take back the last token, which the parser has put back.
+    pub(super) fn untoken(&mut self) {
+        self.children.pop();
+        let start = self.children.len() - self.flushed;
+        let mut trivia: Vec<_> = self.children.drain(start..).collect();
+        trivia.append(&mut self.trivia);
+        self.trivia = trivia;
+        self.flushed = 0;
+    }
+
+    /// Make the elements from the token at offset `start` on a node.
+    pub(super) fn node(&mut self, kind: SyntaxKind, start: u32) {
+        let mut offset: u32 = self.children.iter().map(GreenElement::len).sum();
+        let mut first = self.children.len();
+        while offset > start {
+            first -= 1;
+            offset -= self.children[first].len();
+        }
+        if offset != start {
+            panic!("Internal parser error: no token starts at {}", start);
+        }
+        let children = self.children.drain(first..).collect();
+        let green = GreenElement::Node(Rc::new(GreenNode::new(kind, children)));
+        self.children.push(green);
+        self.flushed = 0;
+    }
+
+    pub(super) fn finish(mut self, file: FileId) -> SyntaxNode {
+        self.children.append(&mut self.trivia);
+        SyntaxNode::new_root(Rc::new(GreenNode::new(Program, self.children)), file)
+    }
+}
+
+impl SyntaxElement {
+    pub fn span(&self) -> Span {
+        match self {
+            SyntaxElement::Node(node) => node.span(),
+            SyntaxElement::Token(tok) => tok.span(),
+        }
+    }
+}
+
+fn loc(span: Span) -> Loc {
+    Loc {
+        file: span.file,
+        start: span.start,
+        len: span.end - span.start,
+    }
+}
+
+fn unexpected<T>(node: &SyntaxNode, expected: &str) -> ParseResult<T> {
+    Err(ParseError(
+        format!("Expected {}, found {:?} node", expected, node.kind()),
+        Some(loc(node.span())),
+    ))
+}
+
+/// The non-trivia children of a node, taken in order.
+struct Parts {
+    node: SyntaxNode,
+    elems: Peekable<std::vec::IntoIter<SyntaxElement>>,
+}
+
+impl Parts {
+    fn new(node: &SyntaxNode) -> Self {
+        let elems: Vec<_> = node
+            .children()
+            .into_iter()
+            .filter(|elem| match elem {
+                SyntaxElement::Token(tok) => !tok.kind().is_trivia(),
+                SyntaxElement::Node(_) => true,
+            })
+            .collect();
+        Parts {
+            node: node.clone(),
+            elems: elems.into_iter().peekable(),
+        }
+    }
+
+    fn missing<T>(&self, found: Option<SyntaxElement>, expected: &str) -> ParseResult<T> {
+        let span = found.map_or(self.node.span(), |elem| elem.span());
+        Err(ParseError(
+            format!("Expected {} in {:?} node", expected, self.node.kind()),
+            Some(loc(span)),
+        ))
+    }
+
+    /// Whether the next part is the token `text`, which is then taken.
+    fn eat(&mut self, text: &str) -> bool {
+        match self.elems.peek() {
+            Some(SyntaxElement::Token(tok)) if tok.text() == text => {
+                self.elems.next();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn punct(&mut self, text: &str) -> ParseResult<()> {
+        if self.eat(text) {
+            Ok(())
+        } else {
+            let found = self.elems.next();
+            self.missing(found, &format!("'{}'", text))
+        }
+    }
+
+    fn token(&mut self, expected: &str) -> ParseResult<SyntaxToken> {
+        match self.elems.next() {
+            Some(SyntaxElement::Token(tok)) => Ok(tok),
+            found => self.missing(found, expected),
+        }
+    }
+
+    fn node(&mut self, expected: &str) -> ParseResult<SyntaxNode> {
+        match self.elems.next() {
+            Some(SyntaxElement::Node(node)) => Ok(node),
+            found => self.missing(found, expected),
+        }
+    }
+
+    fn done(mut self) -> ParseResult<()> {
+        match self.elems.next() {
+            None => Ok(()),
+            found => self.missing(found, "nothing more"),
+        }
+    }
+}
+
+/// Reads the AST off a syntax tree.
+struct Lower {
+    width: IntWidth,
+}
+
+/// The AST that `root` stands for. Only the kinds of nodes and the non-trivia
+/// tokens in them matter, so the tree may have been edited since parsing.
+pub(super) fn lower(root: &SyntaxNode, width: IntWidth) -> ParseResult<SPL> {
+    let lower = Lower { width };
+    root.child_nodes()
+        .iter()
+        .map(|decl| lower.decl(decl))
+        .collect()
+}
+
+impl Lower {
+    /// The token that `tok` lexes to, and the text of a string literal.
+    fn value(&self, tok: &SyntaxToken) -> ParseResult<(Token, Option<String>)> {
+        self.lex("", tok)
+    }
+
+    /// The token that `tok` lexes to after the token `before`.
+    fn lex(&self, before: &str, tok: &SyntaxToken) -> ParseResult<(Token, Option<String>)> {
+        let span = tok.span();
+        let text = format!("{}{}", before, tok.text());
+        let mut lex = Lex::lex_file(span.file, &text, self.width);
+        if !before.is_empty() {
+            lex.next();
+        }
+        match (lex.next(), lex.next()) {
+            (Some(Ok((Token::StrLit(index), _))), None) => {
+                let string = lex.strings.swap_remove(index as usize);
+                Ok((Token::StrLit(index), Some(string)))
+            }
+            (Some(Ok((token, _))), None) => Ok((token, None)),
+            (Some(Err(LexError(msg, mut at))), _) | (_, Some(Err(LexError(msg, mut at)))) => {
+                at.start = at.start + span.start - before.len() as u32;
+                Err(ParseError(msg, Some(at)))
+            }
+            _ => Err(ParseError(
+                format!("'{}' is not a single token", tok.text()),
+                Some(loc(span)),
+            )),
+        }
+    }
+
+    fn ident(&self, parts: &mut Parts) -> ParseResult<Id> {
+        let tok = parts.token("identifier")?;
+        match self.value(&tok)? {
+            (Token::IdTok(id), _) => Ok((id, Some(tok.span()))),
+            _ => parts.missing(Some(SyntaxElement::Token(tok)), "identifier"),
+        }
+    }
+
+    /// Nodes up to a closing ')', separated by commas.
+    fn list<T>(
+        &self,
+        parts: &mut Parts,
+        single: fn(&Self, &SyntaxNode) -> ParseResult<T>,
+    ) -> ParseResult<Vec<T>> {
+        let mut elems = Vec::new();
+        if parts.eat(")") {
+            return Ok(elems);
+        }
+        loop {
+            elems.push(single(self, &parts.node("element")?)?);
+            if parts.eat(")") {
+                return Ok(elems);
+            }
+            parts.punct(",")?;
+        }
+    }
+
+    fn decl(&self, node: &SyntaxNode) -> ParseResult<Decl> {
+        let span = Some(node.span());
+        match node.kind() {
+            VarDecl => Ok((BareDecl::Global(self.var_decl(node)?), span)),
+            FunDecl => {
+                let mut parts = Parts::new(node);
+                let name = self.ident(&mut parts)?;
+                parts.punct("(")?;
+                let mut params = Vec::new();
+                if !parts.eat(")") {
+                    loop {
+                        params.push(self.ident(&mut parts)?);
+                        if parts.eat(")") {
+                            break;
+                        }
+                        parts.punct(",")?;
+                    }
+                }
+                let typ = if parts.eat("::") {
+                    Some(self.fun_type(&parts.node("function type")?)?)
+                } else {
+                    None
+                };
+                let body = self.block(&mut parts)?;
+                parts.done()?;
+                Ok((BareDecl::Fun(name, params, typ, body), span))
+            }
+            _ => unexpected(node, "declaration"),
+        }
+    }
+
+    fn var_decl(&self, node: &SyntaxNode) -> ParseResult<crate::ast::VarDecl> {
+        let mut parts = Parts::new(node);
+        let typ = if parts.eat("var") {
+            None
+        } else {
+            Some(self.typ(&parts.node("type or 'var'")?)?)
+        };
+        let name = self.ident(&mut parts)?;
+        parts.punct("=")?;
+        let exp = self.exp(&parts.node("expression")?)?;
+        parts.punct(";")?;
+        parts.done()?;
+        Ok((typ, name, exp))
+    }
+
+    fn fun_type(&self, node: &SyntaxNode) -> ParseResult<crate::ast::FunType> {
+        if node.kind() != FunType {
+            return unexpected(node, "function type");
+        }
+        let mut parts = Parts::new(node);
+        let mut args = Vec::new();
+        while !parts.eat("->") {
+            args.push(self.typ(&parts.node("type or '->'")?)?);
+        }
+        let ret = self.typ(&parts.node("return type")?)?;
+        parts.done()?;
+        Ok(((args, ret), Some(node.span())))
+    }
+
+    fn typ(&self, node: &SyntaxNode) -> ParseResult<crate::ast::Type> {
+        if node.kind() != Type {
+            return unexpected(node, "type");
+        }
+        let mut parts = Parts::new(node);
+        let typ = if parts.eat("[") {
+            let elem = self.typ(&parts.node("type")?)?;
+            parts.punct("]")?;
+            BareType::List(Box::new(elem))
+        } else if parts.eat("(") {
+            let mut elems = self.list(&mut parts, Self::typ)?;
+            if elems.len() == 1 {
+                elems.pop().unwrap().0
+            } else {
+                BareType::Tuple(elems)
+            }
+        } else {
+            let tok = parts.token("type")?;
+            match self.value(&tok)? {
+                (Token::IdTok(id), _) => BareType::Typename((id, Some(tok.span()))),
+                (Token::TypeTok(btype), _) => BareType::Lit(btype),
+                _ => return parts.missing(Some(SyntaxElement::Token(tok)), "type"),
+            }
+        };
+        parts.done()?;
+        Ok((typ, Some(node.span())))
+    }
+
+    fn block(&self, parts: &mut Parts) -> ParseResult<Vec<Stmt>> {
+        parts.punct("{")?;
+        let mut stmts = Vec::new();
+        while !parts.eat("}") {
+            stmts.push(self.stmt(&parts.node("statement or '}'")?)?);
+        }
+        Ok(stmts)
+    }
+
+    fn condition(&self, parts: &mut Parts) -> ParseResult<Exp> {
+        parts.punct("(")?;
+        let cond = self.exp(&parts.node("condition")?)?;
+        parts.punct(")")?;
+        Ok(cond)
+    }
+
+    fn stmt(&self, node: &SyntaxNode) -> ParseResult<Stmt> {
+        let span = Some(node.span());
+        if node.kind() == LocalStmt {
+            return Ok((BareStmt::Local(self.var_decl(node)?), span));
+        }
+        let mut parts = Parts::new(node);
+        let stmt = match node.kind() {
+            IfStmt => {
+                parts.punct("if")?;
+                let cond = self.condition(&mut parts)?;
+                let then = self.block(&mut parts)?;
+                let els = if parts.eat("else") {
+                    self.block(&mut parts)?
+                } else {
+                    Vec::new()
+                };
+                BareStmt::ITE(cond, then, els)
+            }
+            WhileStmt => {
+                parts.punct("while")?;
+                let cond = self.condition(&mut parts)?;
+                BareStmt::While(cond, self.block(&mut parts)?)
+            }
+            AssignStmt => {
+                let id = self.ident(&mut parts)?;
+                parts.punct("=")?;
+                let exp = self.exp(&parts.node("expression")?)?;
+                parts.punct(";")?;
+                BareStmt::Assign(id, exp)
+            }
+            CallStmt => {
+                let id = self.ident(&mut parts)?;
+                parts.punct("(")?;
+                let args = self.list(&mut parts, Self::exp)?;
+                parts.punct(";")?;
+                BareStmt::Call(id, args)
+            }
+            ReturnStmt => {
+                parts.punct("return")?;
+                let exp = if parts.eat(";") {
+                    None
+                } else {
+                    let exp = self.exp(&parts.node("expression or ';'")?)?;
+                    parts.punct(";")?;
+                    Some(exp)
+                };
+                BareStmt::Ret(exp)
+            }
+            _ => return unexpected(node, "statement"),
+        };
+        parts.done()?;
+        Ok((stmt, span))
+    }
+
+    fn exp(&self, node: &SyntaxNode) -> ParseResult<Exp> {
+        use BareExp::*;
+        let span = Some(node.span());
+        let mut parts = Parts::new(node);
+        let exp = match node.kind() {
+            VarExp => {
+                let id = self.ident(&mut parts)?;
+                let mut fld = Vec::new();
+                while parts.eat(".") {
+                    let tok = parts.token("selector")?;
+                    match self.value(&tok)? {
+                        (Token::Selector(sel), _) => fld.push((sel, Some(tok.span()))),
+                        _ => return parts.missing(Some(SyntaxElement::Token(tok)), "selector"),
+                    }
+                }
+                Var(id, fld)
+            }
+            CallExp => {
+                let id = self.ident(&mut parts)?;
+                parts.punct("(")?;
+                Call(id, self.list(&mut parts, Self::exp)?)
+            }
+            LitExp if parts.eat("-") => {
+                let tok = parts.token("integer literal")?;
+                match self.lex("-", &tok)? {
+                    (Token::Lit(LitVal::Int(n)), _) => Lit(LitVal::Int(self.width.negate(n))),
+                    _ => return parts.missing(Some(SyntaxElement::Token(tok)), "integer literal"),
+                }
+            }
+            LitExp => {
+                let tok = parts.token("literal")?;
+                match self.value(&tok)? {
+                    (Token::Lit(val), _) => Lit(val),
+                    (Token::StrLit(_), Some(string)) => {
+                        parts.done()?;
+                        return Ok(super::string(&string, node.span()));
+                    }
+                    _ => return parts.missing(Some(SyntaxElement::Token(tok)), "literal"),
+                }
+            }
+            TupleExp => {
+                parts.punct("(")?;
+                Tuple(self.list(&mut parts, Self::exp)?)
+            }
+            ParenExp => {
+                parts.punct("(")?;
+                let inner = self.exp(&parts.node("expression")?)?;
+                parts.punct(")")?;
+                parts.done()?;
+                return Ok((inner.0, span));
+            }
+            BinExp => {
+                let lhs = self.exp(&parts.node("expression")?)?;
+                let tok = parts.token("operator")?;
+                let op = match self.value(&tok)? {
+                    (Token::Op(op), _) if !op.is_unary() => op,
+                    _ => return parts.missing(Some(SyntaxElement::Token(tok)), "binary operator"),
+                };
+                let rhs = self.exp(&parts.node("expression")?)?;
+                BinOp((op, Some(tok.span())), Box::new(lhs), Box::new(rhs))
+            }
+            UnExp => {
+                let tok = parts.token("operator")?;
+                let op = match self.value(&tok)? {
+                    (Token::Op(BareOp::Minus), _) => BareOp::Neg,
+                    (Token::Op(BareOp::Not), _) => BareOp::Not,
+                    _ => return parts.missing(Some(SyntaxElement::Token(tok)), "unary operator"),
+                };
+                let arg = self.exp(&parts.node("expression")?)?;
+                UnOp((op, Some(tok.span())), Box::new(arg))
+            }
+            _ => return unexpected(node, "expression"),
+        };
+        parts.done()?;
+        Ok(((exp, None), span))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{ast_of, parse_lossless};
+    use crate::source::SourceMap;
+
+    fn parse(source: &str) -> (SPL, SyntaxNode) {
+        let mut map = SourceMap::new();
+        let file = map.add("test.spl".to_string(), source.to_string());
+        parse_lossless(&map, file, IntWidth::W64).unwrap()
+    }
+
+    fn kinds(nodes: &[SyntaxNode]) -> Vec<SyntaxKind> {
+        nodes.iter().map(SyntaxNode::kind).collect()
+    }
+
+    #[test]
+    fn lossless_round_trip() {
+        let source =
+            "// header\r\nvar x = 1 ;\t/* after */\n\nf ( a ) {\n  return a+x; // done\n}\n";
+        let (spl, root) = parse(source);
+        assert_eq!(root.text(), source);
+        assert_eq!(root.green().len() as usize, source.len());
+        assert_eq!(ast_of(&root, IntWidth::W64), Ok(spl));
+    }
+
+    #[test]
+    fn ast_from_tree() {
+        let source = "T x = (1); (Int, [b]) y = (x.tl.hd, \"s\");\n\
+                      f(a, b) :: -> Void { if (!a) { g(-b, ()); } else { return; } \
+                      while (a) { T z = a * (b + -2) - -(2) - - 9223372036854775808; Int w = 'c' : []; a = z; } }";
+        let (spl, root) = parse(source);
+        assert_eq!(ast_of(&root, IntWidth::W64), Ok(spl));
+    }
+
+    /// Replace the text of every token `from` in `green` by `to`.
+    fn edit(green: &GreenNode, from: &str, to: &str) -> GreenNode {
+        let children = green
+            .children()
+            .iter()
+            .map(|child| match child {
+                GreenElement::Node(node) => GreenElement::Node(Rc::new(edit(node, from, to))),
+                GreenElement::Token(tok) if tok.text() == from => {
+                    GreenElement::Token(Rc::new(GreenToken::new(tok.kind(), to.to_string())))
+                }
+                GreenElement::Token(_) => child.clone(),
+            })
+            .collect();
+        GreenNode::new(green.kind(), children)
+    }
+
+    #[test]
+    fn ast_of_edited_tree() {
+        let (_, root) = parse("var x = 1 + 2; // 2\n");
+        let edited = SyntaxNode::new_root(Rc::new(edit(root.green(), "2", "40")), root.file());
+        let (spl, _) = parse("var x = 1 + 40; // 2\n");
+        assert_eq!(ast_of(&edited, IntWidth::W64), Ok(spl));
+        let edited = SyntaxNode::new_root(Rc::new(edit(root.green(), "+", ",")), root.file());
+        let err = ast_of(&edited, IntWidth::W64).unwrap_err();
+        assert_eq!(err.0, "Expected binary operator in BinExp node");
+        assert_eq!(err.1.map(|loc| loc.start), Some(10));
+    }
+
+    #[test]
+    fn trivia_placement() {
+        let (_, root) = parse("var x = 1; // one\nf(a) { /* c */ return -a; }");
+        let decls = root.child_nodes();
+        assert_eq!(kinds(&decls), vec![VarDecl, FunDecl]);
+        let top: Vec<_> = root
+            .children()
+            .into_iter()
+            .filter_map(|child| match child {
+                SyntaxElement::Token(tok) if tok.kind() == LineComment => Some(tok),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].text(), "// one");
+        let fun = &decls[1];
+        assert!(fun.tokens().iter().any(|tok| tok.kind() == BlockComment));
+        let ret = &fun.child_nodes()[0];
+        assert_eq!(ret.kind(), ReturnStmt);
+        assert_eq!(ret.parent().unwrap().kind(), FunDecl);
+        let neg = &ret.child_nodes()[0];
+        assert_eq!(neg.kind(), UnExp);
+        assert_eq!(neg.text(), "-a");
+        let a = neg.tokens().pop().unwrap();
+        assert_eq!((a.kind(), a.text()), (Ident, "a"));
+        assert_eq!(a.span(), Span::new(root.file(), 41, 42));
+    }
+
+    #[test]
+    fn nesting_follows_ast() {
+        let (_, root) =
+            parse("g() :: [Int] -> Bool { if (f((1, 2)) == \"ab\") { x = 'c' : []; } }");
+        let fun = &root.child_nodes()[0];
+        assert_eq!(kinds(&fun.child_nodes()), vec![FunType, IfStmt]);
+        let fun_type = &fun.child_nodes()[0];
+        assert_eq!(fun_type.text(), "[Int] -> Bool");
+        assert_eq!(kinds(&fun_type.child_nodes()), vec![Type, Type]);
+        let ite = &fun.child_nodes()[1];
+        assert_eq!(kinds(&ite.child_nodes()), vec![BinExp, AssignStmt]);
+        let eq = &ite.child_nodes()[0];
+        assert_eq!(kinds(&eq.child_nodes()), vec![CallExp, LitExp]);
+        let string = &eq.child_nodes()[1];
+        assert_eq!(string.text(), "\"ab\"");
+        assert!(string.child_nodes().is_empty());
+        let call = &eq.child_nodes()[0];
+        assert_eq!(kinds(&call.child_nodes()), vec![TupleExp]);
+    }
+}
@@ -1,65 +1,105 @@
-use std::collections::HashMap;
 use std::iter::Peekable;
-use std::num::ParseIntError;
 use std::str::CharIndices;
 //use core::slice::{Iter};
 
 use super::tok::*;
 use crate::ast::BType::*;
+use crate::ast::BareId;
 use crate::ast::BareOp::*;
 use crate::ast::BareSelector::*;
+use crate::ast::IntWidth;
 use crate::ast::LitVal::*;
+use crate::intern::{kw, Symbol};
+use crate::source::FileId;
 use Misc::*;
 
 pub(super) struct Lex<'s> {
     input: &'s str,
     loc: Loc,
     chars: Peekable<CharIndices<'s>>,
-    wordtoks: HashMap<&'s str, Token>,
-    pub names: Vec<&'s str>,
-    vcount: u32,
+    pub strings: Vec<String>,
+    width: IntWidth,
+    lossless: bool,
+    /// Whether the last token other than trivia was a unary minus sign.
+    after_minus: bool,
+    /// Whether the last token other than trivia ended an operand, so that a
+    /// minus sign after it is binary.
+    after_operand: bool,
     peeked: Option<Option<Result<LocTok, LexError>>>,
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub(super) struct LexError(pub String, pub Loc);
 
+const KEYWORDS: &[(Symbol, Token)] = &[
+    (kw::VAR, Token::Marker(Var)),
+    (kw::VOID, Token::TypeTok(UnitT)),
+    (kw::INT, Token::TypeTok(IntT)),
+    (kw::BOOL, Token::TypeTok(BoolT)),
+    (kw::CHAR, Token::TypeTok(CharT)),
+    (kw::IF, Token::Marker(If)),
+    (kw::ELSE, Token::Marker(Else)),
+    (kw::WHILE, Token::Marker(While)),
+    (kw::RETURN, Token::Marker(Return)),
+    (kw::HD, Token::Selector(Hd)),
+    (kw::TL, Token::Selector(Tl)),
+    (kw::FST, Token::Selector(Fst)),
+    (kw::SND, Token::Selector(Snd)),
+    (kw::FALSE, Token::Lit(Bool(false))),
+    (kw::TRUE, Token::Lit(Bool(true))),
+];
+
+/// The keyword that lexes to `tok`, if any.
+pub(super) fn keyword(tok: Token) -> Option<&'static str> {
+    KEYWORDS
+        .iter()
+        .find(|&&(_, kw)| kw == tok)
+        .map(|&(word, _)| word.as_str())
+}
+
+/// Identifiers are ASCII: a letter, then letters, digits and underscores.
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
 impl<'sub, 's: 'sub> Lex<'s> {
+    /// Lex `source` on its own, as file 0 with 64-bit integers.
     pub fn lex(source: &'s str) -> Lex<'s> {
-        // TODO: remove loc.len+=, use loc.step()
-        use super::tok::Token::*;
-        let mut keywords = HashMap::with_capacity(256);
-        keywords.insert("var", Marker(Var));
-        keywords.insert("Void", TypeTok(UnitT));
-        keywords.insert("Int", TypeTok(IntT));
-        keywords.insert("Bool", TypeTok(BoolT));
-        keywords.insert("Char", TypeTok(CharT));
-        keywords.insert("if", Marker(If));
-        keywords.insert("else", Marker(Else));
-        keywords.insert("while", Marker(While));
-        keywords.insert("return", Marker(Return));
-        keywords.insert("hd", Selector(Hd));
-        keywords.insert("tl", Selector(Tl));
-        keywords.insert("fst", Selector(Fst));
-        keywords.insert("snd", Selector(Snd));
-        keywords.insert("False", Lit(Bool(false)));
-        keywords.insert("True", Lit(Bool(true)));
+        Self::lex_file(FileId(0), source, IntWidth::W64)
+    }
 
+    /// Lex `source`, the contents of `file`, checking integer literals
+    /// against `width`.
+    pub fn lex_file(file: FileId, source: &'s str, width: IntWidth) -> Lex<'s> {
         Lex {
             input: source,
             loc: Loc {
-                line: 0,
-                col: 0,
+                file,
+                start: 0,
                 len: 0,
             },
             chars: source.char_indices().peekable(),
-            wordtoks: keywords,
-            names: Vec::with_capacity(128),
-            vcount: 0,
+            strings: Vec::new(),
+            width,
+            lossless: false,
+            after_minus: false,
+            after_operand: false,
             peeked: None,
         }
     }
 
+    /// Like `lex_file`, but also produce `Trivia` tokens for whitespace and
+    /// comments, so that the tokens cover all of `source`.
+    pub fn lossless(file: FileId, source: &'s str, width: IntWidth) -> Lex<'s> {
+        let mut lex = Self::lex_file(file, source, width);
+        lex.lossless = true;
+        lex
+    }
+
+    pub fn width(&self) -> IntWidth {
+        self.width
+    }
+
     pub fn peek(&mut self) -> Option<&Result<LocTok, LexError>> {
         if let Some(ref val) = self.peeked {
             // Don't touch this. Borrow magic
@@ -87,8 +127,9 @@ impl<'sub, 's: 'sub> Lex<'s> {
     }
 
     fn step(&mut self) -> Option<(usize, char)> {
-        self.loc.len += 1;
-        self.chars.next()
+        let (pos, c) = self.chars.next()?;
+        self.loc.step(c);
+        Some((pos, c))
     }
 
     fn ipeek(&mut self) -> Option<char> {
@@ -96,20 +137,66 @@ impl<'sub, 's: 'sub> Lex<'s> {
     }
 
     fn step_ch(&mut self) -> Option<char> {
-        let ret = Some(self.chars.next()?.1);
-        self.loc.len += 1;
-        ret
+        Some(self.step()?.1)
     }
 
     fn escape_char(&mut self) -> Result<char, &'static str> {
-        match self.step_ch() {
-            Some('n') => Ok('\n'),
-            Some('\\') => Ok('\\'),
-            Some('\n') | None => Err("\'\\ at end of line"),
+        if let Some('\n') | Some('\r') | None = self.ipeek() {
+            return Err("\\ at end of line");
+        }
+        match self.step_ch().unwrap() {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            '0' => Ok('\0'),
+            '\\' => Ok('\\'),
+            '\'' => Ok('\''),
+            '"' => Ok('"'),
+            'x' => {
+                let hi = self
+                    .hex_digit()
+                    .ok_or("Expected two hex digits after \\x")?;
+                let lo = self
+                    .hex_digit()
+                    .ok_or("Expected two hex digits after \\x")?;
+                match hi * 16 + lo {
+                    code @ 0..=0x7f => Ok(code as u8 as char),
+                    _ => Err("\\x escape out of ASCII range"),
+                }
+            }
+            'u' => self.unicode_escape(),
             _ => Err("Unrecognized escape sequence"),
         }
     }
 
+    fn hex_digit(&mut self) -> Option<u32> {
+        let digit = self.ipeek()?.to_digit(16)?;
+        self.step();
+        Some(digit)
+    }
+
+    /// The part of a `\u{...}` escape after the `u`.
+    fn unicode_escape(&mut self) -> Result<char, &'static str> {
+        if self.ipeek() != Some('{') {
+            return Err("Expected { after \\u");
+        }
+        self.step();
+        let mut code: u32 = 0;
+        let mut digits = 0;
+        while let Some(digit) = self.hex_digit() {
+            code = code * 16 + digit;
+            digits += 1;
+            if digits > 6 {
+                return Err("Too many digits in \\u escape");
+            }
+        }
+        if digits == 0 || self.ipeek() != Some('}') {
+            return Err("Expected hex digits and } in \\u escape");
+        }
+        self.step();
+        std::char::from_u32(code).ok_or("\\u escape is not a Unicode scalar value")
+    }
+
     fn step_while(&mut self, start: usize, prop: fn(char) -> bool) -> &'sub str {
         assert!(prop(self.input[start..].chars().next().unwrap()));
         loop {
@@ -126,36 +213,112 @@ impl<'sub, 's: 'sub> Lex<'s> {
         }
     }
 
-    fn parse_int(&mut self, start: usize) -> Result<i64, ParseIntError> {
-        self.step_while(start, |c| c.is_digit(10)).parse()
+    fn parse_int(&mut self, first: char) -> Result<i64, &'static str> {
+        let radix = match (first, self.ipeek()) {
+            ('0', Some('x')) | ('0', Some('X')) => 16,
+            ('0', Some('b')) | ('0', Some('B')) => 2,
+            _ => 10,
+        };
+        let mut digits = String::new();
+        if radix == 10 {
+            digits.push(first);
+        } else {
+            self.step();
+        }
+        // Underscores may only separate digits, not follow the radix prefix,
+        // another underscore or come last.
+        let mut after_digit = radix == 10;
+        let mut misplaced = false;
+        while let Some(c) = self.ipeek() {
+            if c.is_digit(radix) {
+                digits.push(c);
+                after_digit = true;
+            } else if c == '_' {
+                misplaced |= !after_digit;
+                after_digit = false;
+            } else {
+                break;
+            }
+            self.step();
+        }
+        if misplaced || (!after_digit && !digits.is_empty()) {
+            return Err("Underscores in integer literals must be between digits");
+        }
+        if digits.is_empty() {
+            return Err("Integer literal without digits");
+        }
+        u64::from_str_radix(&digits, radix)
+            .ok()
+            .and_then(|val| self.width.literal(val, radix == 10, self.after_minus))
+            .ok_or("Integer literal too large")
     }
 
     fn parse_word(&mut self, start: usize) -> Token {
-        let word = self.step_while(start, char::is_alphanumeric);
-        let names = &mut self.names;
-        let vcount = &mut self.vcount;
-        let wordtoks = &mut self.wordtoks; // pacify the borrow checker
-        *wordtoks.entry(word).or_insert_with(|| {
-            names.push(word);
-            *vcount += 1;
-            crate::parser::lex::Token::IdTok(*vcount - 1)
-        })
+        let word = Symbol::intern(self.step_while(start, is_word_char));
+        if word.is_keyword() {
+            if let Some(&(_, tok)) = KEYWORDS.iter().find(|&&(kw, _)| kw == word) {
+                return tok;
+            }
+        }
+        Token::IdTok(BareId(word))
     }
     fn parse_char(&mut self) -> Result<LocTok, &'static str> {
-        Ok(match self.step_ch() {
-            Some('\n') | None => return Err("\' at end of line"),
-            Some('\\') => Char(self.escape_char()?).to_ltok(self.loc),
-            Some(x) => Char(x).to_ltok(self.loc),
-        })
+        let c = match self.ipeek() {
+            Some('\n') | Some('\r') | None => return Err("Unterminated character literal"),
+            Some('\'') => {
+                self.step();
+                return Err("Empty character literal");
+            }
+            Some('\\') => {
+                self.step();
+                self.escape_char()?
+            }
+            Some(x) => {
+                self.step();
+                x
+            }
+        };
+        // Find the closing quote, to report the whole literal.
+        let mut long = false;
+        loop {
+            match self.ipeek() {
+                Some('\n') | Some('\r') | None => return Err("Unterminated character literal"),
+                Some('\'') => break,
+                Some(_) => long = true,
+            }
+            self.step();
+        }
+        self.step();
+        if long {
+            return Err("Character literal with more than one character");
+        }
+        Ok(Char(c).to_ltok(self.loc))
+    }
+
+    fn parse_string(&mut self) -> Result<LocTok, &'static str> {
+        let mut string = String::new();
+        loop {
+            if let Some('\n') | Some('\r') | None = self.ipeek() {
+                return Err("Unterminated string literal");
+            }
+            match self.step_ch().unwrap() {
+                '"' => break,
+                '\\' => string.push(self.escape_char()?),
+                c => string.push(c),
+            }
+        }
+        self.strings.push(string);
+        Ok((Token::StrLit(self.strings.len() as u32 - 1), self.loc))
     }
 
+    /// Skip to the end of the line: `\n`, `\r\n` and a lone `\r` all end one.
     fn line_comment(&mut self) {
-        for (_, c) in &mut self.chars {
-            if c == '\n' {
+        while let Some(c) = self.ipeek() {
+            if c == '\n' || c == '\r' {
                 break;
             };
+            self.step();
         }
-        self.loc.next_line();
     }
 
     fn rec_block_comment(&mut self) -> Option<()> {
@@ -173,7 +336,6 @@ impl<'sub, 's: 'sub> Lex<'s> {
                         self.rec_block_comment()?;
                     }
                 }
-                '\n' => self.loc.next_line(),
                 _ => (),
             }
         }
@@ -183,6 +345,15 @@ impl<'sub, 's: 'sub> Lex<'s> {
         let startloc = self.loc;
         self.rec_block_comment().ok_or(startloc)
     }
+
+    /// The trivia just consumed as a token when lossless, else the next token.
+    fn trivia(&mut self, kind: Trivia) -> Option<Result<LocTok, LexError>> {
+        if self.lossless {
+            Some(Ok((Token::Trivia(kind), self.loc)))
+        } else {
+            self.token()
+        }
+    }
 }
 
 macro_rules! fail {
@@ -199,6 +370,31 @@ impl Iterator for Lex<'_> {
             self.peeked = None;
             return val_copy;
         }
+        let next = self.token();
+        match next {
+            Some(Ok((Token::Trivia(_), _))) => (),
+            Some(Ok((tok, _))) => {
+                self.after_minus = tok == Token::Op(Minus) && !self.after_operand;
+                self.after_operand = matches!(
+                    tok,
+                    Token::IdTok(_)
+                        | Token::Selector(_)
+                        | Token::Lit(_)
+                        | Token::StrLit(_)
+                        | Token::Marker(ParenClose)
+                );
+            }
+            _ => {
+                self.after_minus = false;
+                self.after_operand = false;
+            }
+        }
+        next
+    }
+}
+
+impl Lex<'_> {
+    fn token(&mut self) -> Option<Result<LocTok, LexError>> {
         self.loc.advance();
         let (pos, chr) = self.step()?;
         Some(Ok(match chr {
@@ -211,7 +407,7 @@ impl Iterator for Lex<'_> {
             ']' => BrackClose.to_ltok(self.loc),
             ',' => Comma.to_ltok(self.loc),
             '.' => Dot.to_ltok(self.loc),
-            '%' => Div.to_ltok(self.loc),
+            '%' => Mod.to_ltok(self.loc),
             '*' => Mul.to_ltok(self.loc),
             '[' => match self.ipeek() {
                 Some(']') => {
@@ -273,32 +469,52 @@ impl Iterator for Lex<'_> {
                 Ok(c) => c,
                 Err(msg) => fail!(msg, self.loc),
             },
-            '-' => match self.chars.peek().copied() {
-                Some((_, '>')) => Arrow.to_ltok(self.loc),
+            '"' => match self.parse_string() {
+                Ok(s) => s,
+                Err(msg) => fail!(msg, self.loc),
+            },
+            '-' => match self.ipeek() {
+                Some('>') => {
+                    self.step();
+                    Arrow.to_ltok(self.loc)
+                }
                 _ => Minus.to_ltok(self.loc),
             },
-            '/' => match self.step_ch() {
+            '/' => match self.ipeek() {
                 Some('/') => {
+                    self.step();
                     self.line_comment();
-                    return self.next();
+                    return self.trivia(Trivia::LineComment);
+                }
+                Some('*') => {
+                    self.step();
+                    match self.block_comment() {
+                        Err(l) => fail!("Unclosed block comment started", l),
+                        Ok(()) => return self.trivia(Trivia::BlockComment),
+                    }
                 }
-                Some('*') => match self.block_comment() {
-                    Err(l) => fail!("Unclosed block comment started", l),
-                    Ok(()) => return self.next(),
-                },
-                _ => fail!("Found lone /", self.loc),
+                _ => Div.to_ltok(self.loc),
             },
-            '\n' => {
-                self.loc.next_line();
-                return self.next();
-            }
             x => {
-                if x.is_alphabetic() {
+                if x.is_ascii_alphabetic() {
                     (self.parse_word(pos), self.loc)
+                } else if x.is_alphabetic() {
+                    fail!("Identifiers must be ASCII", self.loc)
+                } else if x == '_' {
+                    fail!("Identifiers must start with a letter", self.loc)
                 } else if x.is_digit(10) {
-                    Int(self.parse_int(pos).unwrap()).to_ltok(self.loc)
+                    match self.parse_int(x) {
+                        Ok(n) => Int(n).to_ltok(self.loc),
+                        Err(msg) => fail!(msg, self.loc),
+                    }
                 } else if x.is_whitespace() {
-                    return self.next();
+                    while let Some(c) = self.ipeek() {
+                        if !c.is_whitespace() {
+                            break;
+                        }
+                        self.step();
+                    }
+                    return self.trivia(Trivia::Whitespace);
                 } else {
                     fail!("Unrecognized character", self.loc)
                 }
@@ -310,11 +526,11 @@ impl Iterator for Lex<'_> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    fn tloc(line: u32, col: u16, len: u16) -> Loc {
+    fn tloc(start: u32, len: u32) -> Loc {
         Loc {
-            line: line,
-            col: col,
-            len: len,
+            file: FileId(0),
+            start,
+            len,
         }
     }
 
@@ -326,7 +542,7 @@ mod tests {
     #[test]
     fn lex_plus() {
         let mut toks = Lex::lex("+");
-        assert_eq!(toks.next(), Some(Ok((Token::Op(Plus), tloc(0, 0, 1)))));
+        assert_eq!(toks.next(), Some(Ok((Token::Op(Plus), tloc(0, 1)))));
     }
     #[test]
     fn lex_comma() {
@@ -359,9 +575,21 @@ mod tests {
     #[test]
     fn lex_lone_slash() {
         let mut toks = Lex::lex("/");
+        assert_eq!(toks.next().unwrap(), Ok((Token::Op(Div), tloc(0, 1))));
+    }
+
+    #[test]
+    fn lex_div_mod() {
+        let toks: Vec<_> = Lex::lex("7/2%3 /*c*/ //c").map(|x| x.unwrap()).collect();
         assert_eq!(
-            toks.next().unwrap(),
-            Err(LexError("Found lone /".to_string(), tloc(0, 0, 1)))
+            toks,
+            vec![
+                (Token::Lit(Int(7)), tloc(0, 1)),
+                (Token::Op(Div), tloc(1, 1)),
+                (Token::Lit(Int(2)), tloc(2, 1)),
+                (Token::Op(Mod), tloc(3, 1)),
+                (Token::Lit(Int(3)), tloc(4, 1)),
+            ]
         );
     }
 
@@ -370,16 +598,132 @@ mod tests {
         let mut toks = Lex::lex("// This should not lex as anything\n+");
         assert_eq!(
             toks.next().unwrap().unwrap(),
-            (Token::Op(Plus), tloc(1, 0, 1))
+            (Token::Op(Plus), tloc(35, 1))
+        );
+    }
+
+    #[test]
+    fn lex_string() {
+        let mut lexer = Lex::lex(r#""hi \"there\"\n" """#);
+        assert_eq!(lexer.next().unwrap(), Ok((Token::StrLit(0), tloc(0, 16))));
+        assert_eq!(lexer.next().unwrap(), Ok((Token::StrLit(1), tloc(17, 2))));
+        assert_eq!(lexer.strings, vec!["hi \"there\"\n", ""]);
+        let mut lexer = Lex::lex("\"abc\ndef\"");
+        assert_eq!(
+            lexer.next().unwrap(),
+            Err(LexError(
+                "Unterminated string literal".to_string(),
+                tloc(0, 4)
+            ))
+        );
+    }
+
+    #[test]
+    fn lex_line_endings() {
+        for &source in ["// c\r\n+", "// c\r+", "// c\n+"].iter() {
+            let toks: Vec<_> = Lex::lex(source).collect();
+            let start = source.len() as u32 - 1;
+            assert_eq!(
+                toks,
+                vec![Ok((Token::Op(Plus), tloc(start, 1)))],
+                "{:?}",
+                source
+            );
+        }
+        assert_eq!(
+            Lex::lex("\"ab\r\n\"").next().unwrap(),
+            Err(LexError(
+                "Unterminated string literal".to_string(),
+                tloc(0, 3)
+            ))
+        );
+        assert_eq!(
+            Lex::lex("'a\r'").next().unwrap(),
+            Err(LexError(
+                "Unterminated character literal".to_string(),
+                tloc(0, 2)
+            ))
         );
     }
 
     #[test]
     fn lex_char() {
-        let mut toks = Lex::lex("'a");
+        let mut toks = Lex::lex("'a'");
         assert_eq!(
             toks.next().unwrap().unwrap(),
-            (Token::Lit(Char('a')), tloc(0, 0, 2))
+            (Token::Lit(Char('a')), tloc(0, 3))
+        );
+    }
+
+    fn lex_one(source: &str) -> Result<Token, LexError> {
+        Lex::lex(source).next().unwrap().map(|x| x.0)
+    }
+
+    fn lex_err(source: &str) -> String {
+        lex_one(source).unwrap_err().0
+    }
+
+    #[test]
+    fn lex_char_escapes() {
+        let cases = [
+            (r"'\n'", '\n'),
+            (r"'\t'", '\t'),
+            (r"'\r'", '\r'),
+            (r"'\0'", '\0'),
+            (r"'\\'", '\\'),
+            (r"'\''", '\''),
+            (r#"'\"'"#, '"'),
+            (r#"'"'"#, '"'),
+            (r"'\x41'", 'A'),
+            (r"'\u{e9}'", '\u{e9}'),
+            (r"'\u{1F600}'", '\u{1F600}'),
+            ("'\u{e9}'", '\u{e9}'),
+        ];
+        for &(source, c) in cases.iter() {
+            assert_eq!(lex_one(source), Ok(Token::Lit(Char(c))), "{}", source);
+        }
+    }
+
+    #[test]
+    fn lex_char_unterminated() {
+        assert_eq!(
+            Lex::lex("'a").next().unwrap(),
+            Err(LexError(
+                "Unterminated character literal".to_string(),
+                tloc(0, 2)
+            ))
+        );
+        assert_eq!(lex_err("'a\n'"), "Unterminated character literal");
+        assert_eq!(lex_err("'"), "Unterminated character literal");
+        assert_eq!(lex_err("'\\"), "\\ at end of line");
+    }
+
+    #[test]
+    fn lex_char_empty_or_long() {
+        assert_eq!(lex_err("''"), "Empty character literal");
+        assert_eq!(
+            Lex::lex("'ab' x").next().unwrap(),
+            Err(LexError(
+                "Character literal with more than one character".to_string(),
+                tloc(0, 4)
+            ))
+        );
+    }
+
+    #[test]
+    fn lex_char_bad_escapes() {
+        assert_eq!(lex_err(r"'\q'"), "Unrecognized escape sequence");
+        assert_eq!(lex_err(r"'\x4'"), "Expected two hex digits after \\x");
+        assert_eq!(lex_err(r"'\x80'"), "\\x escape out of ASCII range");
+        assert_eq!(lex_err(r"'\u41'"), "Expected { after \\u");
+        assert_eq!(
+            lex_err(r"'\u{}'"),
+            "Expected hex digits and } in \\u escape"
+        );
+        assert_eq!(lex_err(r"'\u{1234567}'"), "Too many digits in \\u escape");
+        assert_eq!(
+            lex_err(r"'\u{d800}'"),
+            "\\u escape is not a Unicode scalar value"
         );
     }
 
@@ -388,30 +732,285 @@ mod tests {
     #[test]
     fn lex_negnum() {
         let mut toks = Lex::lex("-42").map(|x| x.unwrap());
-        assert_eq!(toks.next().unwrap(), (Token::Op(Minus), tloc(0, 0, 1)));
-        assert_eq!(toks.next().unwrap(), (Token::Lit(Int(42)), tloc(0, 1, 2)));
+        assert_eq!(toks.next().unwrap(), (Token::Op(Minus), tloc(0, 1)));
+        assert_eq!(toks.next().unwrap(), (Token::Lit(Int(42)), tloc(1, 2)));
         assert_eq!(toks.next(), None);
+        let min: Vec<_> = Lex::lex("- 9223372036854775808")
+            .map(|x| x.unwrap().0)
+            .collect();
+        assert_eq!(min, vec![Token::Op(Minus), Token::Lit(Int(i64::MIN))]);
+        let too_large = Err(LexError(
+            "Integer literal too large".to_string(),
+            tloc(2, 19),
+        ));
+        assert_eq!(Lex::lex("+ 9223372036854775808").nth(1), Some(too_large));
+        // After an operand the minus is binary, so the literal stands alone.
+        for source in &["x - 9223372036854775808", "(x) - 9223372036854775808"] {
+            assert!(Lex::lex(source).any(|tok| tok.is_err()), "{}", source);
+        }
+        let min: Vec<_> = Lex::lex("x - -9223372036854775808")
+            .map(|x| x.unwrap().0)
+            .collect();
+        assert_eq!(min[3], Token::Lit(Int(i64::MIN)));
     }
 
     #[test]
-    fn lex_vars() {
-        let mut lexer = Lex::lex("foo while b4r foo");
-        let mut toks = lexer.by_ref().map(|x| x.unwrap());
-        let footok = toks.next().unwrap();
-        let foo = match footok.0 {
-            Token::IdTok(x) => x,
-            _ => panic!(),
+    fn lex_int_syntax() {
+        let ints: Vec<_> = Lex::lex("1_000 0x7f 0XFF_FF 0b101 007")
+            .map(|x| x.unwrap().0)
+            .collect();
+        let expected = [1000, 0x7f, 0xffff, 0b101, 7];
+        assert_eq!(
+            ints,
+            expected
+                .iter()
+                .map(|&n| Token::Lit(Int(n)))
+                .collect::<Vec<_>>()
+        );
+        let misplaced = |len| {
+            Err(LexError(
+                "Underscores in integer literals must be between digits".to_string(),
+                tloc(0, len),
+            ))
+        };
+        assert_eq!(Lex::lex("1_").next().unwrap(), misplaced(2));
+        assert_eq!(Lex::lex("1__0").next().unwrap(), misplaced(4));
+        assert_eq!(Lex::lex("0x_").next().unwrap(), misplaced(3));
+        assert_eq!(Lex::lex("0b_1").next().unwrap(), misplaced(4));
+        assert_eq!(Lex::lex("1_x").next().unwrap(), misplaced(2));
+    }
+
+    #[test]
+    fn lex_int_too_large() {
+        let mut toks = Lex::lex("99999999999999999999");
+        assert_eq!(
+            toks.next().unwrap(),
+            Err(LexError(
+                "Integer literal too large".to_string(),
+                tloc(0, 20)
+            ))
+        );
+        let mut toks = Lex::lex("0x");
+        assert_eq!(
+            toks.next().unwrap(),
+            Err(LexError(
+                "Integer literal without digits".to_string(),
+                tloc(0, 2)
+            ))
+        );
+    }
+
+    #[test]
+    fn lex_int_width() {
+        let lex = |src| {
+            Lex::lex_file(FileId(0), src, IntWidth::W32)
+                .next()
+                .unwrap()
+                .map(|x| x.0)
         };
-        assert_eq!(footok, (Token::IdTok(foo), tloc(0, 0, 3)));
-        assert_eq!(toks.next().unwrap(), (Token::Marker(While), tloc(0, 4, 5)));
+        assert_eq!(lex("2147483647"), Ok(Token::Lit(Int(i32::MAX as i64))));
+        assert!(lex("2147483648").is_err());
+        assert_eq!(lex("0x8000_0000"), Ok(Token::Lit(Int(i32::MIN as i64))));
+        assert_eq!(lex("0xffffffff"), Ok(Token::Lit(Int(-1))));
+        assert!(lex("0x1_0000_0000").is_err());
+        let lex = |src| Lex::lex(src).next().unwrap().map(|x| x.0);
+        assert_eq!(lex("9223372036854775807"), Ok(Token::Lit(Int(i64::MAX))));
+        assert!(lex("9223372036854775808").is_err());
+        assert_eq!(lex("0xffff_ffff_ffff_ffff"), Ok(Token::Lit(Int(-1))));
+    }
+
+    #[test]
+    fn lex_vars() {
+        let mut toks = Lex::lex("foo while b4r foo").map(|x| x.unwrap());
+        let foo = Token::IdTok(BareId::intern("foo"));
+        assert_eq!(toks.next().unwrap(), (foo, tloc(0, 3)));
+        assert_eq!(toks.next().unwrap(), (Token::Marker(While), tloc(4, 5)));
         assert_eq!(
             toks.next().unwrap(),
-            (Token::IdTok(foo + 1), tloc(0, 10, 3))
+            (Token::IdTok(BareId::intern("b4r")), tloc(10, 3))
         );
-        assert_eq!(toks.next().unwrap(), (Token::IdTok(foo), tloc(0, 14, 3)));
+        assert_eq!(toks.next().unwrap(), (foo, tloc(14, 3)));
         assert_eq!(toks.next(), None);
-        assert_eq!(lexer.names[foo as usize], "foo");
-        assert_eq!(lexer.names[(foo + 1) as usize], "b4r");
+    }
+
+    #[test]
+    fn lex_underscores() {
+        let toks: Vec<_> = Lex::lex("my_var x_1_ while_ tl_ iF")
+            .map(|x| x.unwrap())
+            .collect();
+        assert_eq!(toks[0].1, tloc(0, 6));
+        let names: Vec<_> = toks
+            .iter()
+            .map(|tok| match tok.0 {
+                Token::IdTok(id) => id.name(),
+                _ => panic!("Expected an identifier, got {:?}", tok.0),
+            })
+            .collect();
+        assert_eq!(names, vec!["my_var", "x_1_", "while_", "tl_", "iF"]);
+    }
+
+    #[test]
+    fn lex_bad_identifiers() {
+        assert_eq!(
+            Lex::lex("_x").next().unwrap(),
+            Err(LexError(
+                "Identifiers must start with a letter".to_string(),
+                tloc(0, 1)
+            ))
+        );
+        assert_eq!(
+            Lex::lex("ünï").next().unwrap(),
+            Err(LexError(
+                "Identifiers must be ASCII".to_string(),
+                tloc(0, 2)
+            ))
+        );
+        let mut toks = Lex::lex("caf\u{e9}");
+        assert!(matches!(toks.next().unwrap(), Ok((Token::IdTok(_), _))));
+        assert_eq!(
+            toks.next().unwrap(),
+            Err(LexError(
+                "Identifiers must be ASCII".to_string(),
+                tloc(3, 2)
+            ))
+        );
+        // Fresh names, which the optimiser makes up, cannot be written.
+        let fresh = BareId::fresh("x");
+        let mut toks = Lex::lex(fresh.name());
+        assert!(matches!(toks.next().unwrap(), Ok((Token::IdTok(_), _))));
+        assert!(toks.next().unwrap().is_err());
+    }
+
+    #[test]
+    fn keywords_round_trip() {
+        for &(word, tok) in KEYWORDS {
+            assert_eq!(Lex::lex(word.as_str()).next().unwrap().unwrap().0, tok);
+            assert_eq!(keyword(tok), Some(word.as_str()));
+        }
+        assert_eq!(keyword(Token::Marker(Semicolon)), None);
+    }
+
+    /// Every token in the `keys:` section of grammar.txt, with its spelling.
+    fn conformance_table() -> Vec<(&'static str, Token)> {
+        use Token::*;
+        vec![
+            ("var", Marker(Var)),
+            ("Void", TypeTok(UnitT)),
+            ("Int", TypeTok(IntT)),
+            ("Bool", TypeTok(BoolT)),
+            ("Char", TypeTok(CharT)),
+            ("if", Marker(If)),
+            ("else", Marker(Else)),
+            ("while", Marker(While)),
+            ("return", Marker(Return)),
+            ("hd", Selector(Hd)),
+            ("tl", Selector(Tl)),
+            ("fst", Selector(Fst)),
+            ("snd", Selector(Snd)),
+            ("False", Lit(Bool(false))),
+            ("True", Lit(Bool(true))),
+            (";", Marker(Semicolon)),
+            ("(", Marker(ParenOpen)),
+            (")", Marker(ParenClose)),
+            ("{", Marker(BraceOpen)),
+            ("}", Marker(BraceClose)),
+            ("]", Marker(BrackClose)),
+            (",", Marker(Comma)),
+            (".", Marker(Dot)),
+            ("&&", Op(And)),
+            ("||", Op(Or)),
+            ("+", Op(Plus)),
+            ("*", Op(Mul)),
+            ("%", Op(Mod)),
+            ("<", Op(Lt)),
+            ("<=", Op(Leq)),
+            (">", Op(Gt)),
+            (">=", Op(Geq)),
+            ("!", Op(Not)),
+            ("!=", Op(Neq)),
+            ("=", Marker(Assign)),
+            ("==", Op(Eq)),
+            ("[", Marker(BrackOpen)),
+            ("[]", Lit(Nil)),
+            (":", Op(Cons)),
+            ("::", Marker(TypeColon)),
+            ("-", Op(Minus)),
+            ("->", Marker(Arrow)),
+            ("/", Op(Div)),
+        ]
+    }
+
+    #[test]
+    fn conformance_single() {
+        for (source, tok) in conformance_table() {
+            let toks: Vec<_> = Lex::lex(source).collect();
+            let len = source.len() as u32;
+            assert_eq!(toks, vec![Ok((tok, tloc(0, len)))], "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn conformance_sequence() {
+        let table = conformance_table();
+        let source = table
+            .iter()
+            .map(|&(source, _)| source)
+            .collect::<Vec<_>>()
+            .join(" \n ");
+        let mut expected = Vec::new();
+        let mut start = 0;
+        for &(source, tok) in table.iter() {
+            expected.push(Ok((tok, tloc(start, source.len() as u32))));
+            start += source.len() as u32 + " \n ".len() as u32;
+        }
+        assert_eq!(Lex::lex(&source).collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn conformance_adjacent() {
+        use Token::*;
+        let toks: Vec<_> = Lex::lex("a->b-->c<==d").map(|x| x.unwrap()).collect();
+        let id = |name| IdTok(BareId::intern(name));
+        assert_eq!(
+            toks,
+            vec![
+                (id("a"), tloc(0, 1)),
+                (Marker(Arrow), tloc(1, 2)),
+                (id("b"), tloc(3, 1)),
+                (Op(Minus), tloc(4, 1)),
+                (Marker(Arrow), tloc(5, 2)),
+                (id("c"), tloc(7, 1)),
+                (Op(Leq), tloc(8, 2)),
+                (Marker(Assign), tloc(10, 1)),
+                (id("d"), tloc(11, 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn lex_lossless() {
+        use Token::*;
+        let source = "x // c\r\n\t/* a /* b */ */1";
+        let toks: Vec<_> = Lex::lossless(FileId(0), source, IntWidth::W64)
+            .map(|x| x.unwrap())
+            .collect();
+        let kinds: Vec<_> = toks.iter().map(|tok| tok.0).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                IdTok(BareId::intern("x")),
+                Trivia(super::Trivia::Whitespace),
+                Trivia(super::Trivia::LineComment),
+                Trivia(super::Trivia::Whitespace),
+                Trivia(super::Trivia::BlockComment),
+                Lit(Int(1)),
+            ]
+        );
+        let text: String = toks
+            .iter()
+            .map(|(_, loc)| &source[loc.start as usize..(loc.start + loc.len) as usize])
+            .collect();
+        assert_eq!(text, source);
     }
 
     #[test]
@@ -419,23 +1018,23 @@ mod tests {
         let mut lexer = Lex::lex("1 2");
         assert_eq!(
             lexer.peek().unwrap().as_ref().unwrap().to_owned(),
-            (Token::Lit(Int(1)), tloc(0, 0, 1))
+            (Token::Lit(Int(1)), tloc(0, 1))
         );
         assert_eq!(
             lexer.peek().unwrap().as_ref().unwrap().to_owned(),
-            (Token::Lit(Int(1)), tloc(0, 0, 1))
+            (Token::Lit(Int(1)), tloc(0, 1))
         );
         assert_eq!(
             lexer.next().unwrap().unwrap(),
-            (Token::Lit(Int(1)), tloc(0, 0, 1))
+            (Token::Lit(Int(1)), tloc(0, 1))
         );
         assert_eq!(
             lexer.peek().unwrap().as_ref().unwrap().to_owned(),
-            (Token::Lit(Int(2)), tloc(0, 2, 1))
+            (Token::Lit(Int(2)), tloc(2, 1))
         );
         assert_eq!(
             lexer.next().unwrap().unwrap(),
-            (Token::Lit(Int(2)), tloc(0, 2, 1))
+            (Token::Lit(Int(2)), tloc(2, 1))
         );
     }
 
@@ -444,8 +1043,8 @@ mod tests {
         use Token::Lit;
         use Token::Op;
         let mut l = Lex::lex("3+2");
-        assert_eq!(l.next().unwrap().unwrap(), (Lit(Int(3)), tloc(0, 0, 1)));
-        assert_eq!(l.next().unwrap().unwrap(), (Op(Plus), tloc(0, 1, 1)));
-        assert_eq!(l.next().unwrap().unwrap(), (Lit(Int(2)), tloc(0, 2, 1)));
+        assert_eq!(l.next().unwrap().unwrap(), (Lit(Int(3)), tloc(0, 1)));
+        assert_eq!(l.next().unwrap().unwrap(), (Op(Plus), tloc(1, 1)));
+        assert_eq!(l.next().unwrap().unwrap(), (Lit(Int(2)), tloc(2, 1)));
     }
 }
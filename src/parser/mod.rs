@@ -1,3 +1,4 @@
+pub mod cst;
 mod lex;
 mod shunting_yard;
 mod tok;
@@ -5,10 +6,12 @@ mod tok;
 use crate::ast::Selector;
 use crate::ast::Span;
 use crate::ast::*;
+use crate::source::{FileId, SourceMap};
 use lex::Lex;
 use lex::LexError;
 
 use crate::ast::BareExp::Var as EVar;
+use cst::SyntaxKind;
 pub use tok::Loc;
 use tok::Misc::*;
 use tok::Token;
@@ -20,6 +23,8 @@ type TokStream<'s> = lex::Lex<'s>;
 
 pub struct Parser<'s> {
     ts: TokStream<'s>,
+    /// The syntax tree so far, when parsing losslessly.
+    tree: Option<cst::Builder<'s>>,
 }
 
 macro_rules! fail {
@@ -38,7 +43,7 @@ macro_rules! ipe {
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
-struct ParseError(String, Option<tok::Loc>);
+pub struct ParseError(pub String, pub Option<tok::Loc>);
 
 impl From<LexError> for ParseError {
     fn from(err: LexError) -> Self {
@@ -77,18 +82,103 @@ fn lexfail<T>(err: LexError) -> ParseResult<T> {
     Err(ParseError::from(err))
 }
 
+/// Parse `file` into both its AST and its lossless syntax tree.
+pub fn parse_lossless(
+    map: &SourceMap,
+    file: FileId,
+    width: IntWidth,
+) -> ParseResult<(SPL, cst::SyntaxNode)> {
+    let source = map.source(file);
+    let mut parser = Parser {
+        ts: Lex::lossless(file, source, width),
+        tree: Some(cst::Builder::new(source)),
+    };
+    let spl = parser.spl()?;
+    let root = parser.tree.unwrap().finish(file);
+    Ok((spl, root))
+}
+
+/// The AST of a syntax tree, read off its nodes and tokens.
+pub fn ast_of(root: &cst::SyntaxNode, width: IntWidth) -> ParseResult<SPL> {
+    cst::lower(root, width)
+}
+
+/// Desugar a string literal into a list of characters.
+fn string(text: &str, span: Span) -> Exp {
+    use BareExp::*;
+    let nil = ((Lit(LitVal::Nil), None), Some(span));
+    text.chars().rev().fold(nil, |tail, c| {
+        let head = ((Lit(LitVal::Char(c)), None), Some(span));
+        let op = (BareOp::Cons, Some(span));
+        (
+            (BinOp(op, Box::new(head), Box::new(tail)), None),
+            Some(span),
+        )
+    })
+}
+
+fn decl_kind(decl: &BareDecl) -> SyntaxKind {
+    match decl {
+        BareDecl::Global(_) => SyntaxKind::VarDecl,
+        BareDecl::Fun(..) => SyntaxKind::FunDecl,
+    }
+}
+
+fn stmt_kind(stmt: &BareStmt) -> SyntaxKind {
+    match stmt {
+        BareStmt::ITE(..) => SyntaxKind::IfStmt,
+        BareStmt::While(..) => SyntaxKind::WhileStmt,
+        BareStmt::Assign(..) => SyntaxKind::AssignStmt,
+        BareStmt::Call(..) => SyntaxKind::CallStmt,
+        BareStmt::Ret(_) => SyntaxKind::ReturnStmt,
+        BareStmt::Local(_) => SyntaxKind::LocalStmt,
+    }
+}
+
 impl<'s> Parser<'s> {
     fn new(source: &'s str) -> Self {
         Self {
             ts: Lex::lex(source),
+            tree: None,
+        }
+    }
+
+    pub fn for_file(map: &'s SourceMap, file: FileId, width: IntWidth) -> Self {
+        Self {
+            ts: Lex::lex_file(file, map.source(file), width),
+            tree: None,
+        }
+    }
+
+    /// Move the trivia before the next token into the syntax tree.
+    fn skip_trivia(&mut self) {
+        if let Some(tree) = &mut self.tree {
+            while let Some(Ok((Trivia(_), _))) = self.ts.peek() {
+                if let Some(Ok(trivia)) = self.ts.next() {
+                    tree.trivia(trivia);
+                }
+            }
+        }
+    }
+
+    /// Make the tokens from the start of `span` on a node of the syntax tree.
+    fn node(&mut self, kind: SyntaxKind, span: Option<Span>) {
+        if let (Some(tree), Some(span)) = (&mut self.tree, span) {
+            tree.node(kind, span.start);
         }
     }
 
     fn nexttok(&mut self) -> Option<<Lex as Iterator>::Item> {
-        self.ts.next()
+        self.skip_trivia();
+        let next = self.ts.next();
+        if let (Some(tree), Some(Ok(loctok))) = (&mut self.tree, &next) {
+            tree.token(*loctok);
+        }
+        next
     }
 
     fn peektok(&mut self) -> ParseResult<Option<&tok::LocTok>> {
+        self.skip_trivia();
         match self.ts.peek() {
             None => Ok(None),
             Some(Err(e)) => lexfail(e.to_owned()),
@@ -97,11 +187,14 @@ impl<'s> Parser<'s> {
     }
 
     fn trytok(&mut self) -> ParseResult<Option<tok::LocTok>> {
-        self.ts.next().transpose().map_err(ParseError::from)
+        self.nexttok().transpose().map_err(ParseError::from)
         //.map_err(|(msg, loc)| (msg, Some(loc)))
     }
 
     fn unpeektok(&mut self, val: tok::LocTok) -> ParseResult<&tok::LocTok> {
+        if let Some(tree) = &mut self.tree {
+            tree.untoken();
+        }
         self.ts
             .unpeek(val)
             .map_err(|_| ipe!("Attempted lookahead beyond 1"))
@@ -126,6 +219,16 @@ impl<'s> Parser<'s> {
         }
     }
 
+    /// A whole program: declarations up to the end of the input.
+    pub fn spl(&mut self) -> ParseResult<SPL> {
+        let mut decls = Vec::new();
+        while let Some(decl) = self.decl()? {
+            self.node(decl_kind(&decl.0), decl.1);
+            decls.push(decl);
+        }
+        Ok(decls)
+    }
+
     fn decl(&mut self) -> ParseResult<Option<Decl>> {
         use crate::ast::BareDecl::*;
         match self.trytok()? {
@@ -156,64 +259,261 @@ impl<'s> Parser<'s> {
     }
 
     fn fun_or_named_type_var_decl(&mut self, id: Id) -> ParseResult<Decl> {
-        todo!();
-    }
-    /*
-    fn fun_or_named_type_var_decl(ts: &mut TokStream) -> ParseResult<Decl> {
-        match ts.next(){
-            None => fail!("Ran out of tokens while parsing declaration with named type"),
-            Some(Ok((tok,loc))) => unimplemented!(), // parse
-            Some(Err(e)) => unimplemented!() //unexpected!(),
+        use crate::ast::BareDecl::*;
+        match self.peektok()? {
+            None => eof("'(' or identifier".to_string()),
+            Some(&(Marker(ParenOpen), _)) => self.fun_def(id),
+            Some(&(IdTok(_), _)) => {
+                let typ = (BareType::Typename(id), id.1);
+                self.node(SyntaxKind::Type, typ.1);
+                let (name, exp, end) = self.var_init()?;
+                Ok((
+                    Global((Some(typ), name, exp)),
+                    opthull(id.1, Some(end.into())),
+                ))
+            }
+            Some(&found) => unexpected(found, "'(' or identifier".to_string()),
+        }
+    }
+
+    /// An identifier, with a dedicated error for keywords in its place.
+    fn ident(&mut self) -> ParseResult<Id> {
+        match self.trytok()? {
+            None => eof("identifier".to_string()),
+            Some((IdTok(id), loc)) => Ok((id, Some(loc.into()))),
+            Some((tok, loc)) => {
+                self.unpeektok((tok, loc))?;
+                match lex::keyword(tok) {
+                    Some(word) => fail!(format!("'{}' is a keyword, not an identifier", word), loc),
+                    None => unexpected((tok, loc), "identifier".to_string()),
+                }
+            }
         }
     }
-    */
 
     fn var_init(&mut self) -> ParseResult<(Id, Exp, Loc)> {
-        todo!();
+        let id = self.ident()?;
+        self.expect(Marker(Assign))?;
+        let exp = self.exp()?;
+        let (_, end) = self.expect(Marker(Semicolon))?;
+        Ok((id, exp, end))
     }
 
     fn fun_def(&mut self, id: Id) -> ParseResult<Decl> {
-        todo!();
+        let (params, _) = self.tuplish(Self::ident)?;
+        let typ = match self.peektok()? {
+            Some(&(Marker(TypeColon), _)) => {
+                self.nexttok();
+                Some(self.fun_type()?)
+            }
+            _ => None,
+        };
+        let (body, end) = self.compound()?;
+        Ok((
+            BareDecl::Fun(id, params, typ, body),
+            opthull(id.1, Some(end)),
+        ))
     }
 
     fn ret_type(&mut self) -> ParseResult<Type> {
-        todo!();
+        self.typ()
     }
 
     fn fun_type(&mut self) -> ParseResult<FunType> {
-        todo!();
+        let mut args: Vec<Type> = Vec::new();
+        let arrow = loop {
+            match self.peektok()? {
+                None => return eof("type or '->'".to_string()),
+                Some(&(Marker(Arrow), loc)) => break loc,
+                Some(_) => args.push(self.typ()?),
+            }
+        };
+        self.nexttok();
+        let ret = self.ret_type()?;
+        let start = args.first().map_or(Some(arrow.into()), |arg| arg.1);
+        let span = opthull(start, ret.1);
+        self.node(SyntaxKind::FunType, span);
+        Ok(((args, ret), span))
     }
 
     fn typ(&mut self) -> ParseResult<Type> {
-        todo!();
+        match self.peektok()? {
+            Some(&(IdTok(id), loc)) => {
+                self.nexttok();
+                let span = Some(loc.into());
+                self.node(SyntaxKind::Type, span);
+                Ok((BareType::Typename((id, span)), span))
+            }
+            _ => self.non_id_type(),
+        }
     }
 
     fn non_id_type(&mut self) -> ParseResult<Type> {
-        todo!();
+        let typ = match self.peektok()? {
+            None => return eof("type".to_string()),
+            Some(&(TypeTok(_), loc)) => {
+                let btype = self.b_type()?;
+                (BareType::Lit(btype), Some(loc.into()))
+            }
+            Some(&(Marker(ParenOpen), _)) => {
+                let (elems, span) = self.tuplish(Self::typ)?;
+                if elems.len() == 1 {
+                    (elems.into_iter().next().unwrap().0, Some(span))
+                } else {
+                    (BareType::Tuple(elems), Some(span))
+                }
+            }
+            Some(&(Marker(BrackOpen), loc)) => {
+                self.nexttok();
+                let elem = self.typ()?;
+                let (_, end) = self.expect(Marker(BrackClose))?;
+                (
+                    BareType::List(Box::new(elem)),
+                    Some(hull(loc.into(), end.into())),
+                )
+            }
+            Some(&found) => return unexpected(found, "type".to_string()),
+        };
+        self.node(SyntaxKind::Type, typ.1);
+        Ok(typ)
     }
 
     fn b_type(&mut self) -> ParseResult<BType> {
-        todo!();
+        match self.trytok()? {
+            None => eof("basic type".to_string()),
+            Some((TypeTok(btype), _)) => Ok(btype),
+            Some(found) => self.backtrack(found, "basic type".to_string()),
+        }
     }
 
     fn stmt(&mut self) -> ParseResult<Stmt> {
-        todo!();
+        let (tok, loc) = match self.trytok()? {
+            None => return eof("statement".to_string()),
+            Some(loctok) => loctok,
+        };
+        let start: Span = loc.into();
+        match tok {
+            Marker(If) => {
+                let cond = self.condition()?;
+                let (then, mut end) = self.compound()?;
+                let els = match self.peektok()? {
+                    Some(&(Marker(Else), _)) => {
+                        self.nexttok();
+                        let (els, els_end) = self.compound()?;
+                        end = els_end;
+                        els
+                    }
+                    _ => Vec::new(),
+                };
+                Ok((BareStmt::ITE(cond, then, els), Some(hull(start, end))))
+            }
+            Marker(While) => {
+                let cond = self.condition()?;
+                let (body, end) = self.compound()?;
+                Ok((BareStmt::While(cond, body), Some(hull(start, end))))
+            }
+            Marker(Return) => {
+                let exp = match self.peektok()? {
+                    Some(&(Marker(Semicolon), _)) => None,
+                    _ => Some(self.exp()?),
+                };
+                let (_, end) = self.expect(Marker(Semicolon))?;
+                Ok((BareStmt::Ret(exp), Some(hull(start, end.into()))))
+            }
+            Marker(Var) => {
+                let (id, exp, end) = self.var_init()?;
+                Ok((
+                    BareStmt::Local((None, id, exp)),
+                    Some(hull(start, end.into())),
+                ))
+            }
+            IdTok(id) => self.assign_or_call((id, Some(start))),
+            TypeTok(_) | Marker(ParenOpen) | Marker(BrackOpen) => {
+                self.unpeektok((tok, loc))?;
+                let typ = self.non_id_type()?;
+                let (id, exp, end) = self.var_init()?;
+                Ok((
+                    BareStmt::Local((Some(typ), id, exp)),
+                    Some(hull(start, end.into())),
+                ))
+            }
+            _ => self.backtrack((tok, loc), "statement".to_string()),
+        }
+    }
+
+    /// The parenthesised condition of an `if` or `while`.
+    fn condition(&mut self) -> ParseResult<Exp> {
+        self.expect(Marker(ParenOpen))?;
+        let cond = self.exp()?;
+        self.expect(Marker(ParenClose))?;
+        Ok(cond)
     }
 
     fn assign_or_call(&mut self, id: Id) -> ParseResult<Stmt> {
-        todo!();
+        match self.peektok()? {
+            None => eof("'=', '(' or identifier".to_string()),
+            Some(&(Marker(ParenOpen), _)) => {
+                let (args, _) = self.tuplish(Self::exp)?;
+                let (_, end) = self.expect(Marker(Semicolon))?;
+                Ok((BareStmt::Call(id, args), opthull(id.1, Some(end.into()))))
+            }
+            Some(&(Marker(Assign), _)) => {
+                self.nexttok();
+                let exp = self.exp()?;
+                let (_, end) = self.expect(Marker(Semicolon))?;
+                Ok((BareStmt::Assign(id, exp), opthull(id.1, Some(end.into()))))
+            }
+            Some(&(Marker(Dot), loc)) => fail!("Assignment to a field is not supported", loc),
+            Some(&(IdTok(_), _)) => {
+                let typ = (BareType::Typename(id), id.1);
+                self.node(SyntaxKind::Type, typ.1);
+                let (name, exp, end) = self.var_init()?;
+                Ok((
+                    BareStmt::Local((Some(typ), name, exp)),
+                    opthull(id.1, Some(end.into())),
+                ))
+            }
+            Some(&found) => unexpected(found, "'=', '(' or identifier".to_string()),
+        }
     }
 
-    fn compound(&mut self) -> ParseResult<Vec<Stmt>> {
-        todo!();
+    fn compound(&mut self) -> ParseResult<(Vec<Stmt>, Span)> {
+        let (_, start) = self.expect(Marker(BraceOpen))?;
+        let mut stmts = Vec::new();
+        loop {
+            match self.peektok()? {
+                None => return eof("statement or '}'".to_string()),
+                Some(&(Marker(BraceClose), end)) => {
+                    self.nexttok();
+                    return Ok((stmts, hull(start.into(), end.into())));
+                }
+                Some(_) => {
+                    let stmt = self.stmt()?;
+                    self.node(stmt_kind(&stmt.0), stmt.1);
+                    stmts.push(stmt);
+                }
+            }
+        }
     }
 
     fn selector(&mut self) -> ParseResult<Selector> {
-        todo!();
+        self.expect(Marker(Dot))?;
+        match self.trytok()? {
+            None => eof("selector".to_string()),
+            Some((SelectTok(sel), loc)) => Ok((sel, Some(loc.into()))),
+            Some(found) => self.backtrack(found, "selector".to_string()),
+        }
     }
 
-    fn field(&mut self) -> ParseResult<(Vec<Selector>, Span)> {
-        todo!()
+    /// Selectors up to the first token that is not a '.', and the span of
+    /// the last one.
+    fn field(&mut self) -> ParseResult<(Vec<Selector>, Option<Span>)> {
+        let mut fld = Vec::new();
+        while let Some(&(Marker(Dot), _)) = self.peektok()? {
+            fld.push(self.selector()?);
+        }
+        let end = fld.last().and_then(|sel| sel.1);
+        Ok((fld, end))
     }
 
     fn exp(&mut self) -> ParseResult<Exp> {
@@ -222,23 +522,36 @@ impl<'s> Parser<'s> {
 
     fn atom(&mut self) -> ParseResult<Exp> {
         use BareExp::*;
-        match self.trytok()? {
-            None => eof("identifier, literal, or '('".to_string()),
+        let (kind, exp) = match self.trytok()? {
+            None => return eof("identifier, literal, or '('".to_string()),
             Some((tok, loc)) => match tok {
-                IdTok(i) => self.field_or_call((i, Some(loc.into()))),
-                LitTok(val) => Ok(((Lit(val), None), Some(loc.into()))),
+                IdTok(i) => {
+                    let exp = self.field_or_call((i, Some(loc.into())))?;
+                    match (exp.0).0 {
+                        Call(..) => (SyntaxKind::CallExp, exp),
+                        _ => (SyntaxKind::VarExp, exp),
+                    }
+                }
+                LitTok(val) => (SyntaxKind::LitExp, ((Lit(val), None), Some(loc.into()))),
+                StrLit(i) => (
+                    SyntaxKind::LitExp,
+                    string(&self.ts.strings[i as usize], loc.into()),
+                ),
                 Marker(ParenOpen) => {
                     self.unpeektok((Marker(ParenOpen), loc))?;
                     let (coords, span) = self.tuplish(Self::exp)?;
                     if coords.len() == 1 {
-                        Ok((coords.into_iter().next().unwrap().0, Some(span)))
+                        let inner = coords.into_iter().next().unwrap();
+                        (SyntaxKind::ParenExp, (inner.0, Some(span)))
                     } else {
-                        Ok(((Tuple(coords), None), Some(span)))
+                        (SyntaxKind::TupleExp, ((Tuple(coords), None), Some(span)))
                     }
                 }
-                x => unexpected((x, loc), "identifier, literal, or '('".to_string()),
+                x => return unexpected((x, loc), "identifier, literal, or '('".to_string()),
             },
-        }
+        };
+        self.node(kind, exp.1);
+        Ok(exp)
     }
 
     fn field_or_call(&mut self, id: Id) -> ParseResult<Exp> {
@@ -252,7 +565,12 @@ impl<'s> Parser<'s> {
                 }
                 _ => {
                     let (fld, end) = self.field()?;
-                    Ok(((Var(id, fld), None), opthull(id.1, Some(end))))
+                    let span = if end.is_some() {
+                        opthull(id.1, end)
+                    } else {
+                        id.1
+                    };
+                    Ok(((Var(id, fld), None), span))
                 }
             },
         }
@@ -293,11 +611,31 @@ impl<'s> Parser<'s> {
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::lex::*;
     use super::*;
-    fn tspan(startline: u32, endline: u32, startcol: u16, endcol: u16) -> Option<Span> {
-        Some(Span::new(startline, endline, startcol, endcol))
+
+    /// A program using most of the syntax, for tests over whole ASTs.
+    pub(crate) const SOURCE: &str = "var g = 1;
+        f(x, y) :: Int [a] -> (Int, Bool) {
+            Int z = x * -y.hd.fst;
+            if (z < g) { var g = z; z = f(g, y.tl); } else { return (z, True); }
+            while (!(z == 0)) { print(z); z = z - 1; }
+            return (g, \"a\\n\" == y);
+        }
+        h() { return f(g, []); }";
+
+    /// Parse `source` as a whole program with 64-bit integers.
+    pub(crate) fn parse(source: &str) -> SPL {
+        let mut map = SourceMap::new();
+        let file = map.add("test.spl".to_string(), source.to_string());
+        Parser::for_file(&map, file, IntWidth::W64)
+            .spl()
+            .unwrap_or_else(|err| panic!("{:?} in\n{}", err, source))
+    }
+
+    fn tspan(start: u32, end: u32) -> Option<Span> {
+        Some(Span::new(FileId(0), start, end))
     }
 
     #[test]
@@ -310,7 +648,7 @@ mod tests {
         use BareExp::*;
         use LitVal::*;
         let mut p = Parser::new("1");
-        let correct = Ok(((Lit(Int(1)), None), tspan(0, 0, 0, 1)));
+        let correct = Ok(((Lit(Int(1)), None), tspan(0, 1)));
         let test = p.atom();
         assert_eq!(test, correct);
         assert_eq!(p.ts.next(), None);
@@ -321,7 +659,7 @@ mod tests {
         use BareExp::*;
         use LitVal::*;
         let mut p = Parser::new("1");
-        let correct = Ok(((Lit(Int(1)), None), tspan(0, 0, 0, 1)));
+        let correct = Ok(((Lit(Int(1)), None), tspan(0, 1)));
         let test = p.exp();
         assert_eq!(test, correct);
         assert_eq!(p.ts.next(), None);
@@ -336,25 +674,113 @@ mod tests {
         let correct = Ok((
             (
                 BinOp(
-                    (Plus, tspan(0, 0, 1, 2)),
-                    Box::new(((Lit(Int(3)), None), tspan(0, 0, 0, 1))),
-                    Box::new(((Lit(Int(2)), None), tspan(0, 0, 2, 3))),
+                    (Plus, tspan(1, 2)),
+                    Box::new(((Lit(Int(3)), None), tspan(0, 1))),
+                    Box::new(((Lit(Int(2)), None), tspan(2, 3))),
                 ),
                 None,
             ),
-            tspan(0, 0, 0, 3),
+            tspan(0, 3),
         ));
         let test = p.exp();
         assert_eq!(test, correct);
         assert_eq!(p.ts.next(), None);
     }
 
+    #[test]
+    fn string_desugars() {
+        use BareExp::*;
+        use LitVal::*;
+        let span = tspan(0, 4);
+        let lit = |val| Box::new(((Lit(val), None), span));
+        let cons = |head, tail| ((BinOp((BareOp::Cons, span), head, tail), None), span);
+        let correct = cons(lit(Char('h')), Box::new(cons(lit(Char('i')), lit(Nil))));
+        assert_eq!(Parser::new("\"hi\"").exp(), Ok(correct));
+        assert_eq!(
+            Parser::new("\"\"").exp(),
+            Ok(((Lit(Nil), None), tspan(0, 2)))
+        );
+    }
+
+    #[test]
+    fn negative_literals() {
+        use crate::visit::{Fold, StripSpans};
+        use BareExp::*;
+        use LitVal::*;
+        let lit = |val, start, end| ((Lit(Int(val)), None), tspan(start, end));
+        let exp = |source: &str, width| {
+            let mut map = SourceMap::new();
+            let file = map.add("a.spl".to_string(), source.to_string());
+            let exp = Parser::for_file(&map, file, width).exp();
+            exp.map(|exp| StripSpans.fold_exp(exp))
+        };
+        assert_eq!(Parser::new("-5").exp(), Ok(lit(-5, 0, 2)));
+        assert_eq!(Parser::new("- /* c */ 0x1").exp(), Ok(lit(-1, 0, 13)));
+        assert_eq!(
+            Parser::new("-9223372036854775808").exp(),
+            Ok(lit(i64::MIN, 0, 20))
+        );
+        let min = ((Lit(Int(i32::MIN as i64)), None), None);
+        assert_eq!(exp("-2147483648", IntWidth::W32), Ok(min));
+        assert!(exp("2147483648", IntWidth::W32).is_err());
+        assert!(exp("x - 2147483648", IntWidth::W32).is_err());
+        assert!(exp("x - -2147483648", IntWidth::W32).is_ok());
+        let neg = |arg| ((UnOp((BareOp::Neg, None), Box::new(arg)), None), None);
+        let five = ((Lit(Int(5)), None), None);
+        assert_eq!(exp("-(5)", IntWidth::W64), Ok(neg(five.clone())));
+        let minus_five = ((Lit(Int(-5)), None), None);
+        assert_eq!(exp("--5", IntWidth::W64), Ok(neg(minus_five)));
+        let one = Box::new(((Lit(Int(1)), None), None));
+        let minus = (
+            (BinOp((BareOp::Minus, None), one, Box::new(five)), None),
+            None,
+        );
+        assert_eq!(exp("1-5", IntWidth::W64), Ok(minus));
+    }
+
+    #[test]
+    fn var_decl_with_field() {
+        use BareExp::*;
+        let mut p = Parser::new("var my_var = x.hd;");
+        let x = (
+            (BareId::intern("x"), tspan(13, 14)),
+            vec![(BareSelector::Hd, tspan(15, 17))],
+        );
+        let correct = (
+            BareDecl::Global((
+                None,
+                (BareId::intern("my_var"), tspan(4, 10)),
+                ((Var(x.0, x.1), None), tspan(13, 17)),
+            )),
+            tspan(0, 18),
+        );
+        assert_eq!(p.decl(), Ok(Some(correct)));
+        assert_eq!(p.decl(), Ok(None));
+    }
+
+    #[test]
+    fn keyword_as_identifier() {
+        assert_eq!(
+            Parser::new("var while = 1;").decl(),
+            Err(ParseError(
+                "'while' is a keyword, not an identifier".to_string(),
+                Some(Loc {
+                    file: FileId(0),
+                    start: 4,
+                    len: 5
+                })
+            ))
+        );
+        let err = Parser::new("var x = y.x;").decl().unwrap_err();
+        assert!(err.0.contains("selector"));
+    }
+
     #[test]
     fn paren_exp() {
         use BareExp::*;
         use LitVal::*;
         let mut p = Parser::new("(1)");
-        let correct = Ok(((Lit(Int(1)), None), tspan(0, 0, 0, 3)));
+        let correct = Ok(((Lit(Int(1)), None), tspan(0, 3)));
         let test = p.exp();
         assert_eq!(test, correct);
         assert_eq!(p.ts.next(), None);
@@ -427,15 +853,81 @@ mod tests {
         use crate::ast::BareExp::*;
         let mut p = Parser::new("foo()");
         let test = p.exp();
-        let foo = p.ts.names.iter().position(|&e| e == "foo").unwrap() as u32;
-        let correct = Ok((
-            (Call((foo, tspan(0, 0, 0, 3)), Vec::new()), None),
-            tspan(0, 0, 0, 5),
-        ));
+        let foo = BareId::intern("foo");
+        let correct = Ok(((Call((foo, tspan(0, 3)), Vec::new()), None), tspan(0, 5)));
         assert_eq!(test, correct);
         assert_eq!(p.ts.next(), None);
     }
 
+    #[test]
+    fn spans_in_source_map() {
+        let mut map = SourceMap::new();
+        map.add("a.spl".to_string(), "1".to_string());
+        let b = map.add("b.spl".to_string(), "\n  (x.hd, 2)".to_string());
+        let exp = Parser::for_file(&map, b, IntWidth::W64).exp().unwrap();
+        let span = exp.1.unwrap();
+        assert_eq!(span, Span::new(b, 3, 12));
+        assert_eq!(map.snippet(span), "(x.hd, 2)");
+        assert_eq!(map.describe(span), "b.spl:2:3");
+    }
+
+    #[test]
+    fn parse_program() {
+        use BareDecl::*;
+        let source = "var xs = 1 : [];\n\
+                      [Int] ys = [];\n\
+                      length(l) :: [a] -> Int {\n\
+                      \x20   var n = 0;\n\
+                      \x20   while (!isEmpty(l)) { n = n + 1; l = l.tl; }\n\
+                      \x20   if (n > 3) { return n; } else { print('a'); }\n\
+                      \x20   return n;\n\
+                      }\n\
+                      main() :: -> Void { (Int, Bool) p = (1, True); T k = p.fst; return; }";
+        let spl = Parser::new(source).spl().unwrap();
+        assert_eq!(spl.len(), 4);
+        assert!(matches!(spl[0].0, Global((None, _, _))));
+        assert!(matches!(
+            spl[1].0,
+            Global((Some((BareType::List(_), _)), _, _))
+        ));
+        match &spl[2] {
+            (Fun(_, params, Some(((args, ret), _)), body), span) => {
+                assert_eq!(params.len(), 1);
+                assert_eq!(args.len(), 1);
+                assert_eq!(ret.0, BareType::Lit(BType::IntT));
+                assert_eq!(body.len(), 4);
+                assert!(matches!(body[1].0, BareStmt::While(_, _)));
+                match &body[2].0 {
+                    BareStmt::ITE(_, then, els) => assert_eq!((then.len(), els.len()), (1, 1)),
+                    _ => panic!(),
+                }
+                assert_eq!(span.unwrap().end as usize, source.find("\nmain").unwrap());
+            }
+            _ => panic!(),
+        }
+        match &spl[3].0 {
+            Fun(_, params, Some(((args, _), _)), body) => {
+                assert!(params.is_empty() && args.is_empty());
+                assert!(matches!(
+                    body[0].0,
+                    BareStmt::Local((Some((BareType::Tuple(_), _)), _, _))
+                ));
+                assert!(matches!(
+                    body[1].0,
+                    BareStmt::Local((Some((BareType::Typename(_), _)), _, _))
+                ));
+                assert_eq!(body[2].0, BareStmt::Ret(None));
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn field_assignment_rejected() {
+        let err = Parser::new("f() { x.hd = 1; }").spl().unwrap_err();
+        assert_eq!(err.0, "Assignment to a field is not supported");
+    }
+
     #[test]
     fn tuple_newline_exp() {
         use BareExp::*;
@@ -444,12 +936,12 @@ mod tests {
         let correct = Ok((
             (
                 Tuple(vec![
-                    ((Lit(Int(1)), None), tspan(0, 0, 1, 2)),
-                    ((Lit(Int(2)), None), tspan(1, 1, 0, 1)),
+                    ((Lit(Int(1)), None), tspan(1, 2)),
+                    ((Lit(Int(2)), None), tspan(4, 5)),
                 ]),
                 None,
             ),
-            tspan(0, 1, 0, 2),
+            tspan(0, 6),
         ));
         let test = p.exp();
         assert_eq!(test, correct);
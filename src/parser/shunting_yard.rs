@@ -71,9 +71,22 @@ impl<'s, 'p> ShuntingYard<'s, 'p> {
         assert_eq!(self.state, Expression);
         match self.parser.peektok()? {
             Some(&(Op(Minus), loc)) => {
-                self.opstack.push((Op(Neg), loc));
                 self.parser.nexttok();
-                self.state = Expression;
+                // A minus sign right before an integer literal is part of it.
+                match self.parser.peektok()? {
+                    Some(&(LitTok(LitVal::Int(n)), end)) => {
+                        self.parser.nexttok();
+                        let val = LitVal::Int(self.parser.ts.width().negate(n));
+                        let span = Some(hull(loc.into(), end.into()));
+                        self.parser.node(SyntaxKind::LitExp, span);
+                        self.outstack.push(((BareExp::Lit(val), None), span));
+                        self.state = Operator;
+                    }
+                    _ => {
+                        self.opstack.push((Op(Neg), loc));
+                        self.state = Expression;
+                    }
+                }
             }
             Some(&(Op(Not), loc)) => {
                 self.opstack.push((Op(Not), loc));
@@ -144,6 +157,7 @@ impl<'s, 'p> ShuntingYard<'s, 'p> {
             let arg1 = self.exppop()?;
             let span = opthull(Some(loc.into()), arg1.1);
             let result = ((UnOp((op, Some(loc.into())), Box::new(arg1)), None), span);
+            self.parser.node(SyntaxKind::UnExp, span);
             self.outstack.push(result)
         } else {
             let arg2 = self.exppop()?; // remember, the stack is back-to-front
@@ -156,6 +170,7 @@ impl<'s, 'p> ShuntingYard<'s, 'p> {
                 ),
                 span,
             );
+            self.parser.node(SyntaxKind::BinExp, span);
             self.outstack.push(result)
         }
         Ok(())
@@ -1,27 +1,22 @@
 use crate::ast::{BType, BareId, BareOp, BareSelector, LitVal};
+use crate::source::FileId;
 
+/// The bytes of a token.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Loc {
-    pub line: u32,
-    pub col: u16,
-    pub len: u16,
+    pub file: FileId,
+    pub start: u32,
+    pub len: u32,
 }
 
 impl Loc {
-    // TODO: guard against overflow
-    pub fn next_line(&mut self) {
-        self.line += 1;
-        self.col = 0;
-        self.len = 0;
-    }
-
     pub fn advance(&mut self) {
-        self.col += self.len;
+        self.start += self.len;
         self.len = 0;
     }
 
-    pub fn step(&mut self) {
-        self.len += 1;
+    pub fn step(&mut self, c: char) {
+        self.len += c.len_utf8() as u32;
     }
 }
 
@@ -48,14 +43,25 @@ pub(super) enum Misc {
     TypeColon,
 }
 
+/// Tokens that only lossless lexing produces.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(super) enum Trivia {
+    Whitespace,
+    LineComment,
+    BlockComment,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub(super) enum Token {
     IdTok(BareId),
     Selector(BareSelector),
     TypeTok(BType),
     Lit(LitVal),
+    /// Index into the lexer's `strings`.
+    StrLit(u32),
     Op(BareOp),
     Marker(Misc),
+    Trivia(Trivia),
 }
 
 pub(super) type LocTok = Located<Token>;
@@ -70,9 +76,9 @@ pub(super) trait TokAble {
     }
 }
 
-impl TokAble for u32 {
+impl TokAble for BareId {
     fn to_tok(self) -> Token {
-        Token::IdTok(self as BareId)
+        Token::IdTok(self)
     }
 }
 
@@ -0,0 +1,553 @@
+use crate::ast::*;
+use crate::intern::FRESH;
+use crate::visit::{walk_id, Visitor};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+/// An AST node, which displays as SPL source in the layout `spl fmt`
+/// produces. Declarations and statements print as whole lines; expressions
+/// and types without a newline. Strings print as the `Cons` cells they
+/// desugar to. Names the optimiser makes up, which cannot be written in
+/// source, print as their base and a number that no other name in the node
+/// has.
+pub struct Pretty<'a, T: ?Sized> {
+    node: &'a T,
+    indent: usize,
+    names: Rc<HashMap<BareId, String>>,
+}
+
+/// The kinds of node that `pretty` prints.
+pub trait Printable {
+    fn accept(&self, visitor: &mut dyn Visitor);
+}
+
+macro_rules! printable {
+    ( $node : ty, $visit : ident ) => {
+        impl Printable for $node {
+            fn accept(&self, visitor: &mut dyn Visitor) {
+                visitor.$visit(self)
+            }
+        }
+    };
+}
+
+printable!(SPL, visit_spl);
+printable!(Decl, visit_decl);
+printable!(VarDecl, visit_var_decl);
+printable!(Stmt, visit_stmt);
+printable!(Exp, visit_exp);
+printable!(Type, visit_type);
+printable!(FunType, visit_fun_type);
+printable!(Id, visit_id);
+
+/// Collects the names in a node: those written as they are, and the made
+/// up ones in order of appearance.
+#[derive(Default)]
+struct Names {
+    written: HashSet<String>,
+    fresh: Vec<BareId>,
+}
+
+impl Visitor for Names {
+    fn visit_id(&mut self, id: &Id) {
+        let name = id.0.name();
+        if !name.contains(FRESH) {
+            self.written.insert(name.to_string());
+        } else if !self.fresh.contains(&id.0) {
+            self.fresh.push(id.0);
+        }
+        walk_id(self, id)
+    }
+}
+
+/// `node` for printing.
+pub fn pretty<T: Printable + ?Sized>(node: &T) -> Pretty<'_, T> {
+    let mut names = Names::default();
+    node.accept(&mut names);
+    let Names { mut written, fresh } = names;
+    let mut renames = HashMap::new();
+    for id in fresh {
+        let base = id.name().split(FRESH).next().unwrap_or_default();
+        let name = (0..)
+            .map(|n| format!("{}{}", base, n))
+            .find(|name| !written.contains(name))
+            .unwrap();
+        written.insert(name.clone());
+        renames.insert(id, name);
+    }
+    Pretty {
+        node,
+        indent: 0,
+        names: Rc::new(renames),
+    }
+}
+
+impl<'a, T: ?Sized> Pretty<'a, T> {
+    fn with<U: ?Sized>(&self, node: &'a U) -> Pretty<'a, U> {
+        Pretty {
+            node,
+            indent: self.indent,
+            names: self.names.clone(),
+        }
+    }
+
+    fn nested<U: ?Sized>(&self, node: &'a U) -> Pretty<'a, U> {
+        Pretty {
+            node,
+            indent: self.indent + 1,
+            names: self.names.clone(),
+        }
+    }
+
+    fn indent(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for _ in 0..self.indent {
+            f.write_str("    ")?;
+        }
+        Ok(())
+    }
+
+    fn block(&self, f: &mut fmt::Formatter<'_>, stmts: &'a [Stmt]) -> fmt::Result {
+        for stmt in stmts {
+            write!(f, "{}", self.nested(stmt))?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes `items` separated by `", "`.
+fn comma_separated<T: fmt::Display>(
+    f: &mut fmt::Formatter<'_>,
+    items: impl IntoIterator<Item = T>,
+) -> fmt::Result {
+    for (i, item) in items.into_iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Pretty<'_, SPL> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut prev_fun = None;
+        for decl in self.node {
+            let fun = matches!(decl.0, BareDecl::Fun(..));
+            if prev_fun.is_some_and(|prev| prev || fun) {
+                writeln!(f)?;
+            }
+            write!(f, "{}", self.with(decl))?;
+            prev_fun = Some(fun);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Pretty<'_, Decl> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.indent(f)?;
+        match &self.node.0 {
+            BareDecl::Global(var) => writeln!(f, "{}", self.with(var)),
+            BareDecl::Fun(id, args, typ, body) => {
+                write!(f, "{}(", self.with(id))?;
+                comma_separated(f, args.iter().map(|arg| self.with(arg)))?;
+                f.write_str(")")?;
+                if let Some(typ) = typ {
+                    write!(f, " :: {}", self.with(typ))?;
+                }
+                writeln!(f, " {{")?;
+                self.block(f, body)?;
+                self.indent(f)?;
+                writeln!(f, "}}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Pretty<'_, VarDecl> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (typ, id, exp) = self.node;
+        match typ {
+            Some(typ) => write!(f, "{}", self.with(typ))?,
+            None => f.write_str("var")?,
+        }
+        write!(f, " {} = {};", self.with(id), self.with(exp))
+    }
+}
+
+impl fmt::Display for Pretty<'_, Stmt> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.indent(f)?;
+        match &self.node.0 {
+            BareStmt::ITE(cond, then, els) => {
+                writeln!(f, "if ({}) {{", self.with(cond))?;
+                self.block(f, then)?;
+                self.indent(f)?;
+                if !els.is_empty() {
+                    writeln!(f, "}} else {{")?;
+                    self.block(f, els)?;
+                    self.indent(f)?;
+                }
+                writeln!(f, "}}")
+            }
+            BareStmt::While(cond, body) => {
+                writeln!(f, "while ({}) {{", self.with(cond))?;
+                self.block(f, body)?;
+                self.indent(f)?;
+                writeln!(f, "}}")
+            }
+            BareStmt::Assign(id, exp) => {
+                writeln!(f, "{} = {};", self.with(id), self.with(exp))
+            }
+            BareStmt::Call(id, args) => {
+                write!(f, "{}(", self.with(id))?;
+                comma_separated(f, args.iter().map(|arg| self.with(arg)))?;
+                writeln!(f, ");")
+            }
+            BareStmt::Ret(None) => writeln!(f, "return;"),
+            BareStmt::Ret(Some(exp)) => writeln!(f, "return {};", self.with(exp)),
+            BareStmt::Local(var) => writeln!(f, "{}", self.with(var)),
+        }
+    }
+}
+
+impl fmt::Display for Pretty<'_, Exp> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let no_atoms = |_: &Exp| false;
+        match &(self.node.0).0 {
+            BareExp::Var(id, fields) => {
+                write!(f, "{}", self.with(id))?;
+                for (field, _) in fields {
+                    write!(f, ".{}", field)?;
+                }
+                Ok(())
+            }
+            BareExp::Call(id, args) => {
+                write!(f, "{}(", self.with(id))?;
+                comma_separated(f, args.iter().map(|arg| self.with(arg)))?;
+                f.write_str(")")
+            }
+            BareExp::Lit(val) => write!(f, "{}", val),
+            BareExp::Tuple(elems) => {
+                f.write_str("(")?;
+                comma_separated(f, elems.iter().map(|elem| self.with(elem)))?;
+                f.write_str(")")
+            }
+            BareExp::BinOp((op, _), lhs, rhs) => {
+                self.with(&**lhs)
+                    .operand(f, parenthesize(*op, Side::Left, lhs, &no_atoms))?;
+                write!(f, " {} ", op)?;
+                self.with(&**rhs)
+                    .operand(f, parenthesize(*op, Side::Right, rhs, &no_atoms))
+            }
+            BareExp::UnOp((op, _), arg) => {
+                write!(f, "{}", op)?;
+                self.with(&**arg)
+                    .operand(f, parenthesize(*op, Side::Right, arg, &no_atoms))
+            }
+        }
+    }
+}
+
+impl Pretty<'_, Exp> {
+    fn operand(&self, f: &mut fmt::Formatter<'_>, parens: bool) -> fmt::Result {
+        if parens {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+impl fmt::Display for Pretty<'_, Type> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.node.0 {
+            BareType::Lit(btype) => write!(f, "{}", btype),
+            BareType::Typename(id) => write!(f, "{}", self.with(id)),
+            BareType::Tuple(elems) => {
+                f.write_str("(")?;
+                comma_separated(f, elems.iter().map(|elem| self.with(elem)))?;
+                f.write_str(")")
+            }
+            BareType::List(elem) => write!(f, "[{}]", self.with(&**elem)),
+        }
+    }
+}
+
+impl fmt::Display for Pretty<'_, FunType> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (args, ret) = &self.node.0;
+        for arg in args {
+            write!(f, "{} ", self.with(arg))?;
+        }
+        write!(f, "-> {}", self.with(ret))
+    }
+}
+
+impl fmt::Display for Pretty<'_, Id> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.names.get(&self.node.0) {
+            Some(name) => f.write_str(name),
+            None => write!(f, "{}", self.node.0),
+        }
+    }
+}
+
+/// Which operand of an operator an expression is: the left one of a binary
+/// operator, or the right one of a binary or the only one of a unary one.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Side {
+    Left,
+    Right,
+}
+
+/// The operator at the top of `exp`, unless it is one or `atom` says to
+/// print it as a unit.
+fn operator(exp: &Exp, atom: &impl Fn(&Exp) -> bool) -> Option<BareOp> {
+    match &(exp.0).0 {
+        _ if atom(exp) => None,
+        BareExp::BinOp((op, _), _, _) | BareExp::UnOp((op, _), _) => Some(*op),
+        _ => None,
+    }
+}
+
+/// Whether `exp` needs parentheses as the `side` operand of `outer` for
+/// the parser to read it back the same way, given how the shunting yard
+/// uses `BareOp::right_precedes`.
+///
+/// A right operand is read as one if its operator is pushed on top of
+/// `outer`. A left operand is read as one if `outer` pops every operator
+/// still open at its end: its own, and those on its right edge, which
+/// matters for `!` since it binds looser than the comparisons. An integer
+/// literal right after `-` would be read as a negative literal.
+pub(crate) fn parenthesize(
+    outer: BareOp,
+    side: Side,
+    exp: &Exp,
+    atom: &impl Fn(&Exp) -> bool,
+) -> bool {
+    match side {
+        Side::Right
+            if outer == BareOp::Neg && matches!((exp.0).0, BareExp::Lit(LitVal::Int(_))) =>
+        {
+            true
+        }
+        Side::Right => operator(exp, atom).is_some_and(|op| !op.right_precedes(outer)),
+        Side::Left => right_edge_any(exp, atom, &|op| outer.right_precedes(op)),
+    }
+}
+
+/// Whether `pred` holds for an operator left open at the end of `exp`.
+fn right_edge_any(exp: &Exp, atom: &impl Fn(&Exp) -> bool, pred: &impl Fn(BareOp) -> bool) -> bool {
+    let op = match operator(exp, atom) {
+        Some(op) => op,
+        None => return false,
+    };
+    let last = match &(exp.0).0 {
+        BareExp::BinOp(_, _, last) | BareExp::UnOp(_, last) => last,
+        _ => return false,
+    };
+    pred(op) || (!parenthesize(op, Side::Right, last, atom) && right_edge_any(last, atom, pred))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::tests::parse;
+    use crate::visit::{Fold, StripSpans};
+
+    /// A xorshift generator, to make test programs reproducibly.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % n
+        }
+
+        fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+            items[self.below(items.len() as u64) as usize]
+        }
+    }
+
+    const NAMES: &[&str] = &["a", "b", "f", "xs", "t", "go"];
+
+    fn id(rng: &mut Rng) -> Id {
+        (BareId::intern(rng.pick(NAMES)), None)
+    }
+
+    fn typ(rng: &mut Rng, depth: u32) -> Type {
+        use BType::*;
+        let typ = match rng.below(if depth == 0 { 2 } else { 4 }) {
+            0 => BareType::Lit(rng.pick(&[IntT, BoolT, CharT])),
+            1 => BareType::Typename(id(rng)),
+            2 => BareType::Tuple(vec![typ(rng, depth - 1), typ(rng, depth - 1)]),
+            _ => BareType::List(Box::new(typ(rng, depth - 1))),
+        };
+        (typ, None)
+    }
+
+    fn exp(rng: &mut Rng, depth: u32) -> Exp {
+        use BareOp::*;
+        let binops = [
+            And, Or, Lt, Leq, Gt, Geq, Eq, Neq, Plus, Minus, Mul, Div, Mod, Cons,
+        ];
+        let exps = |rng: &mut Rng, n| (0..n).map(|_| exp(rng, depth - 1)).collect();
+        let exp = match rng.below(if depth == 0 { 2 } else { 8 }) {
+            0 => {
+                let selectors = [BareSelector::Hd, BareSelector::Tl, BareSelector::Fst];
+                let fields = (0..rng.below(3))
+                    .map(|_| (rng.pick(&selectors), None))
+                    .collect();
+                BareExp::Var(id(rng), fields)
+            }
+            1 => BareExp::Lit(match rng.below(4) {
+                0 => LitVal::Int(rng.pick(&[0, 7, 42, -1, -42, i64::MAX, i64::MIN])),
+                1 => LitVal::Char(rng.pick(&['x', '\n', '\'', '\\', '"', '\0', 'é', '\u{7}'])),
+                2 => LitVal::Bool(rng.below(2) == 0),
+                _ => LitVal::Nil,
+            }),
+            2 => {
+                let n = rng.below(3);
+                BareExp::Call(id(rng), exps(rng, n))
+            }
+            3 => {
+                let n = rng.pick(&[0, 2, 3]);
+                BareExp::Tuple(exps(rng, n))
+            }
+            4 => BareExp::UnOp((rng.pick(&[Neg, Not]), None), Box::new(exp(rng, depth - 1))),
+            _ => BareExp::BinOp(
+                (rng.pick(&binops), None),
+                Box::new(exp(rng, depth - 1)),
+                Box::new(exp(rng, depth - 1)),
+            ),
+        };
+        ((exp, None), None)
+    }
+
+    fn var_decl(rng: &mut Rng) -> VarDecl {
+        let typ = if rng.below(2) == 0 {
+            None
+        } else {
+            Some(typ(rng, 2))
+        };
+        (typ, id(rng), exp(rng, 3))
+    }
+
+    fn stmts(rng: &mut Rng, depth: u32) -> Vec<Stmt> {
+        (0..rng.below(4)).map(|_| stmt(rng, depth)).collect()
+    }
+
+    fn stmt(rng: &mut Rng, depth: u32) -> Stmt {
+        let stmt = match rng.below(if depth == 0 { 4 } else { 6 }) {
+            0 => BareStmt::Assign(id(rng), exp(rng, 4)),
+            1 => BareStmt::Call(id(rng), (0..rng.below(3)).map(|_| exp(rng, 2)).collect()),
+            2 if rng.below(2) == 0 => BareStmt::Ret(None),
+            2 => BareStmt::Ret(Some(exp(rng, 4))),
+            3 => BareStmt::Local(var_decl(rng)),
+            4 => BareStmt::ITE(exp(rng, 3), stmts(rng, depth - 1), stmts(rng, depth - 1)),
+            _ => BareStmt::While(exp(rng, 3), stmts(rng, depth - 1)),
+        };
+        (stmt, None)
+    }
+
+    fn program(rng: &mut Rng) -> SPL {
+        (0..1 + rng.below(4))
+            .map(|_| {
+                let decl = if rng.below(3) == 0 {
+                    BareDecl::Global(var_decl(rng))
+                } else {
+                    let args = (0..rng.below(3)).map(|_| id(rng)).collect();
+                    let typ = if rng.below(2) == 0 {
+                        None
+                    } else {
+                        let args = (0..rng.below(3)).map(|_| typ(rng, 2)).collect();
+                        Some(((args, typ(rng, 2)), None))
+                    };
+                    BareDecl::Fun(id(rng), args, typ, stmts(rng, 2))
+                };
+                (decl, None)
+            })
+            .collect()
+    }
+
+    fn reparse(source: &str) -> SPL {
+        StripSpans.fold_spl(parse(source))
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng = Rng(0x5eed_1234_abcd_0001);
+        for _ in 0..2000 {
+            let spl = program(&mut rng);
+            let source = pretty(&spl).to_string();
+            assert_eq!(reparse(&source), spl, "printed as\n{}", source);
+        }
+    }
+
+    #[test]
+    fn optimised_round_trip() {
+        use crate::opt::{inline::Inliner, tailcall::TailCallEliminator};
+        let source = "inc(x) { return x + 1; }
+            sum(n, acc) { var n0 = n; if (n0 == 0) { return acc; } return sum(n0 - 1, acc + inc(n)); }
+            main() { print(sum(10, inc(0))); }";
+        let spl = TailCallEliminator.run(Inliner::new(10).run(reparse(source)));
+        let printed = pretty(&spl).to_string();
+        assert!(!printed.contains('$'), "{}", printed);
+        // n0 is taken, so the fresh copy of n is n1.
+        assert!(printed.contains("var n1 = n0 - 1;"), "{}", printed);
+        assert!(printed.contains("while (again0) {"), "{}", printed);
+        assert_eq!(pretty(&reparse(&printed)).to_string(), printed);
+    }
+
+    #[test]
+    fn layout() {
+        let source = "var x = 1; f(a) :: Int -> [Char] { if (!(a < 2)) { return \"hi\"; } else { x = -(a * 2) + 3; } while (True) { g((1, 'c')); } } var y = x.hd;";
+        let spl = parse(source);
+        let expected = "var x = 1;
+
+f(a) :: Int -> [Char] {
+    if (!a < 2) {
+        return 'h' : 'i' : [];
+    } else {
+        x = -(a * 2) + 3;
+    }
+    while (True) {
+        g((1, 'c'));
+    }
+}
+
+var y = x.hd;
+";
+        assert_eq!(pretty(&spl).to_string(), expected);
+    }
+
+    #[test]
+    fn open_negation() {
+        use BareOp::*;
+        let var = |name| {
+            (
+                (BareExp::Var((BareId::intern(name), None), vec![]), None),
+                None,
+            )
+        };
+        let bin = |op, lhs, rhs| {
+            (
+                (
+                    BareExp::BinOp((op, None), Box::new(lhs), Box::new(rhs)),
+                    None,
+                ),
+                None,
+            )
+        };
+        let not = |arg| ((BareExp::UnOp((Not, None), Box::new(arg)), None), None);
+        // (x + !y) < r: without the parentheses, `<` would apply to y.
+        let exp: Exp = bin(Lt, bin(Plus, var("x"), not(var("y"))), var("r"));
+        assert_eq!(pretty(&exp).to_string(), "(x + !y) < r");
+        let exp: Exp = bin(Plus, var("x"), not(bin(Lt, var("y"), var("r"))));
+        assert_eq!(pretty(&exp).to_string(), "x + !y < r");
+    }
+}
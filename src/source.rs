@@ -0,0 +1,219 @@
+use crate::ast::Span;
+
+/// Identifies a file in a `SourceMap`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId(pub u32);
+
+struct SourceFile {
+    name: String,
+    source: String,
+    /// Byte offset of the start of every line.
+    line_starts: Vec<u32>,
+}
+
+/// The source files of a compilation. Spans only store byte offsets; lines
+/// and columns are computed from here when a diagnostic needs them.
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+    tab_width: u32,
+}
+
+impl Default for SourceMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What a column counts: bytes, UTF-16 code units (as editors speaking LSP
+/// expect), chars, or the cells a terminal or editor displays them in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColUnit {
+    Utf8,
+    Utf16,
+    Char,
+    Display,
+}
+
+/// The number of cells `c` takes up on screen: 2 for East Asian wide and
+/// fullwidth characters, 0 for combining marks and zero width characters.
+/// This covers the common ranges, not all of UAX #11.
+fn char_width(c: char) -> u32 {
+    match c as u32 {
+        0x0300..=0x036f | 0x200b..=0x200f | 0x20d0..=0x20ff | 0xfe00..=0xfe0f => 0,
+        0x1100..=0x115f
+        | 0x2e80..=0x303e
+        | 0x3041..=0x33ff
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xa000..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x2fffd
+        | 0x30000..=0x3fffd => 2,
+        _ => 1,
+    }
+}
+
+/// A zero-based line and column.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LineCol {
+    pub line: u32,
+    pub col: u32,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::with_tab_width(4)
+    }
+
+    /// A map whose display columns put tab stops every `tab_width` cells.
+    /// A width of 0 is taken as 1: a tab still takes up a cell.
+    pub fn with_tab_width(tab_width: u32) -> Self {
+        SourceMap {
+            files: Vec::new(),
+            tab_width: tab_width.max(1),
+        }
+    }
+
+    pub fn add(&mut self, name: String, source: String) -> FileId {
+        assert!(source.len() <= u32::MAX as usize, "Source file too large");
+        // Lines end in \n, \r\n or a lone \r.
+        let bytes = source.as_bytes();
+        let line_starts = std::iter::once(0)
+            .chain(bytes.iter().enumerate().filter_map(|(pos, &b)| {
+                let ends = b == b'\n' || (b == b'\r' && bytes.get(pos + 1) != Some(&b'\n'));
+                if ends {
+                    Some(pos as u32 + 1)
+                } else {
+                    None
+                }
+            }))
+            .collect();
+        self.files.push(SourceFile {
+            name,
+            source,
+            line_starts,
+        });
+        FileId(self.files.len() as u32 - 1)
+    }
+
+    fn file(&self, file: FileId) -> &SourceFile {
+        &self.files[file.0 as usize]
+    }
+
+    pub fn name(&self, file: FileId) -> &str {
+        &self.file(file).name
+    }
+
+    pub fn source(&self, file: FileId) -> &str {
+        &self.file(file).source
+    }
+
+    pub fn snippet(&self, span: Span) -> &str {
+        &self.source(span.file)[span.start as usize..span.end as usize]
+    }
+
+    pub fn line_col(&self, file: FileId, offset: u32, unit: ColUnit) -> LineCol {
+        let tab_width = self.tab_width;
+        let file = self.file(file);
+        let line = file.line_starts.partition_point(|&start| start <= offset) - 1;
+        let prefix = &file.source[file.line_starts[line] as usize..offset as usize];
+        let col = match unit {
+            ColUnit::Utf8 => prefix.len(),
+            ColUnit::Utf16 => prefix.encode_utf16().count(),
+            ColUnit::Char => prefix.chars().count(),
+            ColUnit::Display => prefix.chars().fold(0, |col, c| match c {
+                '\t' => col + tab_width - col % tab_width,
+                c => col + char_width(c),
+            }) as usize,
+        };
+        LineCol {
+            line: line as u32,
+            col: col as u32,
+        }
+    }
+
+    /// Where `span` starts and ends.
+    pub fn lines(&self, span: Span, unit: ColUnit) -> (LineCol, LineCol) {
+        (
+            self.line_col(span.file, span.start, unit),
+            self.line_col(span.file, span.end, unit),
+        )
+    }
+
+    /// `name:line:col` of the start of `span`, counting from 1, for messages.
+    /// The column is the display column, as editors show it.
+    pub fn describe(&self, span: Span) -> String {
+        let start = self.line_col(span.file, span.start, ColUnit::Display);
+        format!(
+            "{}:{}:{}",
+            self.name(span.file),
+            start.line + 1,
+            start.col + 1
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lc(line: u32, col: u32) -> LineCol {
+        LineCol { line, col }
+    }
+
+    #[test]
+    fn multiple_files() {
+        let mut map = SourceMap::new();
+        let a = map.add("a.spl".to_string(), "var x = 1;\n".to_string());
+        let b = map.add("b.spl".to_string(), "\n\n  f();".to_string());
+        assert_ne!(a, b);
+        assert_eq!(map.snippet(Span::new(a, 4, 5)), "x");
+        assert_eq!(map.snippet(Span::new(b, 4, 5)), "f");
+        assert_eq!(map.describe(Span::new(b, 4, 5)), "b.spl:3:3");
+        assert_eq!(map.line_col(a, 11, ColUnit::Char), lc(1, 0));
+    }
+
+    #[test]
+    fn column_units() {
+        let mut map = SourceMap::new();
+        // 'é' is 2 bytes and 1 UTF-16 unit, '𝄞' 4 bytes and 2 UTF-16 units.
+        let file = map.add("u.spl".to_string(), "x\n'é' '𝄞' y".to_string());
+        let y = 2 + "'é' '𝄞' ".len() as u32;
+        assert_eq!(map.snippet(Span::new(file, y, y + 1)), "y");
+        assert_eq!(map.line_col(file, y, ColUnit::Utf8), lc(1, 12));
+        assert_eq!(map.line_col(file, y, ColUnit::Utf16), lc(1, 9));
+        assert_eq!(map.line_col(file, y, ColUnit::Char), lc(1, 8));
+        assert_eq!(map.line_col(file, y, ColUnit::Display), lc(1, 8));
+    }
+
+    #[test]
+    fn line_endings() {
+        let mut map = SourceMap::new();
+        let file = map.add("crlf.spl".to_string(), "a\r\nb\rc\n\r\nd".to_string());
+        let line_of = |offset| map.line_col(file, offset, ColUnit::Char).line;
+        let lines: Vec<u32> = [0, 3, 5, 7, 9].iter().map(|&off| line_of(off)).collect();
+        assert_eq!(lines, vec![0, 1, 2, 3, 4]);
+        assert_eq!(map.line_col(file, 9, ColUnit::Char), lc(4, 0));
+    }
+
+    #[test]
+    fn display_columns() {
+        let mut map = SourceMap::with_tab_width(8);
+        let file = map.add("tab.spl".to_string(), "\tx\n a\tb\n漢字 c".to_string());
+        let col = |offset| map.line_col(file, offset, ColUnit::Display).col;
+        assert_eq!(col(1), 8);
+        assert_eq!(col(6), 8);
+        assert_eq!(col(15), 5);
+        assert_eq!(map.line_col(file, 15, ColUnit::Char), lc(2, 3));
+        assert_eq!(map.describe(Span::new(file, 15, 16)), "tab.spl:3:6");
+        let mut map = SourceMap::with_tab_width(0);
+        let file = map.add("tab.spl".to_string(), "\t\tx".to_string());
+        assert_eq!(map.line_col(file, 2, ColUnit::Display).col, 2);
+    }
+}
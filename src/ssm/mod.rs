@@ -0,0 +1,333 @@
+pub mod peephole;
+
+use std::fmt;
+use std::str::FromStr;
+
+pub type Label = String;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reg {
+    PC,
+    SP,
+    MP,
+    HP,
+    RR,
+    R5,
+    R6,
+    R7,
+}
+
+/// An instruction of the Simple Stack Machine, or a label marking the
+/// position of the next instruction.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Instr {
+    Label(Label),
+    Ldc(i64),
+    Ldl(i64),
+    Ldla(i64),
+    Lds(i64),
+    Ldsa(i64),
+    Ldr(Reg),
+    Ldrr(Reg, Reg),
+    Lda(i64),
+    Ldaa(i64),
+    Ldh(i64),
+    Ldmh(i64, i64),
+    Stl(i64),
+    Sts(i64),
+    Str(Reg),
+    Sta(i64),
+    Sth,
+    Stmh(i64),
+    Ajs(i64),
+    Link(i64),
+    Unlink,
+    Bra(Label),
+    Brf(Label),
+    Brt(Label),
+    Bsr(Label),
+    Jsr,
+    Ret,
+    Halt,
+    Trap(i64),
+    Nop,
+    Swp,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Neg,
+    And,
+    Or,
+    Xor,
+    Not,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Instr {
+    pub fn is_label(&self) -> bool {
+        matches!(self, Instr::Label(_))
+    }
+}
+
+impl FromStr for Reg {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        use Reg::*;
+        match name {
+            "PC" => Ok(PC),
+            "SP" => Ok(SP),
+            "MP" => Ok(MP),
+            "HP" => Ok(HP),
+            "RR" => Ok(RR),
+            "R5" => Ok(R5),
+            "R6" => Ok(R6),
+            "R7" => Ok(R7),
+            _ => Err(format!("Unknown register '{}'", name)),
+        }
+    }
+}
+
+/// Reads one instruction as `Display` writes it: a mnemonic and its
+/// arguments separated by whitespace, or a label followed by `:`.
+impl FromStr for Instr {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        use Instr::*;
+        if let Some(label) = text.strip_suffix(':') {
+            return Ok(Label(label.to_string()));
+        }
+        let mut words = text.split_whitespace();
+        let mnemonic = words.next().ok_or("Expected an instruction")?;
+        let args: Vec<&str> = words.collect();
+        let arity = |n: usize| {
+            if args.len() == n {
+                Ok(())
+            } else {
+                Err(format!("'{}' takes {} argument(s)", mnemonic, n))
+            }
+        };
+        let int = |i: usize| -> Result<i64, String> {
+            args[i]
+                .parse()
+                .map_err(|_| format!("Expected a number, found '{}'", args[i]))
+        };
+        let label = || args[0].to_string();
+        let instr = match mnemonic {
+            "ldc" | "ldl" | "ldla" | "lds" | "ldsa" | "lda" | "ldaa" | "ldh" | "stl" | "sts"
+            | "sta" | "stmh" | "ajs" | "link" | "trap" => {
+                arity(1)?;
+                let n = int(0)?;
+                match mnemonic {
+                    "ldc" => Ldc(n),
+                    "ldl" => Ldl(n),
+                    "ldla" => Ldla(n),
+                    "lds" => Lds(n),
+                    "ldsa" => Ldsa(n),
+                    "lda" => Lda(n),
+                    "ldaa" => Ldaa(n),
+                    "ldh" => Ldh(n),
+                    "stl" => Stl(n),
+                    "sts" => Sts(n),
+                    "sta" => Sta(n),
+                    "stmh" => Stmh(n),
+                    "ajs" => Ajs(n),
+                    "link" => Link(n),
+                    _ => Trap(n),
+                }
+            }
+            "ldmh" => {
+                arity(2)?;
+                Ldmh(int(0)?, int(1)?)
+            }
+            "ldr" | "str" => {
+                arity(1)?;
+                let reg = args[0].parse()?;
+                if mnemonic == "ldr" {
+                    Ldr(reg)
+                } else {
+                    Str(reg)
+                }
+            }
+            "ldrr" => {
+                arity(2)?;
+                Ldrr(args[0].parse()?, args[1].parse()?)
+            }
+            "bra" | "brf" | "brt" | "bsr" => {
+                arity(1)?;
+                match mnemonic {
+                    "bra" => Bra(label()),
+                    "brf" => Brf(label()),
+                    "brt" => Brt(label()),
+                    _ => Bsr(label()),
+                }
+            }
+            _ => {
+                let instr = match mnemonic {
+                    "sth" => Sth,
+                    "unlink" => Unlink,
+                    "jsr" => Jsr,
+                    "ret" => Ret,
+                    "halt" => Halt,
+                    "nop" => Nop,
+                    "swp" => Swp,
+                    "add" => Add,
+                    "sub" => Sub,
+                    "mul" => Mul,
+                    "div" => Div,
+                    "mod" => Mod,
+                    "neg" => Neg,
+                    "and" => And,
+                    "or" => Or,
+                    "xor" => Xor,
+                    "not" => Not,
+                    "eq" => Eq,
+                    "ne" => Ne,
+                    "lt" => Lt,
+                    "le" => Le,
+                    "gt" => Gt,
+                    "ge" => Ge,
+                    _ => return Err(format!("Unknown instruction '{}'", mnemonic)),
+                };
+                arity(0)?;
+                instr
+            }
+        };
+        Ok(instr)
+    }
+}
+
+/// Reads SSM assembly, one instruction per line. A line may start with a
+/// label, and `;` starts a comment. Errors carry their 1-based line number.
+pub fn parse(text: &str) -> Result<Vec<Instr>, (usize, String)> {
+    let mut code = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        let rest = match line.split_once(':') {
+            Some((label, rest)) if !label.contains(char::is_whitespace) => {
+                code.push(Instr::Label(label.to_string()));
+                rest.trim()
+            }
+            _ => line,
+        };
+        if !rest.is_empty() {
+            code.push(rest.parse().map_err(|err| (i + 1, err))?);
+        }
+    }
+    Ok(code)
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Instr::*;
+        match self {
+            Label(l) => write!(f, "{}:", l),
+            Ldc(n) => write!(f, "ldc {}", n),
+            Ldl(n) => write!(f, "ldl {}", n),
+            Ldla(n) => write!(f, "ldla {}", n),
+            Lds(n) => write!(f, "lds {}", n),
+            Ldsa(n) => write!(f, "ldsa {}", n),
+            Ldr(r) => write!(f, "ldr {}", r),
+            Ldrr(to, from) => write!(f, "ldrr {} {}", to, from),
+            Lda(n) => write!(f, "lda {}", n),
+            Ldaa(n) => write!(f, "ldaa {}", n),
+            Ldh(n) => write!(f, "ldh {}", n),
+            Ldmh(n, m) => write!(f, "ldmh {} {}", n, m),
+            Stl(n) => write!(f, "stl {}", n),
+            Sts(n) => write!(f, "sts {}", n),
+            Str(r) => write!(f, "str {}", r),
+            Sta(n) => write!(f, "sta {}", n),
+            Sth => write!(f, "sth"),
+            Stmh(n) => write!(f, "stmh {}", n),
+            Ajs(n) => write!(f, "ajs {}", n),
+            Link(n) => write!(f, "link {}", n),
+            Unlink => write!(f, "unlink"),
+            Bra(l) => write!(f, "bra {}", l),
+            Brf(l) => write!(f, "brf {}", l),
+            Brt(l) => write!(f, "brt {}", l),
+            Bsr(l) => write!(f, "bsr {}", l),
+            Jsr => write!(f, "jsr"),
+            Ret => write!(f, "ret"),
+            Halt => write!(f, "halt"),
+            Trap(n) => write!(f, "trap {}", n),
+            Nop => write!(f, "nop"),
+            Swp => write!(f, "swp"),
+            Add => write!(f, "add"),
+            Sub => write!(f, "sub"),
+            Mul => write!(f, "mul"),
+            Div => write!(f, "div"),
+            Mod => write!(f, "mod"),
+            Neg => write!(f, "neg"),
+            And => write!(f, "and"),
+            Or => write!(f, "or"),
+            Xor => write!(f, "xor"),
+            Not => write!(f, "not"),
+            Eq => write!(f, "eq"),
+            Ne => write!(f, "ne"),
+            Lt => write!(f, "lt"),
+            Le => write!(f, "le"),
+            Gt => write!(f, "gt"),
+            Ge => write!(f, "ge"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Instr::*;
+    use super::*;
+
+    #[test]
+    fn parse_assembly() {
+        let text = "main: link 2 ; set up\n  ldrr SP MP\n\nloop:\n ldmh -1 2\n bsr loop\n halt\n";
+        let code = parse(text).unwrap();
+        assert_eq!(
+            code,
+            vec![
+                Label("main".to_string()),
+                Link(2),
+                Ldrr(Reg::SP, Reg::MP),
+                Label("loop".to_string()),
+                Ldmh(-1, 2),
+                Bsr("loop".to_string()),
+                Halt,
+            ]
+        );
+        let printed: Vec<String> = code.iter().map(Instr::to_string).collect();
+        assert_eq!(parse(&printed.join("\n")), Ok(code));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            parse("ldc 1\nldc x"),
+            Err((2, "Expected a number, found 'x'".to_string()))
+        );
+        assert_eq!(
+            parse("add 1"),
+            Err((1, "'add' takes 0 argument(s)".to_string()))
+        );
+        assert_eq!(
+            parse("jump l"),
+            Err((1, "Unknown instruction 'jump'".to_string()))
+        );
+        assert_eq!(
+            parse("ldr R9"),
+            Err((1, "Unknown register 'R9'".to_string()))
+        );
+    }
+}
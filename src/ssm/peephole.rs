@@ -0,0 +1,380 @@
+use super::Instr::*;
+use super::{Instr, Label};
+use crate::ast::IntWidth;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// A rewrite of a short instruction sequence. Given the code from some
+/// position onwards, `rewrite` returns how many instructions it replaces and
+/// by what, if the rule applies there.
+pub struct Rule {
+    pub name: &'static str,
+    pub rewrite: fn(&[Instr]) -> Option<Rewrite>,
+}
+
+pub type Rewrite = (usize, Vec<Instr>);
+
+macro_rules! rule {
+    ( $name : expr, [ $( $pat : pat ),* ] $( if $guard : expr )? => [ $( $rep : expr ),* ] ) => {
+        Rule {
+            name: $name,
+            rewrite: |code| match code {
+                [ $( $pat ),* , ..] $( if $guard )? => {
+                    Some(([ $( stringify!($pat) ),* ].len(), vec![ $( $rep ),* ]))
+                }
+                _ => None,
+            },
+        }
+    };
+}
+
+/// The local rewrite rules, tried in order at every position.
+pub static RULES: &[Rule] = &[
+    rule!("add zero", [Ldc(0), Add] => []),
+    rule!("subtract zero", [Ldc(0), Sub] => []),
+    rule!("multiply by one", [Ldc(1), Mul] => []),
+    rule!("divide by one", [Ldc(1), Div] => []),
+    rule!("fold add", [Ldc(a), Ldc(b), Add] => [Ldc(a.wrapping_add(*b))]),
+    rule!("fold subtract", [Ldc(a), Ldc(b), Sub] => [Ldc(a.wrapping_sub(*b))]),
+    rule!("fold multiply", [Ldc(a), Ldc(b), Mul] => [Ldc(a.wrapping_mul(*b))]),
+    rule!("double negation", [Neg, Neg] => []),
+    rule!("double not", [Not, Not] => []),
+    rule!("store then load", [Stl(a), Ldl(b)] if a == b => [Lds(0), Stl(*a)]),
+    rule!("load then store", [Ldl(a), Stl(b)] if a == b => []),
+    rule!("empty adjustment", [Ajs(0)] => []),
+    rule!("merge adjustments", [Ajs(a), Ajs(b)] if a.checked_add(*b).is_some() => [Ajs(a + b)]),
+    rule!("push then pop", [Ldc(_), Ajs(-1)] => []),
+    rule!("branch if false on constant", [Ldc(0), Brf(l)] => [Bra(l.clone())]),
+    rule!("branch if false on constant", [Ldc(_), Brf(_)] => []),
+    rule!("branch if true on constant", [Ldc(0), Brt(_)] => []),
+    rule!("branch if true on constant", [Ldc(_), Brt(l)] => [Bra(l.clone())]),
+    rule!("no operation", [Nop] => []),
+];
+
+/// Instruction counts around a peephole run. Labels are not counted.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Stats {
+    pub before: usize,
+    pub after: usize,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "peephole: {} instructions before, {} after ({} removed)",
+            self.before,
+            self.after,
+            self.before - self.after
+        )
+    }
+}
+
+fn count(code: &[Instr]) -> usize {
+    code.iter().filter(|instr| !instr.is_label()).count()
+}
+
+/// Apply `RULES` and the control flow clean-ups until none of them changes
+/// the code any more. Folded constants wrap around at `width`, as in
+/// `ConstFolder`.
+pub fn run(mut code: Vec<Instr>, width: IntWidth) -> (Vec<Instr>, Stats) {
+    let before = count(&code);
+    loop {
+        let mut changed = local_rules(&mut code, width);
+        changed |= thread_jumps(&mut code);
+        changed |= jumps_to_next(&mut code);
+        changed |= unused_labels(&mut code);
+        changed |= unreachable(&mut code);
+        if !changed {
+            break;
+        }
+    }
+    let after = count(&code);
+    (code, Stats { before, after })
+}
+
+fn local_rules(code: &mut Vec<Instr>, width: IntWidth) -> bool {
+    let mut changed = false;
+    let mut out = Vec::with_capacity(code.len());
+    let mut pos = 0;
+    'outer: while pos < code.len() {
+        for rule in RULES {
+            if let Some((len, replacement)) = (rule.rewrite)(&code[pos..]) {
+                // Constants wrap like the machine would, but an adjustment
+                // the machine cannot encode leaves the code as it was.
+                if replacement
+                    .iter()
+                    .any(|instr| matches!(instr, Ajs(n) if width.wrap(*n).1))
+                {
+                    continue;
+                }
+                out.extend(replacement.into_iter().map(|instr| match instr {
+                    Ldc(n) => Ldc(width.wrap(n).0),
+                    instr => instr,
+                }));
+                pos += len;
+                changed = true;
+                continue 'outer;
+            }
+        }
+        out.push(code[pos].clone());
+        pos += 1;
+    }
+    *code = out;
+    changed
+}
+
+/// Where control ends up when it reaches `label`, following unconditional
+/// branches.
+fn destination<'c>(label: &'c Label, targets: &HashMap<&'c Label, &'c Instr>) -> &'c Label {
+    let mut seen = HashSet::new();
+    let mut label = label;
+    seen.insert(label);
+    while let Some(Bra(next)) = targets.get(label) {
+        if !seen.insert(next) {
+            break;
+        }
+        label = next;
+    }
+    label
+}
+
+/// Retarget branches to labels at unconditional branches.
+fn thread_jumps(code: &mut [Instr]) -> bool {
+    let retargets: Vec<(usize, Label)> = {
+        let mut targets = HashMap::new();
+        for (pos, instr) in code.iter().enumerate() {
+            if let Label(l) = instr {
+                if let Some(next) = code[pos..].iter().find(|instr| !instr.is_label()) {
+                    targets.insert(l, next);
+                }
+            }
+        }
+        code.iter()
+            .enumerate()
+            .filter_map(|(pos, instr)| match instr {
+                Bra(l) | Brf(l) | Brt(l) => {
+                    let dest = destination(l, &targets);
+                    if dest != l {
+                        Some((pos, dest.clone()))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            })
+            .collect()
+    };
+    let changed = !retargets.is_empty();
+    for (pos, dest) in retargets {
+        match &mut code[pos] {
+            Bra(l) | Brf(l) | Brt(l) => *l = dest,
+            _ => unreachable!(),
+        }
+    }
+    changed
+}
+
+/// Remove unconditional branches to the instruction that follows anyway.
+fn jumps_to_next(code: &mut Vec<Instr>) -> bool {
+    let len = code.len();
+    let mut keep = vec![true; len];
+    for pos in 0..len {
+        if let Bra(l) = &code[pos] {
+            keep[pos] = !code[pos + 1..]
+                .iter()
+                .take_while(|instr| instr.is_label())
+                .any(|instr| instr == &Label(l.clone()));
+        }
+    }
+    retain(code, &keep)
+}
+
+fn unused_labels(code: &mut Vec<Instr>) -> bool {
+    let used: HashSet<Label> = code
+        .iter()
+        .filter_map(|instr| match instr {
+            Bra(l) | Brf(l) | Brt(l) | Bsr(l) => Some(l.clone()),
+            _ => None,
+        })
+        .collect();
+    let keep: Vec<bool> = code
+        .iter()
+        .map(|instr| match instr {
+            Label(l) => used.contains(l),
+            _ => true,
+        })
+        .collect();
+    retain(code, &keep)
+}
+
+/// Remove instructions after an unconditional jump that no label leads to.
+fn unreachable(code: &mut Vec<Instr>) -> bool {
+    let mut reachable = true;
+    let keep: Vec<bool> = code
+        .iter()
+        .map(|instr| {
+            let keep = reachable || instr.is_label();
+            match instr {
+                Label(_) => reachable = true,
+                Bra(_) | Ret | Halt if keep => reachable = false,
+                _ => (),
+            }
+            keep
+        })
+        .collect();
+    retain(code, &keep)
+}
+
+fn retain(code: &mut Vec<Instr>, keep: &[bool]) -> bool {
+    if keep.iter().all(|&keep| keep) {
+        return false;
+    }
+    let mut keep = keep.iter();
+    code.retain(|_| *keep.next().unwrap());
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn label(l: &str) -> Instr {
+        Label(l.to_string())
+    }
+
+    #[test]
+    fn adjustments_merge_within_width() {
+        let (code, _) = run(vec![Ajs(2), Ajs(3), Halt], IntWidth::W32);
+        assert_eq!(code, vec![Ajs(5), Halt]);
+        let big = vec![Ajs(i32::MAX as i64), Ajs(1), Halt];
+        assert_eq!(run(big.clone(), IntWidth::W32).0, big);
+        assert_eq!(run(big, IntWidth::W64).0, vec![Ajs(1 << 31), Halt]);
+        let huge = vec![Ajs(i64::MAX), Ajs(1), Halt];
+        assert_eq!(run(huge.clone(), IntWidth::W64).0, huge);
+    }
+
+    #[test]
+    fn local_rules_to_fixpoint() {
+        let (code, stats) = run(
+            vec![Ldl(1), Ldc(0), Ldc(0), Add, Add, Ldc(1), Mul, Halt],
+            IntWidth::W32,
+        );
+        assert_eq!(code, vec![Ldl(1), Halt]);
+        assert_eq!(
+            stats,
+            Stats {
+                before: 8,
+                after: 2
+            }
+        );
+    }
+
+    #[test]
+    fn fold_wraps_like_the_ast_folder() {
+        use crate::ast::{BareDecl, BareExp, LitVal};
+        use crate::opt::fold::ConstFolder;
+        use crate::parser::Parser;
+        use crate::source::SourceMap;
+
+        let mut map = SourceMap::new();
+        let file = map.add("max.spl".to_string(), "var x = 2147483647 + 1;".to_string());
+        for &(width, sum) in &[(IntWidth::W32, i32::MIN as i64), (IntWidth::W64, 1 << 31)] {
+            let (code, _) = run(vec![Ldc(i32::MAX as i64), Ldc(1), Add, Halt], width);
+            assert_eq!(code, vec![Ldc(sum), Halt]);
+            let spl = Parser::for_file(&map, file, width).spl().unwrap();
+            let (spl, _) = ConstFolder::new(width).run(spl);
+            match &spl[0].0 {
+                BareDecl::Global((_, _, exp)) => {
+                    assert_eq!((exp.0).0, BareExp::Lit(LitVal::Int(sum)))
+                }
+                decl => panic!("{:?}", decl),
+            }
+        }
+    }
+
+    #[test]
+    fn store_load() {
+        let (code, _) = run(vec![Stl(1), Ldl(1), Ldl(2), Stl(2), Ret], IntWidth::W32);
+        assert_eq!(code, vec![Lds(0), Stl(1), Ret]);
+        let (code, _) = run(vec![Stl(1), Ldl(2), Ret], IntWidth::W32);
+        assert_eq!(code, vec![Stl(1), Ldl(2), Ret]);
+    }
+
+    #[test]
+    fn jump_to_jump() {
+        let code = vec![
+            Ldl(1),
+            Brf("a".to_string()),
+            Ldc(1),
+            Trap(0),
+            label("a"),
+            Bra("b".to_string()),
+            label("b"),
+            Halt,
+        ];
+        let (code, _) = run(code, IntWidth::W32);
+        assert_eq!(
+            code,
+            vec![
+                Ldl(1),
+                Brf("b".to_string()),
+                Ldc(1),
+                Trap(0),
+                label("b"),
+                Halt,
+            ]
+        );
+    }
+
+    #[test]
+    fn jump_cycle_terminates() {
+        let code = vec![
+            label("a"),
+            Bra("b".to_string()),
+            label("b"),
+            Bra("a".to_string()),
+        ];
+        let (code, _) = run(code, IntWidth::W32);
+        assert_eq!(
+            code,
+            vec![
+                label("a"),
+                Bra("a".to_string()),
+                label("b"),
+                Bra("b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn unreachable_after_branch() {
+        let code = vec![
+            Ldc(1),
+            Brt("end".to_string()),
+            Ldc(2),
+            Trap(0),
+            label("end"),
+            Halt,
+            Ldc(3),
+        ];
+        let (code, stats) = run(code, IntWidth::W32);
+        assert_eq!(code, vec![Halt]);
+        assert_eq!(
+            stats.to_string(),
+            "peephole: 6 instructions before, 1 after (5 removed)"
+        );
+    }
+
+    #[test]
+    fn display() {
+        let code = [
+            label("main"),
+            Ldc(-1),
+            Ldr(super::super::Reg::MP),
+            Bsr("f".to_string()),
+        ];
+        let text: Vec<String> = code.iter().map(ToString::to_string).collect();
+        assert_eq!(text, vec!["main:", "ldc -1", "ldr MP", "bsr f"]);
+    }
+}
@@ -0,0 +1,652 @@
+//! Traversals of the AST. Each trait has a method per node type whose
+//! default implementation walks the node's children by calling the free
+//! function of the same name. A pass overrides the methods for the nodes it
+//! cares about, and calls the free function from there to keep walking.
+//!
+//! `Visitor` looks at the AST, `VisitorMut` changes it in place, and `Fold`
+//! takes it apart and builds a new one. Children are visited in source
+//! order; spans, including those of identifiers, selectors and operators,
+//! pass through `visit_span` and friends last.
+
+use crate::ast::*;
+
+pub trait Visitor {
+    fn visit_spl(&mut self, spl: &SPL) {
+        walk_spl(self, spl)
+    }
+
+    fn visit_decl(&mut self, decl: &Decl) {
+        walk_decl(self, decl)
+    }
+
+    fn visit_var_decl(&mut self, var: &VarDecl) {
+        walk_var_decl(self, var)
+    }
+
+    fn visit_fun_type(&mut self, typ: &FunType) {
+        walk_fun_type(self, typ)
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt)
+    }
+
+    fn visit_exp(&mut self, exp: &Exp) {
+        walk_exp(self, exp)
+    }
+
+    fn visit_type(&mut self, typ: &Type) {
+        walk_type(self, typ)
+    }
+
+    fn visit_id(&mut self, id: &Id) {
+        walk_id(self, id)
+    }
+
+    fn visit_selector(&mut self, selector: &Selector) {
+        walk_selector(self, selector)
+    }
+
+    fn visit_op(&mut self, op: &Op) {
+        walk_op(self, op)
+    }
+
+    fn visit_lit(&mut self, _lit: &LitVal) {}
+
+    fn visit_span(&mut self, _span: &Option<Span>) {}
+}
+
+pub fn walk_spl<V: Visitor + ?Sized>(v: &mut V, spl: &SPL) {
+    for decl in spl {
+        v.visit_decl(decl);
+    }
+}
+
+pub fn walk_decl<V: Visitor + ?Sized>(v: &mut V, (decl, span): &Decl) {
+    match decl {
+        BareDecl::Global(var) => v.visit_var_decl(var),
+        BareDecl::Fun(id, args, typ, body) => {
+            v.visit_id(id);
+            for arg in args {
+                v.visit_id(arg);
+            }
+            if let Some(typ) = typ {
+                v.visit_fun_type(typ);
+            }
+            for stmt in body {
+                v.visit_stmt(stmt);
+            }
+        }
+    }
+    v.visit_span(span);
+}
+
+pub fn walk_var_decl<V: Visitor + ?Sized>(v: &mut V, (typ, id, exp): &VarDecl) {
+    if let Some(typ) = typ {
+        v.visit_type(typ);
+    }
+    v.visit_id(id);
+    v.visit_exp(exp);
+}
+
+pub fn walk_fun_type<V: Visitor + ?Sized>(v: &mut V, ((args, ret), span): &FunType) {
+    for arg in args {
+        v.visit_type(arg);
+    }
+    v.visit_type(ret);
+    v.visit_span(span);
+}
+
+pub fn walk_stmt<V: Visitor + ?Sized>(v: &mut V, (stmt, span): &Stmt) {
+    match stmt {
+        BareStmt::ITE(cond, then, els) => {
+            v.visit_exp(cond);
+            for stmt in then.iter().chain(els) {
+                v.visit_stmt(stmt);
+            }
+        }
+        BareStmt::While(cond, body) => {
+            v.visit_exp(cond);
+            for stmt in body {
+                v.visit_stmt(stmt);
+            }
+        }
+        BareStmt::Assign(id, exp) => {
+            v.visit_id(id);
+            v.visit_exp(exp);
+        }
+        BareStmt::Call(id, args) => {
+            v.visit_id(id);
+            for arg in args {
+                v.visit_exp(arg);
+            }
+        }
+        BareStmt::Ret(exp) => {
+            if let Some(exp) = exp {
+                v.visit_exp(exp);
+            }
+        }
+        BareStmt::Local(var) => v.visit_var_decl(var),
+    }
+    v.visit_span(span);
+}
+
+pub fn walk_exp<V: Visitor + ?Sized>(v: &mut V, ((exp, typ), span): &Exp) {
+    match exp {
+        BareExp::Var(id, fields) => {
+            v.visit_id(id);
+            for field in fields {
+                v.visit_selector(field);
+            }
+        }
+        BareExp::Call(id, args) => {
+            v.visit_id(id);
+            for arg in args {
+                v.visit_exp(arg);
+            }
+        }
+        BareExp::Lit(lit) => v.visit_lit(lit),
+        BareExp::Tuple(elems) => {
+            for elem in elems {
+                v.visit_exp(elem);
+            }
+        }
+        BareExp::BinOp(op, lhs, rhs) => {
+            v.visit_exp(lhs);
+            v.visit_op(op);
+            v.visit_exp(rhs);
+        }
+        BareExp::UnOp(op, arg) => {
+            v.visit_op(op);
+            v.visit_exp(arg);
+        }
+    }
+    if let Some(typ) = typ {
+        v.visit_type(typ);
+    }
+    v.visit_span(span);
+}
+
+pub fn walk_type<V: Visitor + ?Sized>(v: &mut V, (typ, span): &Type) {
+    match typ {
+        BareType::Lit(_) => (),
+        BareType::Typename(id) => v.visit_id(id),
+        BareType::Tuple(elems) => {
+            for elem in elems {
+                v.visit_type(elem);
+            }
+        }
+        BareType::List(elem) => v.visit_type(elem),
+    }
+    v.visit_span(span);
+}
+
+pub fn walk_id<V: Visitor + ?Sized>(v: &mut V, (_, span): &Id) {
+    v.visit_span(span);
+}
+
+pub fn walk_selector<V: Visitor + ?Sized>(v: &mut V, (_, span): &Selector) {
+    v.visit_span(span);
+}
+
+pub fn walk_op<V: Visitor + ?Sized>(v: &mut V, (_, span): &Op) {
+    v.visit_span(span);
+}
+
+pub trait VisitorMut {
+    fn visit_spl_mut(&mut self, spl: &mut SPL) {
+        walk_spl_mut(self, spl)
+    }
+
+    fn visit_decl_mut(&mut self, decl: &mut Decl) {
+        walk_decl_mut(self, decl)
+    }
+
+    fn visit_var_decl_mut(&mut self, var: &mut VarDecl) {
+        walk_var_decl_mut(self, var)
+    }
+
+    fn visit_fun_type_mut(&mut self, typ: &mut FunType) {
+        walk_fun_type_mut(self, typ)
+    }
+
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt)
+    }
+
+    fn visit_exp_mut(&mut self, exp: &mut Exp) {
+        walk_exp_mut(self, exp)
+    }
+
+    fn visit_type_mut(&mut self, typ: &mut Type) {
+        walk_type_mut(self, typ)
+    }
+
+    fn visit_id_mut(&mut self, id: &mut Id) {
+        walk_id_mut(self, id)
+    }
+
+    fn visit_selector_mut(&mut self, selector: &mut Selector) {
+        walk_selector_mut(self, selector)
+    }
+
+    fn visit_op_mut(&mut self, op: &mut Op) {
+        walk_op_mut(self, op)
+    }
+
+    fn visit_lit_mut(&mut self, _lit: &mut LitVal) {}
+
+    fn visit_span_mut(&mut self, _span: &mut Option<Span>) {}
+}
+
+pub fn walk_spl_mut<V: VisitorMut + ?Sized>(v: &mut V, spl: &mut SPL) {
+    for decl in spl {
+        v.visit_decl_mut(decl);
+    }
+}
+
+pub fn walk_decl_mut<V: VisitorMut + ?Sized>(v: &mut V, (decl, span): &mut Decl) {
+    match decl {
+        BareDecl::Global(var) => v.visit_var_decl_mut(var),
+        BareDecl::Fun(id, args, typ, body) => {
+            v.visit_id_mut(id);
+            for arg in args {
+                v.visit_id_mut(arg);
+            }
+            if let Some(typ) = typ {
+                v.visit_fun_type_mut(typ);
+            }
+            for stmt in body {
+                v.visit_stmt_mut(stmt);
+            }
+        }
+    }
+    v.visit_span_mut(span);
+}
+
+pub fn walk_var_decl_mut<V: VisitorMut + ?Sized>(v: &mut V, (typ, id, exp): &mut VarDecl) {
+    if let Some(typ) = typ {
+        v.visit_type_mut(typ);
+    }
+    v.visit_id_mut(id);
+    v.visit_exp_mut(exp);
+}
+
+pub fn walk_fun_type_mut<V: VisitorMut + ?Sized>(v: &mut V, ((args, ret), span): &mut FunType) {
+    for arg in args {
+        v.visit_type_mut(arg);
+    }
+    v.visit_type_mut(ret);
+    v.visit_span_mut(span);
+}
+
+pub fn walk_stmt_mut<V: VisitorMut + ?Sized>(v: &mut V, (stmt, span): &mut Stmt) {
+    match stmt {
+        BareStmt::ITE(cond, then, els) => {
+            v.visit_exp_mut(cond);
+            for stmt in then.iter_mut().chain(els) {
+                v.visit_stmt_mut(stmt);
+            }
+        }
+        BareStmt::While(cond, body) => {
+            v.visit_exp_mut(cond);
+            for stmt in body {
+                v.visit_stmt_mut(stmt);
+            }
+        }
+        BareStmt::Assign(id, exp) => {
+            v.visit_id_mut(id);
+            v.visit_exp_mut(exp);
+        }
+        BareStmt::Call(id, args) => {
+            v.visit_id_mut(id);
+            for arg in args {
+                v.visit_exp_mut(arg);
+            }
+        }
+        BareStmt::Ret(exp) => {
+            if let Some(exp) = exp {
+                v.visit_exp_mut(exp);
+            }
+        }
+        BareStmt::Local(var) => v.visit_var_decl_mut(var),
+    }
+    v.visit_span_mut(span);
+}
+
+pub fn walk_exp_mut<V: VisitorMut + ?Sized>(v: &mut V, ((exp, typ), span): &mut Exp) {
+    match exp {
+        BareExp::Var(id, fields) => {
+            v.visit_id_mut(id);
+            for field in fields {
+                v.visit_selector_mut(field);
+            }
+        }
+        BareExp::Call(id, args) => {
+            v.visit_id_mut(id);
+            for arg in args {
+                v.visit_exp_mut(arg);
+            }
+        }
+        BareExp::Lit(lit) => v.visit_lit_mut(lit),
+        BareExp::Tuple(elems) => {
+            for elem in elems {
+                v.visit_exp_mut(elem);
+            }
+        }
+        BareExp::BinOp(op, lhs, rhs) => {
+            v.visit_exp_mut(lhs);
+            v.visit_op_mut(op);
+            v.visit_exp_mut(rhs);
+        }
+        BareExp::UnOp(op, arg) => {
+            v.visit_op_mut(op);
+            v.visit_exp_mut(arg);
+        }
+    }
+    if let Some(typ) = typ {
+        v.visit_type_mut(typ);
+    }
+    v.visit_span_mut(span);
+}
+
+pub fn walk_type_mut<V: VisitorMut + ?Sized>(v: &mut V, (typ, span): &mut Type) {
+    match typ {
+        BareType::Lit(_) => (),
+        BareType::Typename(id) => v.visit_id_mut(id),
+        BareType::Tuple(elems) => {
+            for elem in elems {
+                v.visit_type_mut(elem);
+            }
+        }
+        BareType::List(elem) => v.visit_type_mut(elem),
+    }
+    v.visit_span_mut(span);
+}
+
+pub fn walk_id_mut<V: VisitorMut + ?Sized>(v: &mut V, (_, span): &mut Id) {
+    v.visit_span_mut(span);
+}
+
+pub fn walk_selector_mut<V: VisitorMut + ?Sized>(v: &mut V, (_, span): &mut Selector) {
+    v.visit_span_mut(span);
+}
+
+pub fn walk_op_mut<V: VisitorMut + ?Sized>(v: &mut V, (_, span): &mut Op) {
+    v.visit_span_mut(span);
+}
+
+pub trait Fold {
+    fn fold_spl(&mut self, spl: SPL) -> SPL {
+        fold_spl(self, spl)
+    }
+
+    fn fold_decl(&mut self, decl: Decl) -> Decl {
+        fold_decl(self, decl)
+    }
+
+    fn fold_var_decl(&mut self, var: VarDecl) -> VarDecl {
+        fold_var_decl(self, var)
+    }
+
+    fn fold_fun_type(&mut self, typ: FunType) -> FunType {
+        fold_fun_type(self, typ)
+    }
+
+    fn fold_stmt(&mut self, stmt: Stmt) -> Stmt {
+        fold_stmt(self, stmt)
+    }
+
+    fn fold_exp(&mut self, exp: Exp) -> Exp {
+        fold_exp(self, exp)
+    }
+
+    fn fold_type(&mut self, typ: Type) -> Type {
+        fold_type(self, typ)
+    }
+
+    fn fold_id(&mut self, id: Id) -> Id {
+        fold_id(self, id)
+    }
+
+    fn fold_selector(&mut self, selector: Selector) -> Selector {
+        fold_selector(self, selector)
+    }
+
+    fn fold_op(&mut self, op: Op) -> Op {
+        fold_op(self, op)
+    }
+
+    fn fold_lit(&mut self, lit: LitVal) -> LitVal {
+        lit
+    }
+
+    fn fold_span(&mut self, span: Option<Span>) -> Option<Span> {
+        span
+    }
+}
+
+pub fn fold_spl<F: Fold + ?Sized>(f: &mut F, spl: SPL) -> SPL {
+    spl.into_iter().map(|decl| f.fold_decl(decl)).collect()
+}
+
+fn fold_stmts<F: Fold + ?Sized>(f: &mut F, stmts: Vec<Stmt>) -> Vec<Stmt> {
+    stmts.into_iter().map(|stmt| f.fold_stmt(stmt)).collect()
+}
+
+fn fold_exps<F: Fold + ?Sized>(f: &mut F, exps: Vec<Exp>) -> Vec<Exp> {
+    exps.into_iter().map(|exp| f.fold_exp(exp)).collect()
+}
+
+pub fn fold_decl<F: Fold + ?Sized>(f: &mut F, (decl, span): Decl) -> Decl {
+    let decl = match decl {
+        BareDecl::Global(var) => BareDecl::Global(f.fold_var_decl(var)),
+        BareDecl::Fun(id, args, typ, body) => {
+            let id = f.fold_id(id);
+            let args = args.into_iter().map(|arg| f.fold_id(arg)).collect();
+            let typ = typ.map(|typ| f.fold_fun_type(typ));
+            BareDecl::Fun(id, args, typ, fold_stmts(f, body))
+        }
+    };
+    (decl, f.fold_span(span))
+}
+
+pub fn fold_var_decl<F: Fold + ?Sized>(f: &mut F, (typ, id, exp): VarDecl) -> VarDecl {
+    let typ = typ.map(|typ| f.fold_type(typ));
+    let id = f.fold_id(id);
+    (typ, id, f.fold_exp(exp))
+}
+
+pub fn fold_fun_type<F: Fold + ?Sized>(f: &mut F, ((args, ret), span): FunType) -> FunType {
+    let args = args.into_iter().map(|arg| f.fold_type(arg)).collect();
+    let ret = f.fold_type(ret);
+    ((args, ret), f.fold_span(span))
+}
+
+pub fn fold_stmt<F: Fold + ?Sized>(f: &mut F, (stmt, span): Stmt) -> Stmt {
+    let stmt = match stmt {
+        BareStmt::ITE(cond, then, els) => {
+            let cond = f.fold_exp(cond);
+            let then = fold_stmts(f, then);
+            BareStmt::ITE(cond, then, fold_stmts(f, els))
+        }
+        BareStmt::While(cond, body) => {
+            let cond = f.fold_exp(cond);
+            BareStmt::While(cond, fold_stmts(f, body))
+        }
+        BareStmt::Assign(id, exp) => {
+            let id = f.fold_id(id);
+            BareStmt::Assign(id, f.fold_exp(exp))
+        }
+        BareStmt::Call(id, args) => {
+            let id = f.fold_id(id);
+            BareStmt::Call(id, fold_exps(f, args))
+        }
+        BareStmt::Ret(exp) => BareStmt::Ret(exp.map(|exp| f.fold_exp(exp))),
+        BareStmt::Local(var) => BareStmt::Local(f.fold_var_decl(var)),
+    };
+    (stmt, f.fold_span(span))
+}
+
+pub fn fold_exp<F: Fold + ?Sized>(f: &mut F, ((exp, typ), span): Exp) -> Exp {
+    let exp = match exp {
+        BareExp::Var(id, fields) => {
+            let id = f.fold_id(id);
+            let fields = fields
+                .into_iter()
+                .map(|field| f.fold_selector(field))
+                .collect();
+            BareExp::Var(id, fields)
+        }
+        BareExp::Call(id, args) => {
+            let id = f.fold_id(id);
+            BareExp::Call(id, fold_exps(f, args))
+        }
+        BareExp::Lit(lit) => BareExp::Lit(f.fold_lit(lit)),
+        BareExp::Tuple(elems) => BareExp::Tuple(fold_exps(f, elems)),
+        BareExp::BinOp(op, lhs, rhs) => {
+            let lhs = f.fold_exp(*lhs);
+            let op = f.fold_op(op);
+            let rhs = f.fold_exp(*rhs);
+            BareExp::BinOp(op, Box::new(lhs), Box::new(rhs))
+        }
+        BareExp::UnOp(op, arg) => {
+            let op = f.fold_op(op);
+            BareExp::UnOp(op, Box::new(f.fold_exp(*arg)))
+        }
+    };
+    let typ = typ.map(|typ| f.fold_type(typ));
+    ((exp, typ), f.fold_span(span))
+}
+
+pub fn fold_type<F: Fold + ?Sized>(f: &mut F, (typ, span): Type) -> Type {
+    let typ = match typ {
+        BareType::Lit(btype) => BareType::Lit(btype),
+        BareType::Typename(id) => BareType::Typename(f.fold_id(id)),
+        BareType::Tuple(elems) => {
+            BareType::Tuple(elems.into_iter().map(|elem| f.fold_type(elem)).collect())
+        }
+        BareType::List(elem) => BareType::List(Box::new(f.fold_type(*elem))),
+    };
+    (typ, f.fold_span(span))
+}
+
+pub fn fold_id<F: Fold + ?Sized>(f: &mut F, (id, span): Id) -> Id {
+    (id, f.fold_span(span))
+}
+
+pub fn fold_selector<F: Fold + ?Sized>(f: &mut F, (selector, span): Selector) -> Selector {
+    (selector, f.fold_span(span))
+}
+
+pub fn fold_op<F: Fold + ?Sized>(f: &mut F, (op, span): Op) -> Op {
+    (op, f.fold_span(span))
+}
+
+/// Removes all spans, to compare ASTs from different sources.
+pub struct StripSpans;
+
+impl Fold for StripSpans {
+    fn fold_span(&mut self, _span: Option<Span>) -> Option<Span> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::tests::{parse, SOURCE};
+
+    /// Collects identifiers and counts spans, overriding only those.
+    #[derive(Default)]
+    struct Collect {
+        ids: Vec<BareId>,
+        spans: usize,
+        lits: usize,
+    }
+
+    impl Visitor for Collect {
+        fn visit_id(&mut self, id: &Id) {
+            self.ids.push(id.0);
+            walk_id(self, id);
+        }
+
+        fn visit_lit(&mut self, _lit: &LitVal) {
+            self.lits += 1;
+        }
+
+        fn visit_span(&mut self, span: &Option<Span>) {
+            if span.is_some() {
+                self.spans += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn visit_in_source_order() {
+        let spl = parse(SOURCE);
+        let mut collect = Collect::default();
+        collect.visit_spl(&spl);
+        // g f x y a z x y z g g z z f g y z z print z z z g y h f g
+        let names: Vec<_> = collect.ids.iter().map(|id| id.name()).collect();
+        let (g, f, x, y, a, z, print, h) = ("g", "f", "x", "y", "a", "z", "print", "h");
+        assert_eq!(
+            names,
+            vec![
+                g, f, x, y, a, z, x, y, z, g, g, z, z, f, g, y, z, z, print, z, z, z, g, y, h, f, g
+            ]
+        );
+        assert_eq!(collect.lits, 8);
+        // Everything the parser makes has a span.
+        let mut stripped = StripSpans.fold_spl(spl);
+        let mut count = Collect::default();
+        count.visit_spl(&stripped);
+        assert_eq!(count.spans, 0);
+        assert!(collect.spans > collect.ids.len());
+        // Stripping is a no-op on the AST otherwise.
+        let again = StripSpans.fold_spl(parse(SOURCE));
+        assert_eq!(stripped, again);
+        struct Rename;
+        impl VisitorMut for Rename {
+            fn visit_id_mut(&mut self, id: &mut Id) {
+                id.0 = BareId::intern(&format!("{}_", id.0));
+            }
+        }
+        Rename.visit_spl_mut(&mut stripped);
+        let mut renamed = Collect::default();
+        renamed.visit_spl(&stripped);
+        let expected: Vec<String> = names.iter().map(|name| format!("{}_", name)).collect();
+        assert_eq!(
+            renamed.ids.iter().map(|id| id.name()).collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    #[test]
+    fn fold_rewrites_bottom_up() {
+        /// Replaces `-x` by `0 - x`, counting the operators it sees.
+        struct Desugar(usize);
+        impl Fold for Desugar {
+            fn fold_op(&mut self, op: Op) -> Op {
+                self.0 += 1;
+                op
+            }
+
+            fn fold_exp(&mut self, exp: Exp) -> Exp {
+                match fold_exp(self, exp) {
+                    ((BareExp::UnOp((BareOp::Neg, span), arg), typ), exp_span) => {
+                        let zero = ((BareExp::Lit(LitVal::Int(0)), None), span);
+                        let minus = (BareOp::Minus, span);
+                        ((BareExp::BinOp(minus, Box::new(zero), arg), typ), exp_span)
+                    }
+                    exp => exp,
+                }
+            }
+        }
+        let mut desugar = Desugar(0);
+        let spl = StripSpans.fold_spl(desugar.fold_spl(parse(SOURCE)));
+        assert_eq!(desugar.0, 9);
+        let expected = parse(&SOURCE.replace("-y.hd.fst", "(0 - y.hd.fst)"));
+        assert_eq!(spl, StripSpans.fold_spl(expected));
+    }
+}